@@ -0,0 +1,366 @@
+// Headless backplot. Replays a toolpath in the XZ plane and draws it as SVG,
+// Z to the right and the radius up, with the stock, the chuck face and the
+// finished surfaces of each operation drawn over it.
+
+use std::f32::consts::PI;
+
+use crate::generator::{JobParams, MachineParams};
+use crate::geometry::{Point, Position};
+use crate::job::Stock;
+use crate::toolpath::{expand_cycles, Motion, Toolpath};
+
+const WIDTH: f32 = 800.0; // Pixels, the height follows the aspect of the plot
+const ARC_STEP: f32 = PI / 36.0; // Arcs are drawn as chords of at most 5 degrees
+
+#[derive(Clone, Copy, PartialEq)]
+enum Stroke {
+    Rapid,
+    Feed,
+    Thread,
+    Surface,
+}
+
+impl Stroke {
+    fn class(self) -> &'static str {
+        match self {
+            Stroke::Rapid => "rapid",
+            Stroke::Feed => "feed",
+            Stroke::Thread => "thread",
+            Stroke::Surface => "surface",
+        }
+    }
+}
+
+// Polylines in (z, radius), built by following the moves
+struct Replay {
+    position: Position,
+    lines: Vec<(Stroke, Vec<(f32, f32)>)>,
+}
+
+impl Replay {
+    fn new(radius_mode: bool) -> Replay {
+        Replay {
+            position: Position::new(radius_mode),
+            lines: vec![],
+        }
+    }
+
+    fn segment(&mut self, stroke: Stroke, from: (f32, f32), to: (f32, f32)) {
+        // Carry on the last polyline when this segment continues it
+        if let Some((last, points)) = self.lines.last_mut() {
+            if *last == stroke && points.last() == Some(&from) {
+                points.push(to);
+                return;
+            }
+        }
+        self.lines.push((stroke, vec![from, to]));
+    }
+
+    fn line(&mut self, stroke: Stroke, from: Option<Point>, to: Option<Point>) {
+        // Nothing to draw while the position is unknown
+        if let (Some(from), Some(to)) = (from, to) {
+            self.segment(stroke, (from.z, from.r), (to.z, to.r));
+        }
+    }
+
+    fn arc(&mut self, stroke: Stroke, cw: bool, start: Point, end: Point, i: f32, k: f32) {
+        // I/K are radial, relative to the start
+        let centre = (start.z + k, start.r + i);
+        let radius = (k * k + i * i).sqrt();
+        let from = (start.r - centre.1).atan2(start.z - centre.0);
+        let to = (end.r - centre.1).atan2(end.z - centre.0);
+        let mut sweep = to - from;
+        if cw && sweep > 0.0 {
+            sweep -= 2.0 * PI;
+        } else if !cw && sweep < 0.0 {
+            sweep += 2.0 * PI;
+        }
+        let steps = (sweep.abs() / ARC_STEP).ceil().max(1.0) as usize;
+        let mut last = (start.z, start.r);
+        for n in 1..steps {
+            let angle = from + sweep * n as f32 / steps as f32;
+            let point = (
+                centre.0 + radius * angle.cos(),
+                centre.1 + radius * angle.sin(),
+            );
+            self.segment(stroke, last, point);
+            last = point;
+        }
+        self.segment(stroke, last, (end.z, end.r));
+    }
+
+    fn run(&mut self, moves: &[Motion], feed_stroke: Stroke) {
+        for motion in &expand_cycles(moves) {
+            let from = self.position.point();
+            let to = self.position.after(motion);
+            match *motion {
+                Motion::Rapid { .. } => self.line(Stroke::Rapid, from, to.point()),
+                Motion::Feed { .. } => self.line(feed_stroke, from, to.point()),
+                Motion::Arc { cw, i, k, .. } => {
+                    if let (Some(start), Some(end)) = (from, to.point()) {
+                        self.arc(feed_stroke, cw, start, end, i, k);
+                    }
+                }
+                Motion::Thread { .. } => self.line(Stroke::Thread, from, to.point()),
+                Motion::RigidTap { z, .. } => {
+                    // Down and back out along the same line
+                    let bottom = Position { z: Some(z), ..to }.point();
+                    self.line(Stroke::Thread, from, bottom);
+                    self.line(Stroke::Thread, bottom, from);
+                }
+                _ => (),
+            }
+            self.position = to;
+        }
+    }
+}
+
+pub struct Backplot {
+    pub radius_mode: bool,
+    pub stock: Option<(f32, f32)>, // Diameter, and Z of the end of the stock
+    pub chuck_face: Option<f32>,   // Z
+    pub jaws: Option<(f32, f32)>,  // Radius over the jaws and Z of their faces
+    pub surfaces: Vec<Toolpath>,   // Finished surfaces, drawn over the toolpath
+}
+
+impl Backplot {
+    pub fn new(jobs: &[JobParams], machine: &MachineParams, stock: Option<&Stock>) -> Backplot {
+        // The stock sticks out of the jaws when the machine has a chuck
+        let face = Stock::face(jobs);
+        let jaws = machine
+            .chuck
+            .map(|chuck| (chuck.x / machine.x_scale(), chuck.z));
+        Backplot {
+            radius_mode: machine.radius_mode,
+            stock: stock.map(|stock| (stock.diameter, face)),
+            chuck_face: match jaws {
+                Some((_, z)) => Some(z),
+                None => stock.map(|stock| face - stock.length),
+            },
+            jaws,
+            surfaces: jobs
+                .iter()
+                .map(|job| job.finished_surface(machine))
+                .collect(),
+        }
+    }
+
+    pub fn svg(&self, path: &Toolpath) -> String {
+        let mut replay = Replay::new(self.radius_mode);
+        replay.run(&path.moves, Stroke::Feed);
+        for surface in &self.surfaces {
+            let mut outline = Replay::new(self.radius_mode);
+            outline.run(&surface.moves, Stroke::Surface);
+            replay.lines.extend(
+                outline
+                    .lines
+                    .into_iter()
+                    .filter(|(stroke, _)| *stroke == Stroke::Surface),
+            );
+        }
+
+        // Bounds of everything drawn, always including the centreline
+        let mut points: Vec<(f32, f32)> = replay
+            .lines
+            .iter()
+            .flat_map(|(_, points)| points.iter().cloned())
+            .collect();
+        if let Some((diameter, face)) = self.stock {
+            points.push((face, diameter / 2.0));
+        }
+        if let Some(chuck) = self.chuck_face {
+            points.push((chuck, 0.0));
+        }
+        if let Some((r, z)) = self.jaws {
+            points.push((z, r));
+        }
+        points.push((points.first().map_or(0.0, |p| p.0), 0.0));
+        let min_z = points.iter().map(|p| p.0).fold(f32::INFINITY, f32::min);
+        let max_z = points.iter().map(|p| p.0).fold(f32::NEG_INFINITY, f32::max);
+        let min_r = points.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+        let max_r = points.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max);
+        let margin = 0.05 * (max_z - min_z).max(max_r - min_r).max(1.0);
+        let (left, top) = (min_z - margin, -max_r - margin);
+        let width = max_z - min_z + 2.0 * margin;
+        let height = max_r - min_r + 2.0 * margin;
+
+        let mut svg = vec![
+            format!(
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{:.3} {:.3} {:.3} {:.3}\" width=\"{:.0}\" height=\"{:.0}\">",
+                left,
+                top,
+                width,
+                height,
+                WIDTH,
+                WIDTH * height / width
+            ),
+            "<style>".to_string(),
+            "line, polyline { fill: none; vector-effect: non-scaling-stroke; }".to_string(),
+            ".rapid { stroke: #d62728; stroke-dasharray: 4 3; }".to_string(),
+            ".feed { stroke: #1f77b4; }".to_string(),
+            ".thread { stroke: #2ca02c; }".to_string(),
+            ".surface { stroke: #000000; stroke-width: 2; }".to_string(),
+            ".stock { fill: #eeeeee; stroke: #999999; vector-effect: non-scaling-stroke; }"
+                .to_string(),
+            ".chuck { stroke: #555555; stroke-width: 4; }".to_string(),
+            ".jaws { fill: #bbbbbb; stroke: #555555; vector-effect: non-scaling-stroke; }"
+                .to_string(),
+            ".axis { stroke: #888888; stroke-dasharray: 12 3 3 3; }".to_string(),
+            "</style>".to_string(),
+        ];
+        if let Some((diameter, face)) = self.stock {
+            let from = self.chuck_face.unwrap_or(min_z);
+            svg.push(format!(
+                "<rect class=\"stock\" x=\"{:.3}\" y=\"{:.3}\" width=\"{:.3}\" height=\"{:.3}\"/>",
+                from,
+                -diameter / 2.0,
+                face - from,
+                diameter / 2.0
+            ));
+        }
+        if let Some((r, z)) = self.jaws {
+            // Gripping the stock, back to the edge of the plot
+            let inside = self.stock.map_or(0.0, |(diameter, _)| diameter / 2.0);
+            svg.push(format!(
+                "<rect class=\"jaws\" x=\"{:.3}\" y=\"{:.3}\" width=\"{:.3}\" height=\"{:.3}\"/>",
+                left,
+                -r,
+                z - left,
+                r - inside
+            ));
+        }
+        svg.push(format!(
+            "<line class=\"axis\" x1=\"{:.3}\" y1=\"0\" x2=\"{:.3}\" y2=\"0\"/>",
+            left,
+            left + width
+        ));
+        if let Some(chuck) = self.chuck_face {
+            svg.push(format!(
+                "<line class=\"chuck\" x1=\"{:.3}\" y1=\"{:.3}\" x2=\"{:.3}\" y2=\"0\"/>",
+                chuck, top, chuck
+            ));
+        }
+        for (stroke, points) in &replay.lines {
+            let points: Vec<String> = points
+                .iter()
+                .map(|(z, r)| format!("{:.3},{:.3}", z, -r))
+                .collect();
+            svg.push(format!(
+                "<polyline class=\"{}\" points=\"{}\"/>",
+                stroke.class(),
+                points.join(" ")
+            ));
+        }
+        svg.push("</svg>".to_string());
+        svg.join("\n") + "\n"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::job::Program;
+
+    fn turn() -> Toolpath {
+        let mut path = Toolpath::new();
+        path.push(Motion::Setup {
+            units_inch: false,
+            radius_mode: true,
+            feed_per_rev: false,
+        });
+        path.rapid_x(10.0);
+        path.rapid_z(1.0);
+        path.feed_z(-10.0, 100.0);
+        path.feed_x(12.0, 100.0);
+        path.rapid_z(1.0);
+        path
+    }
+
+    #[test]
+    fn arcs_are_drawn_as_chords() {
+        // Quarter circle of radius 2 from X10 Z0 to X12 Z-2, centre at X10 Z-2
+        let mut replay = Replay::new(true);
+        replay.run(
+            &[
+                Motion::Rapid {
+                    x: Some(10.0),
+                    z: Some(0.0),
+                },
+                Motion::Arc {
+                    cw: false,
+                    x: 12.0,
+                    z: -2.0,
+                    i: 0.0,
+                    k: -2.0,
+                    feed: 100.0,
+                },
+            ],
+            Stroke::Feed,
+        );
+        // Nothing is drawn until the first move gives both axes
+        assert_eq!(replay.lines.len(), 1);
+        let (stroke, points) = &replay.lines[0];
+        assert!(*stroke == Stroke::Feed);
+        assert_eq!(points.len(), 19);
+        for &(z, r) in points {
+            let radius = ((z + 2.0).powi(2) + (r - 10.0).powi(2)).sqrt();
+            assert!((radius - 2.0).abs() < 1e-4);
+        }
+        assert_eq!(points[points.len() - 1], (-2.0, 12.0));
+    }
+
+    #[test]
+    fn svg_of_a_turning_pass() {
+        let backplot = Backplot {
+            radius_mode: true,
+            stock: Some((24.0, 0.0)),
+            chuck_face: Some(-20.0),
+            jaws: None,
+            surfaces: vec![],
+        };
+        let expected = r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="-21.050 -13.050 23.100 14.100" width="800" height="488">
+<style>
+line, polyline { fill: none; vector-effect: non-scaling-stroke; }
+.rapid { stroke: #d62728; stroke-dasharray: 4 3; }
+.feed { stroke: #1f77b4; }
+.thread { stroke: #2ca02c; }
+.surface { stroke: #000000; stroke-width: 2; }
+.stock { fill: #eeeeee; stroke: #999999; vector-effect: non-scaling-stroke; }
+.chuck { stroke: #555555; stroke-width: 4; }
+.jaws { fill: #bbbbbb; stroke: #555555; vector-effect: non-scaling-stroke; }
+.axis { stroke: #888888; stroke-dasharray: 12 3 3 3; }
+</style>
+<rect class="stock" x="-20.000" y="-12.000" width="20.000" height="12.000"/>
+<line class="axis" x1="-21.050" y1="0" x2="2.050" y2="0"/>
+<line class="chuck" x1="-20.000" y1="-13.050" x2="-20.000" y2="0"/>
+<polyline class="feed" points="1.000,-10.000 -10.000,-10.000 -10.000,-12.000"/>
+<polyline class="rapid" points="-10.000,-12.000 1.000,-12.000"/>
+</svg>
+"##;
+        assert_eq!(backplot.svg(&turn()), expected);
+    }
+
+    #[test]
+    fn chuck_jaws_from_the_machine() {
+        let program = Program::from_toml(include_str!("../examples/shaft.toml")).unwrap();
+        let stock = program.stock;
+        let backplot = Backplot::new(&program.operations, &program.machine, Some(&stock));
+        // Jaws over X80 in diameter mode, faces at Z-39
+        assert_eq!(backplot.jaws, Some((40.0, -39.0)));
+        assert_eq!(backplot.chuck_face, Some(-39.0));
+        let svg = backplot.svg(&turn());
+        assert!(svg.contains("<rect class=\"stock\" x=\"-39.000\" y=\"-8.000\" width=\"40.000\""));
+        let jaws = svg
+            .lines()
+            .find(|line| line.starts_with("<rect class=\"jaws\""))
+            .unwrap();
+        assert!(jaws.contains("y=\"-40.000\"") && jaws.ends_with("height=\"32.000\"/>"));
+
+        // Without jaws the chuck face is where the stock ends
+        let mut machine = program.machine;
+        machine.chuck = None;
+        let backplot = Backplot::new(&program.operations, &machine, Some(&stock));
+        assert_eq!(backplot.chuck_face, Some(-39.0));
+        assert!(!backplot.svg(&turn()).contains("class=\"jaws\""));
+    }
+}
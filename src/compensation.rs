@@ -0,0 +1,318 @@
+// Tool nose radius compensation computed by the generator, for controllers
+// without G41/G42. Works in (z, radius) coordinates: the cut is offset by the
+// nose radius towards the tool, corners are rolled around or intersected, and
+// the nose centre path is shifted to the imaginary tip the tool was touched
+// off with.
+
+use crate::geometry::{motions, segments, Element, Point, Segment, EPSILON};
+use crate::toolpath::{CompSide, Motion};
+
+pub fn normal(tangent: Point, side: CompSide) -> Point {
+    match side {
+        CompSide::Right => Point {
+            z: tangent.r,
+            r: -tangent.z,
+        },
+        CompSide::Left => Point {
+            z: -tangent.r,
+            r: tangent.z,
+        },
+    }
+}
+
+// Side of the path the tool is on, for a cut in direction (dz, dx) with the
+// tool sitting towards (tz, tx) from the cut line
+pub fn side_for(direction: (f32, f32), tool: (f32, f32)) -> CompSide {
+    let direction = Point {
+        z: direction.0,
+        r: direction.1,
+    };
+    let right = normal(direction, CompSide::Right);
+    if right.z * tool.0 + right.r * tool.1 > 0.0 {
+        CompSide::Right
+    } else {
+        CompSide::Left
+    }
+}
+
+// Direction from the nose centre to the imaginary tip for tool orientations
+// 1-9, with X up and Z towards the tailstock. 9 is the nose centre itself.
+pub fn tip_vector(orientation: i32) -> Point {
+    let (z, r) = match orientation {
+        1 => (1.0, 1.0),
+        2 => (-1.0, 1.0),
+        3 => (-1.0, -1.0),
+        4 => (1.0, -1.0),
+        5 => (1.0, 0.0),
+        6 => (0.0, 1.0),
+        7 => (-1.0, 0.0),
+        8 => (0.0, -1.0),
+        _ => (0.0, 0.0),
+    };
+    Point { z, r }
+}
+
+fn line_circle(point: Point, dir: Point, centre: Point, radius: f32) -> Vec<Point> {
+    // |point + t * dir - centre| = radius, dir is a unit vector
+    let offset = point.minus(centre);
+    let b = offset.z * dir.z + offset.r * dir.r;
+    let c = offset.length().powi(2) - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return vec![];
+    }
+    let root = discriminant.sqrt();
+    vec![point.add(dir, -b - root), point.add(dir, -b + root)]
+}
+
+fn circle_circle(a: Point, a_radius: f32, b: Point, b_radius: f32) -> Vec<Point> {
+    let between = b.minus(a);
+    let distance = between.length();
+    if distance < EPSILON
+        || distance > a_radius + b_radius
+        || distance < (a_radius - b_radius).abs()
+    {
+        return vec![];
+    }
+    let along =
+        (a_radius * a_radius - b_radius * b_radius + distance * distance) / (2.0 * distance);
+    let height = (a_radius * a_radius - along * along).max(0.0).sqrt();
+    let unit = between.unit();
+    let middle = a.add(unit, along);
+    let across = Point {
+        z: -unit.r,
+        r: unit.z,
+    };
+    vec![middle.add(across, height), middle.add(across, -height)]
+}
+
+// Where two offset elements cross, taking the crossing nearest the corner
+fn intersect(a: &Segment, b: &Segment, corner: Point) -> Option<Point> {
+    let points = match (&a.element, &b.element) {
+        (Element::Line, Element::Line) => {
+            let a_dir = a.end.minus(a.start).unit();
+            let b_dir = b.end.minus(b.start).unit();
+            let denominator = a_dir.cross(b_dir);
+            if denominator.abs() < EPSILON {
+                return None;
+            }
+            let t = b.start.minus(a.start).cross(b_dir) / denominator;
+            vec![a.start.add(a_dir, t)]
+        }
+        (Element::Line, Element::Arc { centre, .. }) => {
+            let radius = b.start.minus(*centre).length();
+            line_circle(a.start, a.end.minus(a.start).unit(), *centre, radius)
+        }
+        (Element::Arc { centre, .. }, Element::Line) => {
+            let radius = a.start.minus(*centre).length();
+            line_circle(b.start, b.end.minus(b.start).unit(), *centre, radius)
+        }
+        (
+            Element::Arc {
+                centre: a_centre, ..
+            },
+            Element::Arc {
+                centre: b_centre, ..
+            },
+        ) => {
+            let a_radius = a.start.minus(*a_centre).length();
+            let b_radius = b.start.minus(*b_centre).length();
+            circle_circle(*a_centre, a_radius, *b_centre, b_radius)
+        }
+    };
+    points.into_iter().min_by(|p, q| {
+        let p = p.minus(corner).length();
+        let q = q.minus(corner).length();
+        p.total_cmp(&q)
+    })
+}
+
+// Offset the feed moves of a finishing cut that starts at (x, z). Returns the
+// compensated start point and moves in program units.
+pub fn offset_cut(
+    start: (f32, f32),
+    cut: &[Motion],
+    side: CompSide,
+    radius: f32,
+    orientation: i32,
+    x_scale: f32,
+) -> ((f32, f32), Vec<Motion>) {
+    let start = Point {
+        z: start.1,
+        r: start.0 / x_scale,
+    };
+    // Moves that go nowhere have no direction to offset along
+    let segments: Vec<Segment> = segments(start, cut, x_scale)
+        .into_iter()
+        .filter(|segment| segment.length() > EPSILON)
+        .collect();
+    if segments.is_empty() {
+        return ((start.r * x_scale, start.z), vec![]);
+    }
+
+    // Nose centre path: each segment moved out by the radius
+    let mut centre: Vec<Segment> = vec![];
+    for segment in &segments {
+        let mut offset = Segment {
+            element: segment.element.clone(),
+            start: segment
+                .start
+                .add(normal(segment.tangent(segment.start), side), radius),
+            end: segment
+                .end
+                .add(normal(segment.tangent(segment.end), side), radius),
+            feed: segment.feed,
+        };
+        if let Some(previous) = centre.last_mut() {
+            if previous.end.minus(offset.start).length() > EPSILON {
+                let incoming = previous.tangent(previous.end);
+                let outgoing = offset.tangent(offset.start);
+                let turn = incoming.cross(outgoing);
+                let convex = match side {
+                    CompSide::Right => turn > 0.0,
+                    CompSide::Left => turn < 0.0,
+                };
+                if convex {
+                    // Roll around the corner on the nose radius
+                    let arc = Segment {
+                        element: Element::Arc {
+                            cw: side == CompSide::Left,
+                            centre: segment.start,
+                        },
+                        start: previous.end,
+                        end: offset.start,
+                        feed: segment.feed,
+                    };
+                    centre.push(arc);
+                } else if let Some(corner) = intersect(previous, &offset, segment.start) {
+                    // Inside corner, both elements stop where they cross
+                    previous.end = corner;
+                    offset.start = corner;
+                }
+            }
+        }
+        centre.push(offset);
+    }
+
+    // Program the imaginary tip rather than the nose centre
+    let tip = tip_vector(orientation);
+    let shift = Point {
+        z: tip.z * radius,
+        r: tip.r * radius,
+    };
+    let shifted: Vec<Segment> = centre
+        .iter()
+        .map(|segment| segment.translate(shift))
+        .collect();
+    let first = shifted[0].start;
+    ((first.r * x_scale, first.z), motions(&shifted, x_scale))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emitter::contour_lines;
+    use crate::post::LinuxCnc;
+
+    fn feed(x: Option<f32>, z: Option<f32>) -> Motion {
+        Motion::Feed { x, z, feed: 100.0 }
+    }
+
+    // Outside diameter cut in radius mode, tool above the work
+    fn offset(start: (f32, f32), cut: &[Motion], orientation: i32) -> ((f32, f32), Vec<String>) {
+        let (start, moves) = offset_cut(start, cut, CompSide::Right, 0.5, orientation, 1.0);
+        (start, contour_lines(&moves, &LinuxCnc))
+    }
+
+    fn assert_start(actual: (f32, f32), expected: (f32, f32)) {
+        assert!(
+            (actual.0 - expected.0).abs() < 0.001 && (actual.1 - expected.1).abs() < 0.001,
+            "start {:?}, expected {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn chamfer() {
+        let cut = [feed(Some(6.0), Some(-1.0)), feed(None, Some(-10.0))];
+        let (start, lines) = offset((5.0, 0.0), &cut, 9);
+        assert_start(start, (5.354, 0.354));
+        assert_eq!(
+            lines,
+            [
+                "G1 X6.354 Z-0.646 F100",
+                "G3 X6.500 Z-1.000 I-0.354 K-0.354",
+                "G1 Z-10.000",
+            ]
+        );
+
+        // Tip below and towards the chuck from the nose centre
+        let (start, lines) = offset((5.0, 0.0), &cut, 3);
+        assert_start(start, (4.854, -0.146));
+        assert_eq!(lines[0], "G1 X5.854 Z-1.146 F100");
+    }
+
+    #[test]
+    fn outside_corner() {
+        let cut = [feed(None, Some(-5.0)), feed(Some(3.0), None)];
+        let (start, lines) = offset((5.0, 0.0), &cut, 9);
+        assert_start(start, (5.5, 0.0));
+        assert_eq!(
+            lines,
+            [
+                "G1 Z-5.000 F100",
+                "G3 X5.000 Z-5.500 I-0.500 K0.000",
+                "G1 X3.000"
+            ]
+        );
+    }
+
+    #[test]
+    fn inside_corner() {
+        let cut = [feed(None, Some(-5.0)), feed(Some(8.0), None)];
+        let (_, lines) = offset((5.0, 0.0), &cut, 9);
+        assert_eq!(lines, ["G1 Z-4.500 F100", "   X8.000"]);
+    }
+
+    #[test]
+    fn inside_fillet() {
+        let cut = [
+            feed(None, Some(-5.0)),
+            Motion::Arc {
+                cw: true,
+                x: 7.0,
+                z: -7.0,
+                i: 2.0,
+                k: 0.0,
+                feed: 100.0,
+            },
+            feed(Some(9.0), None),
+        ];
+        let (start, lines) = offset((5.0, 0.0), &cut, 9);
+        assert_start(start, (5.5, 0.0));
+        assert_eq!(
+            lines,
+            [
+                "G1 Z-5.000 F100",
+                "G2 X7.000 Z-6.500 I1.500 K0.000",
+                "G1 X9.000"
+            ]
+        );
+    }
+
+    #[test]
+    fn zero_length_moves_are_skipped() {
+        let cut = [
+            feed(None, Some(-5.0)),
+            feed(None, Some(-5.0)),
+            feed(Some(8.0), None),
+        ];
+        let (_, lines) = offset((5.0, 0.0), &cut, 9);
+        assert_eq!(lines, ["G1 Z-4.500 F100", "   X8.000"]);
+
+        let (start, moves) = offset_cut((5.0, -5.0), &cut[1..2], CompSide::Right, 0.5, 9, 1.0);
+        assert_start(start, (5.0, -5.0));
+        assert!(moves.is_empty());
+    }
+}
@@ -0,0 +1,334 @@
+// Cycle time estimate. Replays a toolpath with the machine's rapid rates,
+// acceleration and tool change time. Feed moves run at their feed per minute,
+// or per revolution of the active spindle speed after G95, as do threads and
+// taps at their pitch.
+
+use std::f32::consts::PI;
+
+use crate::generator::{JobParams, Rates};
+use crate::geometry::Position;
+use crate::toolpath::{expand_cycles, Motion, SpindleSpeed, Toolpath};
+
+pub struct Estimator<'a> {
+    rates: &'a Rates,
+    units_inch: bool,
+    feed_per_rev: bool,
+    position: Position,
+    speed: Option<SpindleSpeed>,
+}
+
+fn axis_time(distance: f32, rate: f32, acceleration: f32) -> f32 {
+    // Seconds to move distance from rest to rest at rate per minute, on a
+    // trapezoidal velocity profile or a triangle when too short to get up
+    // to speed
+    let velocity = rate / 60.0;
+    if acceleration <= 0.0 {
+        distance / velocity
+    } else if distance >= velocity * velocity / acceleration {
+        distance / velocity + velocity / acceleration
+    } else {
+        2.0 * (distance / acceleration).sqrt()
+    }
+}
+
+impl<'a> Estimator<'a> {
+    pub fn new(rates: &'a Rates) -> Estimator<'a> {
+        Estimator {
+            rates,
+            units_inch: false,
+            feed_per_rev: false,
+            position: Position::new(false),
+            speed: None,
+        }
+    }
+
+    fn rpm_at(&self, x: Option<f32>) -> f32 {
+        match self.speed {
+            Some(SpindleSpeed::Rpm(rpm)) => rpm as f32,
+            Some(SpindleSpeed::SurfaceSpeed { speed, max_rpm }) => {
+                // Surface speed is per metre or per foot, at the diameter x
+                let per_unit = if self.units_inch { 12.0 } else { 1000.0 };
+                let diameter = x.map_or(0.0, |x| (x * 2.0 / self.position.x_scale).abs());
+                if diameter > 0.0 {
+                    (speed * per_unit / (PI * diameter)).min(max_rpm as f32)
+                } else {
+                    max_rpm as f32
+                }
+            }
+            None => 0.0,
+        }
+    }
+
+    fn rapid(&self, x: Option<f32>, z: Option<f32>) -> f32 {
+        // Both axes move together, the slower one sets the time
+        let x_time = match (self.position.x, x) {
+            (Some(from), Some(to)) => axis_time(
+                (to - from).abs() / self.position.x_scale,
+                self.rates.rapid_x,
+                self.rates.acceleration,
+            ),
+            _ => 0.0,
+        };
+        let z_time = match (self.position.z, z) {
+            (Some(from), Some(to)) => axis_time(
+                (to - from).abs(),
+                self.rates.rapid_z,
+                self.rates.acceleration,
+            ),
+            _ => 0.0,
+        };
+        x_time.max(z_time)
+    }
+
+    fn feed(&self, motion: &Motion) -> f32 {
+        // Nothing to measure from until the position is known
+        let mut time = 0.0;
+        for segment in self.position.segments(motion) {
+            let rate = if self.feed_per_rev {
+                // Surface speed changes the rpm along the segment, take the middle
+                let middle = segment.point_at(0.5);
+                segment.feed * self.rpm_at(Some(middle.r * self.position.x_scale))
+            } else {
+                segment.feed
+            };
+            if rate > 0.0 {
+                time += axis_time(segment.length(), rate, self.rates.acceleration);
+            }
+        }
+        time
+    }
+
+    fn thread(&self, from: f32, to: f32, pitch: f32) -> f32 {
+        // Spindle synchronised, one pitch per revolution
+        let rate = pitch * self.rpm_at(self.position.x);
+        if rate > 0.0 {
+            (to - from).abs() / rate * 60.0
+        } else {
+            0.0
+        }
+    }
+
+    // Seconds to run the moves, carrying the position and spindle on to the
+    // next call
+    pub fn run(&mut self, moves: &[Motion]) -> f32 {
+        let mut time = 0.0;
+        for motion in &expand_cycles(moves) {
+            time += match *motion {
+                Motion::Setup {
+                    units_inch,
+                    feed_per_rev,
+                    ..
+                } => {
+                    self.units_inch = units_inch;
+                    self.feed_per_rev = feed_per_rev;
+                    0.0
+                }
+                Motion::ToolChange(_) => self.rates.tool_change,
+                Motion::Spindle { speed, .. } => {
+                    self.speed = Some(speed);
+                    0.0
+                }
+                Motion::SpindleStop => {
+                    self.speed = None;
+                    0.0
+                }
+                // The reference position isn't known, so the trip there and
+                // back isn't counted
+                Motion::Reference { .. } => 0.0,
+                Motion::Rapid { x, z } => self.rapid(x, z),
+                Motion::Feed { .. } | Motion::Arc { .. } => self.feed(motion),
+                Motion::Dwell(seconds) => seconds,
+                Motion::Thread { z, pitch } => match self.position.z {
+                    Some(from) => self.thread(from, z, pitch),
+                    None => 0.0,
+                },
+                Motion::RigidTap { z, pitch } => match self.position.z {
+                    Some(from) => 2.0 * self.thread(from, z, pitch),
+                    None => 0.0,
+                },
+                Motion::Cycle(_)
+                | Motion::Coolant(_)
+                | Motion::NoseCompensation(_)
+                | Motion::Comment(_)
+                | Motion::End => 0.0,
+            };
+            self.position.follow(motion);
+        }
+        time
+    }
+}
+
+pub struct Estimate {
+    pub sections: Vec<(String, f32)>, // Name and seconds
+}
+
+impl Estimate {
+    // Times for named sections, run one after the other
+    pub fn new(names: Vec<String>, sections: &[Toolpath], rates: &Rates) -> Estimate {
+        let mut estimator = Estimator::new(rates);
+        Estimate {
+            sections: names
+                .into_iter()
+                .zip(sections)
+                .map(|(name, section)| (name, estimator.run(&section.moves)))
+                .collect(),
+        }
+    }
+
+    pub fn total(&self) -> f32 {
+        self.sections.iter().map(|(_, time)| time).sum()
+    }
+
+    // One line per section and the total, for printing or as comments
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .sections
+            .iter()
+            .map(|(name, time)| format!("{:<16} {}", name, format_time(*time)))
+            .collect();
+        lines.push(format!("{:<16} {}", "total", format_time(self.total())));
+        lines
+    }
+
+    // Comments to put at the start of the program
+    pub fn header(&self) -> Toolpath {
+        let mut path = Toolpath::new();
+        path.push(Motion::Comment("Estimated cycle time".to_string()));
+        for line in self.summary() {
+            path.push(Motion::Comment(line));
+        }
+        path
+    }
+}
+
+// Names for the sections from generate_sections: the setup, each operation
+// and the program end
+pub fn section_names(jobs: &[JobParams]) -> Vec<String> {
+    let mut names = vec!["setup".to_string()];
+    names.extend(
+        jobs.iter()
+            .enumerate()
+            .map(|(i, job)| format!("{} {}", i + 1, job.job_type.name())),
+    );
+    names.push("end".to_string());
+    names
+}
+
+pub fn format_time(seconds: f32) -> String {
+    let seconds = seconds.round() as i64;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    fn no_acceleration() -> Rates {
+        Rates {
+            rapid_x: 1000.0,
+            rapid_z: 3000.0,
+            acceleration: 0.0,
+            tool_change: 5.0,
+        }
+    }
+
+    // A 50 long cut along Z on X20 in diameter mode, after the given spindle.
+    // The rapid there starts from nowhere and takes no time.
+    fn cut(feed_per_rev: bool, speed: SpindleSpeed, feed: f32) -> Vec<Motion> {
+        vec![
+            Motion::Setup {
+                units_inch: false,
+                radius_mode: false,
+                feed_per_rev,
+            },
+            Motion::Spindle { speed, cw: true },
+            Motion::Rapid {
+                x: Some(20.0),
+                z: Some(1.0),
+            },
+            Motion::Feed {
+                x: None,
+                z: Some(-49.0),
+                feed,
+            },
+        ]
+    }
+
+    #[test]
+    fn trapezoid_profile() {
+        // 100/s takes 0.2s and 10 to reach, leaving 80 at full speed
+        assert!(close(axis_time(100.0, 6000.0, 500.0), 1.2));
+    }
+
+    #[test]
+    fn triangle_profile() {
+        // Too short to reach full speed, half accelerating and half braking
+        assert!(close(axis_time(5.0, 6000.0, 500.0), 0.2));
+        // Both profiles agree where full speed is just reached
+        assert!(close(axis_time(20.0, 6000.0, 500.0), 0.4));
+    }
+
+    #[test]
+    fn without_acceleration() {
+        assert!(close(axis_time(100.0, 6000.0, 0.0), 1.0));
+    }
+
+    #[test]
+    fn rapids_take_the_slower_axis() {
+        let rates = no_acceleration();
+        let mut estimator = Estimator::new(&rates);
+        let moves = [
+            Motion::Rapid {
+                x: Some(0.0),
+                z: Some(0.0),
+            },
+            // 50 radial at 1000/min against 100 at 3000/min
+            Motion::Rapid {
+                x: Some(100.0),
+                z: Some(100.0),
+            },
+            Motion::ToolChange(2),
+        ];
+        assert!(close(estimator.run(&moves), 3.0 + 5.0));
+    }
+
+    #[test]
+    fn feed_per_minute() {
+        let rates = no_acceleration();
+        let moves = cut(false, SpindleSpeed::Rpm(500), 100.0);
+        assert!(close(Estimator::new(&rates).run(&moves), 30.0));
+    }
+
+    #[test]
+    fn feed_per_rev_at_the_spindle_rpm() {
+        let rates = no_acceleration();
+        let moves = cut(true, SpindleSpeed::Rpm(500), 0.2);
+        assert!(close(Estimator::new(&rates).run(&moves), 30.0));
+    }
+
+    #[test]
+    fn feed_per_rev_at_surface_speed() {
+        // 500 rpm on a diameter of 20
+        let rates = no_acceleration();
+        let speed = SpindleSpeed::SurfaceSpeed {
+            speed: PI * 20.0 * 500.0 / 1000.0,
+            max_rpm: 3000,
+        };
+        let moves = cut(true, speed, 0.2);
+        assert!(close(Estimator::new(&rates).run(&moves), 30.0));
+    }
+
+    #[test]
+    fn format_hours_minutes_seconds() {
+        assert_eq!(format_time(3725.4), "1:02:05");
+    }
+}
@@ -0,0 +1,225 @@
+// Turns a Toolpath into G-code text for the chosen PostProcessor.
+// Tracks modal state so repeated motion words and feeds are only written
+// when they change, continuation lines are indented like hand written code.
+
+use crate::post::{PostProcessor, ProgramState};
+use crate::toolpath::{Motion, Toolpath};
+
+struct Modal {
+    motion: Option<&'static str>, // Active G0/G1/G2/G3, None after cycles
+    feed: Option<f32>,
+    state: ProgramState,
+}
+
+fn axis_words(x: Option<f32>, z: Option<f32>) -> String {
+    let mut words = vec![];
+    if let Some(x) = x {
+        words.push(format!("X{:.3}", x));
+    }
+    if let Some(z) = z {
+        words.push(format!("Z{:.3}", z));
+    }
+    words.join(" ")
+}
+
+fn motion_line(modal: &mut Modal, code: &'static str, words: String, feed: Option<f32>) -> String {
+    let mut line = if modal.motion == Some(code) {
+        format!("   {}", words)
+    } else {
+        format!("{} {}", code, words)
+    };
+    if let Some(feed) = feed {
+        if modal.feed != Some(feed) {
+            line += &format!(" F{}", feed);
+            modal.feed = Some(feed);
+        }
+    }
+    modal.motion = Some(code);
+    line
+}
+
+fn motion_lines(modal: &mut Modal, moves: &[Motion], post: &dyn PostProcessor) -> Vec<String> {
+    let mut lines = vec![];
+    for motion in moves {
+        let line = match *motion {
+            Motion::Setup {
+                units_inch,
+                radius_mode,
+                feed_per_rev,
+            } => {
+                modal.state = ProgramState {
+                    units_inch,
+                    radius_mode,
+                };
+                post.setup(units_inch, radius_mode, feed_per_rev)
+            }
+            Motion::ToolChange(tool) => {
+                // Restate the motion word for the new tool
+                modal.motion = None;
+                post.tool_change(tool)
+            }
+            Motion::Spindle { speed, cw } => post.spindle(speed, cw),
+            Motion::SpindleStop => "M5".to_string(),
+            Motion::Coolant(on) => (if on { "M8" } else { "M9" }).to_string(),
+            Motion::Rapid { x, z } => motion_line(modal, "G0", axis_words(x, z), None),
+            Motion::Feed { x, z, feed } => motion_line(modal, "G1", axis_words(x, z), Some(feed)),
+            Motion::Arc {
+                cw,
+                x,
+                z,
+                i,
+                k,
+                feed,
+            } => {
+                let words = format!("X{:.3} Z{:.3} I{:.3} K{:.3}", x, z, i, k);
+                motion_line(modal, if cw { "G2" } else { "G3" }, words, Some(feed))
+            }
+            Motion::Dwell(seconds) => post.dwell(seconds),
+            Motion::Reference { second } => {
+                modal.motion = None;
+                post.reference(second)
+            }
+            Motion::NoseCompensation(side) => {
+                // The lead-in/out move restates its motion word
+                modal.motion = None;
+                post.nose_compensation(side)
+            }
+            Motion::Thread { z, pitch } => {
+                modal.motion = None;
+                post.thread_move(z, pitch)
+            }
+            Motion::RigidTap { z, pitch } => {
+                modal.motion = None;
+                post.rigid_tap(z, pitch)
+            }
+            Motion::Cycle(ref cycle) => match post.cycle(cycle, &modal.state) {
+                Some(code) => {
+                    modal.motion = None;
+                    modal.feed = None;
+                    code
+                }
+                None => {
+                    // Not supported by this controller
+                    lines.append(&mut motion_lines(modal, &cycle.expand().moves, post));
+                    continue;
+                }
+            },
+            Motion::Comment(ref text) => post.comment(text),
+            Motion::End => post.program_end().join("\n"),
+        };
+        lines.extend(line.split('\n').map(String::from));
+    }
+    lines
+}
+
+// Stand alone blocks for contours that cycles refer to, with their own modal
+// state
+pub fn contour_lines(moves: &[Motion], post: &dyn PostProcessor) -> Vec<String> {
+    let mut modal = Modal {
+        motion: None,
+        feed: None,
+        state: ProgramState {
+            units_inch: false,
+            radius_mode: false,
+        },
+    };
+    motion_lines(&mut modal, moves, post)
+}
+
+pub fn emit_gcode(path: &Toolpath, post: &dyn PostProcessor) -> String {
+    let mut modal = Modal {
+        motion: None,
+        feed: None,
+        state: ProgramState {
+            units_inch: false,
+            radius_mode: false,
+        },
+    };
+    let mut lines = post.program_start();
+    let mut number = 0;
+
+    for line in motion_lines(&mut modal, &path.moves, post) {
+        // Program delimiters, O-numbers and blocks a cycle already numbered
+        // are left alone
+        match post.line_number_step() {
+            Some(step) if !line.starts_with(['%', 'O', 'N']) => {
+                number += step;
+                lines.push(format!("N{} {}", number, line.trim_start()));
+            }
+            _ => lines.push(line),
+        }
+    }
+    let mut code = lines.join("\n");
+    code += "\n";
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::DrillCycle;
+    use crate::post::{Grbl, LinuxCnc};
+    use crate::toolpath::{Cycle, SpindleSpeed};
+
+    #[test]
+    fn modal_words_are_written_once() {
+        let mut path = Toolpath::new();
+        path.push(Motion::Setup {
+            units_inch: false,
+            radius_mode: false,
+            feed_per_rev: false,
+        });
+        path.push(Motion::ToolChange(1));
+        path.push(Motion::Spindle {
+            speed: SpindleSpeed::Rpm(800),
+            cw: true,
+        });
+        path.rapid_x(20.0);
+        path.rapid_z(1.0);
+        path.feed_z(-10.0, 100.0);
+        path.feed_x(22.0, 100.0);
+        path.feed_z(-20.0, 80.0);
+        path.push(Motion::Arc {
+            cw: false,
+            x: 24.0,
+            z: -21.0,
+            i: 0.0,
+            k: -1.0,
+            feed: 80.0,
+        });
+        path.push(Motion::ToolChange(2));
+        path.rapid_x(30.0);
+        path.push(Motion::End);
+        assert_eq!(
+            emit_gcode(&path, &LinuxCnc),
+            "G90 G21 G64 G18 G7
+M6 T1 G43
+G97 S800 M3
+G0 X20.000
+   Z1.000
+G1 Z-10.000 F100
+   X22.000
+   Z-20.000 F80
+G3 X24.000 Z-21.000 I0.000 K-1.000
+M6 T2 G43
+G0 X30.000
+M2
+"
+        );
+    }
+
+    #[test]
+    fn unsupported_cycles_are_expanded() {
+        let drill = Cycle::Drill {
+            kind: DrillCycle::Simple,
+            start_z: 0.0,
+            z: -5.0,
+            retract: 1.0,
+            peck: 0.0,
+            dwell: 0.0,
+            feed: 60.0,
+        };
+        let lines = contour_lines(&[Motion::Cycle(drill)], &Grbl);
+        assert_eq!(lines, ["G1 Z-5.000 F60", "G0 Z1.000"]);
+    }
+}
@@ -0,0 +1,431 @@
+// Machine envelope checks. Every move is followed in program coordinates and
+// the programmed point is checked against the travel limits, the chuck jaws
+// and the tailstock from the machine definition.
+
+use std::fmt;
+
+use crate::error::JobError;
+use crate::generator::MachineParams;
+use crate::geometry::{Element, Point, Position, EPSILON};
+use crate::toolpath::{expand_cycles, Motion, Toolpath};
+
+const CHORD: f32 = 0.1; // Arcs are checked as chords of about this length
+
+#[derive(Debug, PartialEq)]
+pub enum Obstacle {
+    XTravel,
+    ZTravel,
+    Chuck,
+    Tailstock,
+}
+
+// Positions are program units
+#[derive(Debug, PartialEq)]
+pub struct Collision {
+    pub rapid: bool,
+    pub to: (Option<f32>, Option<f32>), // Words of the move, X first
+    pub at: Option<(f32, f32)>,         // Unknown when the move starts from a reference position
+    pub obstacle: Obstacle,
+}
+
+impl fmt::Display for Collision {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} to", if self.rapid { "rapid" } else { "feed" })?;
+        if let Some(x) = self.to.0 {
+            write!(f, " X{:.3}", x)?;
+        }
+        if let Some(z) = self.to.1 {
+            write!(f, " Z{:.3}", z)?;
+        }
+        match self.obstacle {
+            Obstacle::XTravel => write!(f, " goes beyond the X travel")?,
+            Obstacle::ZTravel => write!(f, " goes beyond the Z travel")?,
+            Obstacle::Chuck => write!(f, " hits the chuck")?,
+            Obstacle::Tailstock => write!(f, " hits the tailstock")?,
+        }
+        match self.at {
+            Some((x, z)) => write!(f, " at X{:.3} Z{:.3}", x, z),
+            None => Ok(()),
+        }
+    }
+}
+
+fn outside(value: f32, min: f32, max: f32) -> bool {
+    value < min - EPSILON || value > max + EPSILON
+}
+
+fn leaves(from: f32, to: f32, min: f32, max: f32) -> Option<f32> {
+    // Fraction of the way from -> to where a coordinate leaves min..max. A
+    // move that starts outside was reported when it went there.
+    if outside(from, min, max) || !outside(to, min, max) {
+        None
+    } else if to > max {
+        Some((max - from) / (to - from))
+    } else {
+        Some((min - from) / (to - from))
+    }
+}
+
+fn enters(from: Point, to: Point, z: (f32, f32), x: (f32, f32)) -> Option<f32> {
+    // Fraction of the way from -> to where the line enters the box, clipped
+    // edge by edge. Open sides are infinite.
+    let d = to.minus(from);
+    let (mut first, mut last) = (0.0f32, 1.0f32);
+    for &(p, q) in &[
+        (-d.z, from.z - z.0),
+        (d.z, z.1 - from.z),
+        (-d.r, from.r - x.0),
+        (d.r, x.1 - from.r),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            first = first.max(q / p);
+        } else {
+            last = last.min(q / p);
+        }
+    }
+    if first <= last {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+// Follows the programmed point, r holds X in program units
+pub struct Envelope<'a> {
+    machine: &'a MachineParams,
+    position: Position,
+    first: Option<Collision>,
+}
+
+impl<'a> Envelope<'a> {
+    pub fn new(machine: &'a MachineParams) -> Envelope<'a> {
+        Envelope {
+            machine,
+            position: Position::new(machine.radius_mode),
+            first: None,
+        }
+    }
+
+    fn hit(&self, from: Point, to: Point) -> Option<(Obstacle, f32)> {
+        // The first obstacle along a straight move
+        let mut hits = vec![];
+        if let Some(ref limits) = self.machine.limits {
+            if let Some(t) = leaves(from.r, to.r, limits.x_min, limits.x_max) {
+                hits.push((Obstacle::XTravel, t));
+            }
+            if let Some(t) = leaves(from.z, to.z, limits.z_min, limits.z_max) {
+                hits.push((Obstacle::ZTravel, t));
+            }
+        }
+        if let Some(chuck) = self.machine.chuck {
+            let x = (-chuck.x.abs(), chuck.x.abs());
+            if let Some(t) = enters(from, to, (-f32::INFINITY, chuck.z), x) {
+                hits.push((Obstacle::Chuck, t));
+            }
+        }
+        if let Some(tailstock) = self.machine.tailstock {
+            let x = (-tailstock.x.abs(), tailstock.x.abs());
+            if let Some(t) = enters(from, to, (tailstock.z, f32::INFINITY), x) {
+                hits.push((Obstacle::Tailstock, t));
+            }
+        }
+        hits.into_iter()
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+    }
+
+    fn line(
+        &self,
+        rapid: bool,
+        from: Point,
+        to: Point,
+        words: (Option<f32>, Option<f32>),
+    ) -> Option<Collision> {
+        self.hit(from, to).map(|(obstacle, t)| {
+            let at = from.add(to.minus(from), t);
+            Collision {
+                rapid,
+                to: words,
+                at: Some((at.r, at.z)),
+                obstacle,
+            }
+        })
+    }
+
+    fn words(&self, rapid: bool, x: Option<f32>, z: Option<f32>) -> Option<Collision> {
+        // Only the words are known of a move out of a reference position
+        let limits = self.machine.limits.as_ref()?;
+        let obstacle = match (x, z) {
+            (Some(x), _) if outside(x, limits.x_min, limits.x_max) => Obstacle::XTravel,
+            (_, Some(z)) if outside(z, limits.z_min, limits.z_max) => Obstacle::ZTravel,
+            _ => return None,
+        };
+        Some(Collision {
+            rapid,
+            to: (x, z),
+            at: None,
+            obstacle,
+        })
+    }
+
+    fn straight(
+        &self,
+        rapid: bool,
+        from: Option<Point>,
+        to: Option<Point>,
+        (x, z): (Option<f32>, Option<f32>),
+    ) -> Option<Collision> {
+        match (from, to) {
+            (Some(from), Some(to)) => self.line(rapid, from, to, (x, z)),
+            (None, Some(to)) => self
+                .words(rapid, x, z)
+                .or_else(|| self.line(rapid, to, to, (x, z))),
+            _ => self.words(rapid, x, z),
+        }
+    }
+
+    fn arc(
+        &self,
+        motion: &Motion,
+        start: Point,
+        words: (Option<f32>, Option<f32>),
+    ) -> Option<Collision> {
+        let x_scale = self.position.x_scale;
+        let mut from = start;
+        for segment in self.position.segments(motion) {
+            let pieces = match segment.element {
+                Element::Line => 1,
+                Element::Arc { .. } => (segment.length() / CHORD).ceil().max(8.0) as usize,
+            };
+            for piece in 1..=pieces {
+                let point = segment.point_at(piece as f32 / pieces as f32);
+                let to = Point {
+                    z: point.z,
+                    r: point.r * x_scale,
+                };
+                let collision = self.line(false, from, to, words);
+                if collision.is_some() {
+                    return collision;
+                }
+                from = to;
+            }
+        }
+        None
+    }
+
+    fn run(&mut self, moves: &[Motion]) {
+        // Keeps the first collision, later ones usually follow from it
+        for motion in &expand_cycles(moves) {
+            let from = self.position.program();
+            let to = self.position.after(motion);
+            let collision = match *motion {
+                Motion::Rapid { x, z } => self.straight(true, from, to.program(), (x, z)),
+                Motion::Feed { x, z, .. } => self.straight(false, from, to.program(), (x, z)),
+                Motion::Arc { x, z, .. } => match from {
+                    Some(start) => self.arc(motion, start, (Some(x), Some(z))),
+                    None => self.straight(false, from, to.program(), (Some(x), Some(z))),
+                },
+                Motion::Thread { z, .. } => {
+                    self.straight(false, from, to.program(), (None, Some(z)))
+                }
+                Motion::RigidTap { z, .. } => {
+                    let bottom = Position { z: Some(z), ..to }.program();
+                    self.straight(false, from, bottom, (None, Some(z)))
+                }
+                _ => None,
+            };
+            self.position = to;
+            if self.first.is_none() {
+                self.first = collision;
+            }
+        }
+    }
+
+    pub fn check(&mut self, path: &Toolpath) -> Option<Collision> {
+        self.run(&path.moves);
+        self.first.take()
+    }
+}
+
+pub fn check(sections: &[Toolpath], machine: &MachineParams) -> Vec<JobError> {
+    // The first collision in each section. Operation sections sit between the
+    // machine setup and the program end, as generate_sections lays them out.
+    let mut envelope = Envelope::new(machine);
+    let mut errors = vec![];
+    for (i, section) in sections.iter().enumerate() {
+        if let Some(collision) = envelope.check(section) {
+            let error = JobError::Collision(collision);
+            if i == 0 || i + 1 == sections.len() {
+                errors.push(error);
+            } else {
+                errors.push(JobError::InOperation {
+                    index: i,
+                    error: Box::new(error),
+                });
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{Limits, Tailstock};
+    use crate::job::Program;
+
+    fn point(z: f32, r: f32) -> Point {
+        Point { z, r }
+    }
+
+    fn close(a: Option<f32>, b: f32) -> bool {
+        a.is_some_and(|a| (a - b).abs() < 1e-5)
+    }
+
+    #[test]
+    fn leaving_the_travel() {
+        assert!(close(leaves(0.0, 20.0, -5.0, 10.0), 0.5));
+        assert!(close(leaves(0.0, -10.0, -5.0, 10.0), 0.5));
+        // Staying inside, or already outside
+        assert_eq!(leaves(0.0, 10.0, -5.0, 10.0), None);
+        assert_eq!(leaves(20.0, 30.0, -5.0, 10.0), None);
+    }
+
+    #[test]
+    fn entering_a_box() {
+        let z = (-f32::INFINITY, -40.0);
+        let x = (-60.0, 60.0);
+        // Straight along Z into the face of the jaws
+        assert!(close(
+            enters(point(0.0, 20.0), point(-50.0, 20.0), z, x),
+            0.8
+        ));
+        // Down onto the top of the jaws from above
+        assert!(close(
+            enters(point(-45.0, 80.0), point(-45.0, 40.0), z, x),
+            0.5
+        ));
+        // Already inside
+        assert!(close(
+            enters(point(-45.0, 20.0), point(0.0, 20.0), z, x),
+            0.0
+        ));
+        // Passing over, and stopping short
+        assert_eq!(enters(point(0.0, 70.0), point(-50.0, 70.0), z, x), None);
+        assert_eq!(enters(point(0.0, 20.0), point(-30.0, 20.0), z, x), None);
+        // Across the corner
+        assert!(close(
+            enters(point(-30.0, 70.0), point(-50.0, 50.0), z, x),
+            0.5
+        ));
+        assert_eq!(enters(point(-30.0, 70.0), point(-40.0, 80.0), z, x), None);
+    }
+
+    fn shaft() -> Program {
+        Program::from_toml(include_str!("../examples/shaft.toml")).unwrap()
+    }
+
+    fn moves(moves: Vec<Motion>) -> Toolpath {
+        Toolpath { moves }
+    }
+
+    #[test]
+    fn into_the_chuck() {
+        let program = shaft();
+        let mut envelope = Envelope::new(&program.machine);
+        let path = moves(vec![
+            Motion::Rapid {
+                x: Some(20.0),
+                z: Some(1.0),
+            },
+            Motion::Feed {
+                x: None,
+                z: Some(-50.0),
+                feed: 100.0,
+            },
+        ]);
+        assert_eq!(
+            envelope.check(&path),
+            Some(Collision {
+                rapid: false,
+                to: (None, Some(-50.0)),
+                at: Some((20.0, -39.0)),
+                obstacle: Obstacle::Chuck,
+            })
+        );
+    }
+
+    #[test]
+    fn first_obstacle_along_the_move() {
+        // Travel ends at Z-45 behind the jaw face at Z-39
+        let mut program = shaft();
+        program.machine.limits = Some(Limits {
+            x_min: -5.0,
+            x_max: 150.0,
+            z_min: -45.0,
+            z_max: 100.0,
+        });
+        let rapid = Motion::Rapid {
+            x: Some(20.0),
+            z: Some(-50.0),
+        };
+        let start = Motion::Rapid {
+            x: Some(20.0),
+            z: Some(1.0),
+        };
+        let mut envelope = Envelope::new(&program.machine);
+        let collision = envelope.check(&moves(vec![start, rapid.clone()]));
+        assert_eq!(collision.unwrap().obstacle, Obstacle::Chuck);
+        // Out of a reference position only the words can be checked
+        let mut envelope = Envelope::new(&program.machine);
+        let collision = envelope.check(&moves(vec![rapid])).unwrap();
+        assert_eq!(
+            (collision.obstacle, collision.at),
+            (Obstacle::ZTravel, None)
+        );
+    }
+
+    #[test]
+    fn arcs_checked_along_their_chords() {
+        // A half circle of radius 5 from X0 to X20, bulging to Z5 past the
+        // tailstock at Z4. The chord from end to end stays clear of it.
+        let mut program = shaft();
+        program.machine.tailstock = Some(Tailstock { x: 20.0, z: 4.0 });
+        let mut envelope = Envelope::new(&program.machine);
+        let path = moves(vec![
+            Motion::Rapid {
+                x: Some(0.0),
+                z: Some(0.0),
+            },
+            Motion::Arc {
+                cw: false,
+                x: 20.0,
+                z: 0.0,
+                i: 5.0,
+                k: 0.0,
+                feed: 100.0,
+            },
+        ]);
+        let collision = envelope.check(&path).unwrap();
+        assert_eq!(collision.obstacle, Obstacle::Tailstock);
+        // Reached 3 off the centre at radius 5, X4 in diameter mode
+        let (x, z) = collision.at.unwrap();
+        assert!(
+            (z - 4.0).abs() < 1e-3 && (x - 4.0).abs() < 0.1,
+            "{}",
+            collision
+        );
+    }
+
+    #[test]
+    fn shaft_example_is_clear() {
+        let program = shaft();
+        let (sections, warnings) =
+            crate::generator::generate_sections(&program.operations, &program.machine).unwrap();
+        assert!(warnings.is_empty());
+        assert!(check(&sections, &program.machine).is_empty());
+    }
+}
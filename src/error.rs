@@ -0,0 +1,261 @@
+// Problems found when validating a job before any G-code is generated.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::envelope::Collision;
+
+#[derive(Debug, PartialEq)]
+pub enum JobError {
+    NotPositive { field: &'static str, value: f32 },
+    Negative { field: &'static str, value: f32 },
+    FinishBeyondStart { start: f32, finish: f32 }, // Finish can't be reached cutting from start
+    MinorAboveMajor { major: f32, minor: f32 },
+    ThreadDepthMismatch { depth: f32, expected: f32 }, // G76 cuts to the depth, passes to the minor
+    InnerAboveOuter { inner: f32, outer: f32 },
+    RetractBelowHole { retract: f32, start: f32 },
+    ZeroLength,
+    UnknownTool(i32),                               // Not in the machine tool table
+    BadOrientation { tool: i32, orientation: i32 }, // Tool tip orientation outside 1-9
+    CompensationUnsupported,                        // Controller has no G41/G42
+    RigidTapUnsupported,                            // Controller has no rigid tapping
+    CssUnsupported,                                 // Controller has no constant surface speed
+    AngleOutOfRange { angle: f32, max: f32 },       // Degrees
+    UnknownMorseTaper(u8),
+    CornersTooLong { corners: f32, length: f32 }, // Start and end corners overlap
+    CornerNotSupported,                           // Only turning and facing have corners
+    FilletBelowNoseRadius { radius: f32, nose_radius: f32 },
+    ScheduleNotSupported, // Finishing schedules are for turning, facing, boring and threading
+    EmptySchedule,
+    ScheduleTooDeep { finishing: f32, depth: f32 }, // Finishing passes exceed the thread depth
+    EmptyContour,
+    ProfileNotMonotonic { element: usize }, // element counts from 1
+    ArcTooSmall { element: usize, radius: f32 }, // Radius can't span the chord
+    GrooveNarrowerThanTool { width: f32, tool_width: f32 },
+    OverlapTooLarge { overlap: f32, tool_width: f32 }, // Plunges would not advance
+    DegressionOutOfRange(f32),                         // Roughing or thread degression outside 1-3
+    SafeDiameterInStock { x: f32, limit: f32, boring: bool }, // Retract would cut through stock
+    TravelReversed { axis: char, min: f32, max: f32 },
+    Collision(Collision), // A move leaves the travel or runs into the workholding
+    InOperation { index: usize, error: Box<JobError> }, // index counts from 1
+    Invalid(Vec<JobError>), // Every problem found
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            JobError::NotPositive { field, value } => {
+                write!(f, "{} must be greater than zero, got {}", field, value)
+            }
+            JobError::Negative { field, value } => {
+                write!(f, "{} must not be negative, got {}", field, value)
+            }
+            JobError::FinishBeyondStart { start, finish } => write!(
+                f,
+                "finish depth {} is beyond start depth {} for this cut direction",
+                finish, start
+            ),
+            JobError::MinorAboveMajor { major, minor } => write!(
+                f,
+                "minor diameter {} must be smaller than major diameter {}",
+                minor, major
+            ),
+            JobError::InnerAboveOuter { inner, outer } => write!(
+                f,
+                "inner diameter {} must be smaller than outer diameter {}",
+                inner, outer
+            ),
+            JobError::RetractBelowHole { retract, start } => write!(
+                f,
+                "retract plane {} must not be below the top of the hole {}",
+                retract, start
+            ),
+            JobError::ZeroLength => write!(f, "length must not be zero"),
+            JobError::UnknownTool(tool) => {
+                write!(f, "tool {} is not in the machine tool table", tool)
+            }
+            JobError::BadOrientation { tool, orientation } => write!(
+                f,
+                "tool {} orientation must be 1 to 9, got {}",
+                tool, orientation
+            ),
+            JobError::ThreadDepthMismatch { depth, expected } => write!(
+                f,
+                "thread_depth {} doesn't match the major and minor diameters, they give {:.4}",
+                depth, expected
+            ),
+            JobError::AngleOutOfRange { angle, max } => write!(
+                f,
+                "angle must be between 0 and {} degrees, got {}",
+                max, angle
+            ),
+            JobError::UnknownMorseTaper(number) => {
+                write!(f, "no Morse taper number {}, use 0 to 7", number)
+            }
+            JobError::CornersTooLong { corners, length } => write!(
+                f,
+                "corners take {} of the cut but the length is only {}",
+                corners, length
+            ),
+            JobError::ScheduleNotSupported => write!(
+                f,
+                "finishing schedules can only be used for turning, facing, boring and threading"
+            ),
+            JobError::EmptySchedule => write!(f, "finishing schedule needs at least one depth"),
+            JobError::ScheduleTooDeep { finishing, depth } => write!(
+                f,
+                "finishing passes {} must be shallower than the thread depth {}",
+                finishing, depth
+            ),
+            JobError::CornerNotSupported => {
+                write!(
+                    f,
+                    "chamfers and radii can only be added to turning and facing"
+                )
+            }
+            JobError::FilletBelowNoseRadius {
+                radius,
+                nose_radius,
+            } => write!(
+                f,
+                "fillet radius {} is smaller than the tool nose radius {}",
+                radius, nose_radius
+            ),
+            JobError::EmptyContour => write!(f, "profile contour has no elements"),
+            JobError::ProfileNotMonotonic { element } => write!(
+                f,
+                "contour element {} turns back, X must only grow and Z only fall",
+                element
+            ),
+            JobError::ArcTooSmall { element, radius } => write!(
+                f,
+                "contour element {} radius {} is too small to reach its end point",
+                element, radius
+            ),
+            JobError::GrooveNarrowerThanTool { width, tool_width } => write!(
+                f,
+                "groove width {} is narrower than the tool width {}",
+                width, tool_width
+            ),
+            JobError::OverlapTooLarge {
+                overlap,
+                tool_width,
+            } => write!(
+                f,
+                "plunge overlap {} must be less than the tool width {}",
+                overlap, tool_width
+            ),
+            JobError::RigidTapUnsupported => write!(
+                f,
+                "controller has no rigid tapping, use a floating tap holder"
+            ),
+            JobError::CssUnsupported => write!(
+                f,
+                "controller has no constant surface speed, use a fixed rpm"
+            ),
+            JobError::CompensationUnsupported => write!(
+                f,
+                "controller has no nose radius compensation, use computed compensation"
+            ),
+            JobError::DegressionOutOfRange(degression) => {
+                write!(f, "degression {} must be between 1 and 3", degression)
+            }
+            JobError::SafeDiameterInStock {
+                x,
+                limit,
+                boring: false,
+            } => write!(
+                f,
+                "safe diameter {} must be clear of the stock, at least {}",
+                x, limit
+            ),
+            JobError::SafeDiameterInStock { x, limit, .. } => write!(
+                f,
+                "safe diameter {} must be inside the bore, at most {}",
+                x, limit
+            ),
+            JobError::TravelReversed { axis, min, max } => write!(
+                f,
+                "{} travel minimum {} must be less than the maximum {}",
+                axis, min, max
+            ),
+            JobError::Collision(ref collision) => write!(f, "{}", collision),
+            JobError::InOperation { index, ref error } => {
+                write!(f, "operation {}: {}", index, error)
+            }
+            JobError::Invalid(ref errors) => {
+                write!(f, "invalid job")?;
+                for error in errors {
+                    write!(f, "\n  - {}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for JobError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        let cases = [
+            (
+                JobError::NotPositive {
+                    field: "step",
+                    value: 0.0,
+                },
+                "step must be greater than zero, got 0",
+            ),
+            (
+                JobError::FinishBeyondStart {
+                    start: 10.0,
+                    finish: 12.0,
+                },
+                "finish depth 12 is beyond start depth 10 for this cut direction",
+            ),
+            (
+                JobError::SafeDiameterInStock {
+                    x: 8.0,
+                    limit: 9.0,
+                    boring: true,
+                },
+                "safe diameter 8 must be inside the bore, at most 9",
+            ),
+            (
+                JobError::ThreadDepthMismatch {
+                    depth: 0.9,
+                    expected: 0.92,
+                },
+                "thread_depth 0.9 doesn't match the major and minor diameters, they give 0.9200",
+            ),
+        ];
+        for (error, message) in &cases {
+            assert_eq!(error.to_string(), *message);
+        }
+    }
+
+    #[test]
+    fn every_problem_is_listed() {
+        let error = JobError::Invalid(vec![
+            JobError::TravelReversed {
+                axis: 'X',
+                min: 5.0,
+                max: 1.0,
+            },
+            JobError::InOperation {
+                index: 2,
+                error: Box::new(JobError::ZeroLength),
+            },
+        ]);
+        assert_eq!(
+            error.to_string(),
+            "invalid job
+  - X travel minimum 5 must be less than the maximum 1
+  - operation 2: length must not be zero"
+        );
+    }
+}
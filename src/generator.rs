@@ -1,155 +1,2320 @@
 // TO-DO:
-// - Threading
-// - Spindle surface mode
-// - Boring
 // - Additional machine parameters
 
-const CLEARANCE: f32 = 1.0;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
+use crate::compensation::{offset_cut, side_for};
+use crate::emitter::emit_gcode;
+use crate::envelope;
+use crate::error::JobError;
+use crate::geometry::{x_scale, EPSILON};
+use crate::post::Controller;
+use crate::toolpath::{Cycle, Motion, Profile, SpindleSpeed, Toolpath, DEGRESSION};
+
+const ROUGH_RETRACT: f32 = 0.5; // Pull off at the end of each profile roughing layer
+const MIN_CHIP: f32 = 0.5; // Thinnest degressive roughing pass, as a share of the step
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum JobType {
-    Boring, // Not yet implemented
+    Boring(BoringParams),
     Facing,
     FaceBoring,
     Turning,
-    Drilling, //Not yet implemented
-              // Threading,
+    Taper(TaperParams),
+    Profile(ProfileParams),
+    Grooving(GrooveParams),
+    FaceGrooving(FaceGrooveParams),
+    Parting(PartParams),
+    Drilling(DrillParams),
+    Tapping(TapParams),
+    Threading(ThreadParams),
+}
+
+impl JobType {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            JobType::Boring(_) => "boring",
+            JobType::Facing => "facing",
+            JobType::FaceBoring => "face boring",
+            JobType::Turning => "turning",
+            JobType::Taper(_) => "taper",
+            JobType::Profile(_) => "profile",
+            JobType::Grooving(_) => "grooving",
+            JobType::FaceGrooving(_) => "face grooving",
+            JobType::Parting(_) => "parting",
+            JobType::Drilling(_) => "drilling",
+            JobType::Tapping(_) => "tapping",
+            JobType::Threading(_) => "threading",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ThreadParams {
+    pub pitch: f32,          // Distance per revolution
+    pub major_diameter: f32, // Thread crest
+    pub minor_diameter: f32, // Thread root
+    pub thread_depth: f32,   // Full depth, measured radially
+    pub infeed_angle: f32,   // Compound slide angle in degrees, 29.5 typical
+    pub spring_passes: i32,  // Extra passes at full depth
+    pub degression: f32,     // 1.0 -> constant depth, 2.0 -> constant area
+}
+
+// Diameter change per unit length for Morse tapers 0-7
+const MORSE_TAPERS: [f32; 8] = [
+    0.05205, 0.04988, 0.04995, 0.05020, 0.05194, 0.05263, 0.05214, 0.05200,
+];
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaperEnd {
+    Diameter(f32), // X at the end of the cut
+    Angle(f32),    // Included angle in degrees
+    PerFoot(f32),  // Taper per foot, diameter change over 12 units of length
+    Morse(u8),     // Morse taper number
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TaperParams {
+    pub end: TaperEnd, // Starts at finish_depth, angles grow towards the end of the cut
+}
+
+impl TaperParams {
+    // X at the end of the cut, job.finish_depth is X at start_cut
+    pub fn end_x(&self, job: &JobParams, radius_mode: bool) -> f32 {
+        let x_scale = x_scale(radius_mode);
+        let ratio = match self.end {
+            TaperEnd::Diameter(x) => return x,
+            TaperEnd::Angle(angle) => 2.0 * (angle / 2.0).to_radians().tan(),
+            TaperEnd::PerFoot(per_foot) => per_foot / 12.0,
+            TaperEnd::Morse(number) => MORSE_TAPERS[number as usize],
+        };
+        // Ratio is diametral, X may be a radius
+        job.finish_depth + ratio * job.length.abs() * x_scale / 2.0
+    }
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContourElement {
+    Line {
+        x: f32,
+        z: f32,
+    },
+    Arc {
+        x: f32,
+        z: f32,
+        radius: f32,
+        cw: bool,
+    }, // The shorter arc to x, z
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProfileParams {
+    pub start_x: f32, // First point of the contour, at the tailstock end
+    pub start_z: f32,
+    pub contour: Vec<ContourElement>, // X must not fall and Z must not rise along it
+}
+
+impl FromStr for ProfileParams {
+    type Err = String;
+
+    // Blocks separated by ';', the first is the start point:
+    // "X10 Z0; X12 Z-1; X12 Z-20; X16 Z-22 R2 G3"
+    fn from_str(text: &str) -> Result<ProfileParams, String> {
+        let mut points = vec![];
+        for block in text.split(';').filter(|block| !block.trim().is_empty()) {
+            let (mut x, mut z, mut radius, mut cw) = (None, None, None, false);
+            for word in block.split_whitespace() {
+                let word = word.to_uppercase();
+                // Split after the first character, it need not be ASCII
+                let mut chars = word.chars();
+                let letter = chars.next();
+                let value = chars.as_str();
+                match letter {
+                    Some('G') if value == "2" || value == "3" => cw = value == "2",
+                    Some('X') | Some('Z') | Some('R') => {
+                        let value = value
+                            .parse::<f32>()
+                            .map_err(|_| format!("bad number in contour: {}", word))?;
+                        match letter {
+                            Some('X') => x = Some(value),
+                            Some('Z') => z = Some(value),
+                            _ => radius = Some(value),
+                        }
+                    }
+                    _ => return Err(format!("unknown word in contour: {}", word)),
+                }
+            }
+            match (x, z) {
+                (Some(x), Some(z)) => points.push((x, z, radius, cw)),
+                _ => return Err(format!("contour block needs X and Z: {}", block.trim())),
+            }
+        }
+        if points.is_empty() {
+            return Err("empty contour".to_string());
+        }
+        let contour = points[1..]
+            .iter()
+            .map(|&(x, z, radius, cw)| match radius {
+                Some(radius) => ContourElement::Arc { x, z, radius, cw },
+                None => ContourElement::Line { x, z },
+            })
+            .collect();
+        Ok(ProfileParams {
+            start_x: points[0].0,
+            start_z: points[0].1,
+            contour,
+        })
+    }
+}
+
+// Centre (radius, z) of the shorter arc between two (radius, z) points. The
+// centre sits right of the chord for G2, left for G3.
+pub fn arc_centre(start: (f32, f32), end: (f32, f32), radius: f32, cw: bool) -> Option<(f32, f32)> {
+    let (dr, dz) = (end.0 - start.0, end.1 - start.1);
+    let chord = (dr * dr + dz * dz).sqrt();
+    if radius.is_nan() || chord == 0.0 || radius < chord / 2.0 - 1e-6 {
+        return None;
+    }
+    let height = (radius * radius - chord * chord / 4.0).max(0.0).sqrt();
+    let side = if cw { 1.0 } else { -1.0 };
+    Some((
+        start.0 + dr / 2.0 - side * dz / chord * height,
+        start.1 + dz / 2.0 + side * dr / chord * height,
+    ))
+}
+
+impl ProfileParams {
+    // Feed and arc moves along the contour. Arc centres are radial I/K from
+    // the start of each arc.
+    fn moves(&self, feed: f32, radius_mode: bool) -> Vec<Motion> {
+        let x_scale = x_scale(radius_mode);
+        let mut position = (self.start_x / x_scale, self.start_z);
+        let mut moves = vec![];
+        for element in &self.contour {
+            match *element {
+                ContourElement::Line { x, z } => {
+                    // Only the axes that move
+                    let end = (x / x_scale, z);
+                    moves.push(Motion::Feed {
+                        x: if end.0 != position.0 { Some(x) } else { None },
+                        z: if end.1 != position.1 { Some(z) } else { None },
+                        feed,
+                    });
+                    position = end;
+                }
+                ContourElement::Arc { x, z, radius, cw } => {
+                    let end = (x / x_scale, z);
+                    let centre = arc_centre(position, end, radius, cw).unwrap_or(position);
+                    moves.push(Motion::Arc {
+                        cw,
+                        x,
+                        z,
+                        i: centre.0 - position.0,
+                        k: centre.1 - position.1,
+                        feed,
+                    });
+                    position = end;
+                }
+            }
+        }
+        moves
+    }
+}
+
+// Grooving and parting blades are set with their tailstock side corner on Z.
+// Depths in X run from start_depth (the OD) down to finish_depth.
+#[derive(Serialize, Deserialize)]
+pub struct GrooveParams {
+    pub width: f32, // Groove runs from start_cut towards the chuck
+    pub tool_width: f32,
+    pub overlap: f32, // Between plunges when the groove is wider than the tool
+    pub peck: f32,    // X depth of each peck, 0 plunges in one go
+    pub retract: f32, // Back off in X after each peck to break the chip
+}
+
+// Face grooves plunge in Z from the face at start_depth down to finish_depth.
+// The blade's outer corner is set on X.
+#[derive(Serialize, Deserialize)]
+pub struct FaceGrooveParams {
+    pub inner_diameter: f32,
+    pub outer_diameter: f32,
+    pub tool_width: f32,
+    pub overlap: f32, // Between plunges when the groove is wider than the tool
+    pub peck: f32,    // Z depth of each peck, 0 plunges in one go
+    pub retract: f32, // Back off in Z after each peck to break the chip
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PartParams {
+    pub peck: f32,
+    pub retract: f32,
+    pub slow_diameter: f32, // Below this X the feed drops to slow_feed
+    pub slow_feed: f32,
+    pub chamfer: f32, // Chamfer on the part before it comes off, 0 for none
+}
+
+// How the roughing depth is split between passes
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PassStrategy {
+    Equal, // Step rounded down so every pass takes the same cut
+    #[default]
+    MaxStep, // Full steps, the remainder comes off on the first pass
+    Degressive(f32), // Lighter cuts with depth down to half a step, 1.0 -> full steps, up to 3.0
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BoreExit {
+    Retract,    // Step off towards centreline, rapid out
+    FeedOut,    // Feed back out along the bore (G85 style)
+    Dwell(f32), // Dwell in seconds at the bottom, then feed out (G89 style)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BoringParams {
+    pub exit: BoreExit,
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DrillCycle {
+    Simple,    // G81
+    Dwell,     // G82
+    Peck,      // G83 - full retract between pecks
+    ChipBreak, // G73 - short retract between pecks
+}
+
+impl FromStr for DrillCycle {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<DrillCycle, String> {
+        match name.to_lowercase().as_ref() {
+            "simple" => Ok(DrillCycle::Simple),
+            "dwell" => Ok(DrillCycle::Dwell),
+            "peck" => Ok(DrillCycle::Peck),
+            "chipbreak" => Ok(DrillCycle::ChipBreak),
+            _ => Err(format!("unknown drill cycle: {}", name)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DrillParams {
+    pub cycle: DrillCycle,
+    pub peck: f32,    // Peck depth
+    pub retract: f32, // R plane, absolute Z
+    pub dwell: f32,   // Dwell at bottom in seconds
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TapMode {
+    Rigid,    // G33.1, the controller reverses the spindle
+    Floating, // Matched feed into a floating holder, spindle reversed by M3/M4
+}
+
+impl FromStr for TapMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<TapMode, String> {
+        match name.to_lowercase().as_ref() {
+            "rigid" => Ok(TapMode::Rigid),
+            "floating" => Ok(TapMode::Floating),
+            _ => Err(format!("unknown tapping mode: {}", name)),
+        }
+    }
+}
+
+// Taps cut on the centreline from start_cut down by length. The thread hand
+// follows spindle_cw, the spindle runs in reverse to back the tap out.
+#[derive(Serialize, Deserialize)]
+pub struct TapParams {
+    pub pitch: f32,
+    pub mode: TapMode,
+    pub retract: f32, // Start and return plane, absolute Z
+    pub dwell: f32,   // Floating only, seconds at the bottom before reversing
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Corner {
+    Chamfer { size: f32, angle: f32 }, // size along the cut, angle in degrees from the cut surface
+    Radius(f32),                       // G2/G3 fillet or round
+}
+
+impl Corner {
+    // Legs of the corner (along the cut, across it), radial for X
+    fn legs(&self) -> (f32, f32) {
+        match *self {
+            Corner::Chamfer { size, angle } => (size, size * angle.to_radians().tan()),
+            Corner::Radius(radius) => (radius, radius),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FinishSchedule {
+    pub depths: Vec<f32>, // Depth of each finishing pass, the last is the final pass
+    #[serde(default)]
+    pub spring_passes: i32, // Repeats of the final pass without any depth
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct JobParams {
     pub job_type: JobType,
-    pub start_depth: f32,  // OD of material
-    pub finish_depth: f32, // Final OD
+    pub start_depth: f32,  // OD of material, pilot hole for boring
+    pub finish_depth: f32, // Final OD, final ID for boring
     pub step: f32,         // DOC
     pub finish_step: f32,  // finishing DOC
     pub start_cut: f32,    // Start of Z
     pub length: f32,       // -ve implies cutting dir is away from chuck
     pub feed: f32,         // Feedrate
+    pub tool: Option<i32>, // Overrides machine tool_num
+    pub rpm: Option<i32>,  // Overrides machine rpm
+    #[serde(default)]
+    pub start_corner: Option<Corner>, // At start_cut, the edge of the face or OD
+    #[serde(default)]
+    pub end_corner: Option<Corner>, // Where the cut meets the shoulder
+    #[serde(default)]
+    pub finish_feed: Option<f32>, // Overrides feed on the finishing pass
+    #[serde(default)]
+    pub finish_speed: Option<f32>, // Finishing rpm, or surface speed when running CSS
+    #[serde(default)]
+    pub finishing: Option<FinishSchedule>, // Overrides finish_step, radial for threading
+    #[serde(default)]
+    pub pass_strategy: PassStrategy, // Roughing passes for turning, taper, facing and boring
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpindleMode {
+    #[default]
+    Rpm, // G97 at rpm
+    // G96, surface speed in m/min or ft/min
+    Css {
+        surface_speed: f32,
+        max_rpm: i32,
+    },
+}
+
+// Distance kept from the stock on rapids, X in program units
+#[derive(Serialize, Deserialize)]
+pub struct Clearance {
+    pub x: f32,
+    pub z: f32,
 }
 
+impl Default for Clearance {
+    fn default() -> Clearance {
+        Clearance { x: 1.0, z: 1.0 }
+    }
+}
+
+// Machine speeds used to estimate cycle times, in program units
+#[derive(Serialize, Deserialize)]
+pub struct Rates {
+    pub rapid_x: f32,      // Per minute, radial
+    pub rapid_z: f32,      // Per minute
+    pub acceleration: f32, // Per second squared, both axes
+    pub tool_change: f32,  // Seconds
+}
+
+impl Default for Rates {
+    fn default() -> Rates {
+        Rates {
+            rapid_x: 5000.0,
+            rapid_z: 5000.0,
+            acceleration: 500.0,
+            tool_change: 3.0,
+        }
+    }
+}
+
+// Travel of the machine in program coordinates, X in program units
+#[derive(Serialize, Deserialize)]
+pub struct Limits {
+    pub x_min: f32,
+    pub x_max: f32,
+    pub z_min: f32,
+    pub z_max: f32,
+}
+
+// Jaws reach out to X and towards the tailstock as far as Z
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Chuck {
+    pub x: f32, // Program units, over the jaws
+    pub z: f32, // Jaw faces
+}
+
+// The centre and quill take up everything within X from Z towards the tailstock
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Tailstock {
+    pub x: f32, // Program units, over the quill
+    pub z: f32, // Point of the centre
+}
+
+// Parses positions given on the command line as "X60 Z-40"
+fn parse_xz(text: &str, what: &str) -> Result<(f32, f32), String> {
+    let (mut x, mut z) = (None, None);
+    for word in text.split_whitespace() {
+        let mut chars = word.chars();
+        let letter = chars.next().map(|c| c.to_ascii_uppercase());
+        let value = chars
+            .as_str()
+            .parse::<f32>()
+            .map_err(|_| format!("bad {}: {}", what, text))?;
+        match letter {
+            Some('X') => x = Some(value),
+            Some('Z') => z = Some(value),
+            _ => return Err(format!("bad {}: {}", what, text)),
+        }
+    }
+    match (x, z) {
+        (Some(x), Some(z)) => Ok((x, z)),
+        _ => Err(format!("{} needs X and Z: {}", what, text)),
+    }
+}
+
+impl FromStr for Chuck {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Chuck, String> {
+        parse_xz(text, "chuck").map(|(x, z)| Chuck { x, z })
+    }
+}
+
+impl FromStr for Tailstock {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Tailstock, String> {
+        parse_xz(text, "tailstock").map(|(x, z)| Tailstock { x, z })
+    }
+}
+
+// What to do when a move leaves the travel or runs into the workholding
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionAction {
+    #[default]
+    Refuse, // No program is generated
+    Warn,
+}
+
+// Where roughing passes go at the end of the cut before the rapid back
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RetractMode {
+    #[default]
+    Stock, // Feed out to the clearance outside the stock
+    Diameter(f32), // Feed out of the stock, then rapid to a fixed safe X
+    Previous,      // Feed out to the clearance above the previous pass
+    Diagonal,      // 45 degree pull off by the clearance, back towards the start
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolChangePosition {
+    G28,                         // Reference position
+    G30,                         // Second reference position
+    Position { x: f32, z: f32 }, // Program coordinates, X first
+}
+
+impl FromStr for ToolChangePosition {
+    type Err = String;
+
+    // g28, g30 or "X100 Z50"
+    fn from_str(text: &str) -> Result<ToolChangePosition, String> {
+        match text.trim().to_lowercase().as_ref() {
+            "g28" => Ok(ToolChangePosition::G28),
+            "g30" => Ok(ToolChangePosition::G30),
+            _ => parse_xz(text, "tool change position")
+                .map(|(x, z)| ToolChangePosition::Position { x, z }),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Tool {
+    pub number: i32,
+    pub nose_radius: f32,
+    pub orientation: i32, // Tip direction 1-9 as in the controller tool table
+}
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoseCompensation {
+    #[default]
+    Off, // Finishing passes cut on the programmed line
+    Controller, // G41/G42 with lead-in/out on finishing passes
+    Computed,   // Offset path from the tool table, for controllers without G41/G42
+}
+
+impl FromStr for NoseCompensation {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<NoseCompensation, String> {
+        match name.to_lowercase().as_ref() {
+            "off" => Ok(NoseCompensation::Off),
+            "controller" => Ok(NoseCompensation::Controller),
+            "computed" => Ok(NoseCompensation::Computed),
+            _ => Err(format!("unknown nose compensation: {}", name)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct MachineParams {
-    pub rpm: i32,
+    pub rpm: i32, // Also used for operations that can't run CSS
+    #[serde(default)]
+    pub spindle_mode: SpindleMode,
     pub spindle_cw: bool, // false -> CCW
     pub units_inch: bool, // true -> mm, default: mm
     pub tool_num: i32,
-    pub radius_mode: bool, // false -> diameter mode (G7), default: (G8)
-                           // clearance override
-                           // chamfer parts - need cutter comp?
-                           // surface mode
-                           // surface speed
+    pub radius_mode: bool,   // false -> diameter mode (G7), default: (G8)
+    pub canned_cycles: bool, // false -> expand cycles (G76, G8x) into explicit moves
+    #[serde(default)]
+    pub controller: Controller, // Dialect of the emitted G-code
+    #[serde(default)]
+    pub tools: Vec<Tool>, // Tool geometry, needed for computed compensation
+    #[serde(default)]
+    pub nose_compensation: NoseCompensation,
+    #[serde(default)]
+    pub clearance: Clearance,
+    #[serde(default)]
+    pub retract: RetractMode, // Roughing passes of turning, facing and boring
+    #[serde(default)]
+    pub tool_change: Option<ToolChangePosition>, // Visited at the start and end of the program
+    #[serde(default)]
+    pub rates: Rates,
+    #[serde(default)]
+    pub limits: Option<Limits>,
+    #[serde(default)]
+    pub chuck: Option<Chuck>,
+    #[serde(default)]
+    pub tailstock: Option<Tailstock>,
+    #[serde(default)]
+    pub on_collision: CollisionAction,
+}
+
+impl MachineParams {
+    pub fn tool(&self, number: i32) -> Option<&Tool> {
+        self.tools.iter().find(|tool| tool.number == number)
+    }
+
+    pub fn x_scale(&self) -> f32 {
+        x_scale(self.radius_mode)
+    }
+}
+
+fn check_positive(errors: &mut Vec<JobError>, field: &'static str, value: f32) {
+    // NaN fails both checks
+    if value.is_nan() || value <= 0.0 {
+        errors.push(JobError::NotPositive { field, value });
+    }
+}
+
+fn check_not_negative(errors: &mut Vec<JobError>, field: &'static str, value: f32) {
+    if value.is_nan() || value < 0.0 {
+        errors.push(JobError::Negative { field, value });
+    }
+}
+
+fn compensation_problems(errors: &mut Vec<JobError>, job: &JobParams, machine: &MachineParams) {
+    let number = job.tool.unwrap_or(machine.tool_num);
+    if let Some(tool) = machine.tool(number) {
+        check_not_negative(errors, "nose_radius", tool.nose_radius);
+        if tool.orientation < 1 || tool.orientation > 9 {
+            errors.push(JobError::BadOrientation {
+                tool: number,
+                orientation: tool.orientation,
+            });
+        }
+    }
+    match machine.nose_compensation {
+        NoseCompensation::Off => (),
+        NoseCompensation::Controller => {
+            if !machine
+                .controller
+                .post_processor()
+                .nose_compensation_supported()
+            {
+                errors.push(JobError::CompensationUnsupported);
+            }
+        }
+        NoseCompensation::Computed => match machine.tool(number) {
+            None => errors.push(JobError::UnknownTool(number)),
+            Some(tool) => {
+                // End corners are inside corners, the nose can't follow a
+                // smaller fillet
+                if let Some(Corner::Radius(radius)) = job.end_corner {
+                    if radius < tool.nose_radius {
+                        errors.push(JobError::FilletBelowNoseRadius {
+                            radius,
+                            nose_radius: tool.nose_radius,
+                        });
+                    }
+                }
+            }
+        },
+    }
+}
+
+fn contour_problems(errors: &mut Vec<JobError>, profile: &ProfileParams, radius_mode: bool) {
+    // Type I roughing needs X to only grow and Z to only fall, arcs included
+    if profile.contour.is_empty() {
+        errors.push(JobError::EmptyContour);
+    }
+    let x_scale = x_scale(radius_mode);
+    let mut from = (profile.start_x, profile.start_z);
+    for (i, element) in profile.contour.iter().enumerate() {
+        let (x, z) = match *element {
+            ContourElement::Line { x, z } => (x, z),
+            ContourElement::Arc { x, z, radius, cw } => {
+                let start = (from.0 / x_scale, from.1);
+                let end = (x / x_scale, z);
+                match arc_centre(start, end, radius, cw) {
+                    Some(centre) => {
+                        // Both ends in one quadrant of the centre, or the arc
+                        // passes an extreme of X or Z
+                        let opposite = |a: f32, b: f32| a * b < -1e-6;
+                        if opposite(start.0 - centre.0, end.0 - centre.0)
+                            || opposite(start.1 - centre.1, end.1 - centre.1)
+                        {
+                            errors.push(JobError::ProfileNotMonotonic { element: i + 1 });
+                        }
+                    }
+                    None => errors.push(JobError::ArcTooSmall {
+                        element: i + 1,
+                        radius,
+                    }),
+                }
+                (x, z)
+            }
+        };
+        if x < from.0 || z > from.1 {
+            errors.push(JobError::ProfileNotMonotonic { element: i + 1 });
+        }
+        from = (x, z);
+    }
+}
+
+fn schedule_problems(errors: &mut Vec<JobError>, job: &JobParams) {
+    let schedule = match job.finishing {
+        Some(ref schedule) => schedule,
+        None => return,
+    };
+    match job.job_type {
+        JobType::Turning
+        | JobType::Facing
+        | JobType::FaceBoring
+        | JobType::Boring(_)
+        | JobType::Threading(_) => (),
+        _ => {
+            errors.push(JobError::ScheduleNotSupported);
+            return;
+        }
+    }
+    if schedule.depths.is_empty() {
+        errors.push(JobError::EmptySchedule);
+    }
+    for depth in &schedule.depths {
+        check_positive(errors, "finishing depth", *depth);
+    }
+    check_not_negative(errors, "spring_passes", schedule.spring_passes as f32);
+    if let JobType::Threading(ref thread) = job.job_type {
+        let finishing: f32 = schedule.depths.iter().sum();
+        if finishing >= thread.thread_depth {
+            errors.push(JobError::ScheduleTooDeep {
+                finishing,
+                depth: thread.thread_depth,
+            });
+        }
+    }
+}
+
+fn retract_problems(errors: &mut Vec<JobError>, job: &JobParams, machine: &MachineParams) {
+    // A safe diameter has to be clear of the stock for OD work and inside
+    // the bore for boring
+    let diameter = match machine.retract {
+        RetractMode::Diameter(x) => x,
+        _ => return,
+    };
+    match job.job_type {
+        JobType::Turning => {
+            let limit = job.start_depth + machine.clearance.x;
+            if diameter.is_nan() || diameter < limit {
+                errors.push(JobError::SafeDiameterInStock {
+                    x: diameter,
+                    limit,
+                    boring: false,
+                });
+            }
+        }
+        JobType::Boring(BoringParams {
+            exit: BoreExit::Retract,
+        }) => {
+            let limit = job.start_depth - machine.clearance.x;
+            if diameter.is_nan() || diameter > limit {
+                errors.push(JobError::SafeDiameterInStock {
+                    x: diameter,
+                    limit,
+                    boring: true,
+                });
+            }
+        }
+        _ => (),
+    }
+}
+
+fn corner_problems(errors: &mut Vec<JobError>, job: &JobParams, machine: &MachineParams) {
+    let corners = [job.start_corner, job.end_corner];
+    if corners.iter().all(Option::is_none) {
+        return;
+    }
+    match job.job_type {
+        JobType::Turning | JobType::Facing => (),
+        _ => {
+            errors.push(JobError::CornerNotSupported);
+            return;
+        }
+    }
+    let mut along = 0.0;
+    for corner in corners.iter().flatten() {
+        match *corner {
+            Corner::Chamfer { size, angle } => {
+                check_positive(errors, "chamfer size", size);
+                if angle.is_nan() || angle <= 0.0 || angle >= 90.0 {
+                    errors.push(JobError::AngleOutOfRange { angle, max: 90.0 });
+                }
+            }
+            Corner::Radius(radius) => check_positive(errors, "corner radius", radius),
+        }
+        along += corner.legs().0;
+    }
+    // Legs are radial, a facing cut's length runs along X in program units
+    if let JobType::Facing = job.job_type {
+        along *= machine.x_scale();
+    }
+    if along > job.length.abs() {
+        errors.push(JobError::CornersTooLong {
+            corners: along,
+            length: job.length.abs(),
+        });
+    }
+}
+
+impl JobParams {
+    pub fn finishing_feed(&self) -> f32 {
+        self.finish_feed.unwrap_or(self.feed)
+    }
+
+    // A single pass of finish_step unless a schedule is given
+    pub fn finish_schedule(&self) -> FinishSchedule {
+        match self.finishing {
+            Some(ref schedule) => schedule.clone(),
+            None => FinishSchedule {
+                depths: vec![self.finish_step],
+                spring_passes: 0,
+            },
+        }
+    }
+
+    // Passes at the end of the cut that take the finishing feed, speed and
+    // nose compensation
+    fn finishing_passes(&self) -> usize {
+        let schedule = self.finish_schedule();
+        schedule.depths.len() + schedule.spring_passes.max(0) as usize
+    }
+
+    // The surface the operation leaves, as moves in program units from a
+    // rapid to its first point
+    pub fn finished_surface(&self, machine: &MachineParams) -> Toolpath {
+        let mut path = Toolpath::new();
+        let end_z = self.start_cut - self.length;
+        let mut line = |points: &[(f32, f32)]| {
+            path.push(Motion::Rapid {
+                x: Some(points[0].0),
+                z: Some(points[0].1),
+            });
+            for &(x, z) in &points[1..] {
+                path.feed_xz(x, z, 0.0);
+            }
+        };
+        match self.job_type {
+            JobType::Turning => {
+                let (x, cut) = z_finish_cut(self, machine, self.finish_depth, 0.0);
+                line(&[(x, self.start_cut)]);
+                path.append(cut);
+            }
+            JobType::Boring(_) => line(&[
+                (self.finish_depth, self.start_cut),
+                (self.finish_depth, end_z),
+                (self.start_depth, end_z),
+            ]),
+            JobType::Facing | JobType::FaceBoring => {
+                let (z, cut) = x_finish_cut(self, machine, self.finish_depth, 0.0);
+                line(&[(self.start_cut, z)]);
+                path.append(cut);
+            }
+            JobType::Taper(ref taper) => line(&[
+                (self.finish_depth, self.start_cut),
+                (taper.end_x(self, machine.radius_mode), end_z),
+                (self.start_depth, end_z),
+            ]),
+            JobType::Profile(ref profile) => {
+                line(&[(profile.start_x, profile.start_z)]);
+                path.moves.extend(profile.moves(0.0, machine.radius_mode));
+            }
+            JobType::Grooving(ref groove) => line(&[
+                (self.start_depth, self.start_cut),
+                (self.finish_depth, self.start_cut),
+                (self.finish_depth, self.start_cut - groove.width),
+                (self.start_depth, self.start_cut - groove.width),
+            ]),
+            JobType::FaceGrooving(ref groove) => line(&[
+                (groove.outer_diameter, self.start_depth),
+                (groove.outer_diameter, self.finish_depth),
+                (groove.inner_diameter, self.finish_depth),
+                (groove.inner_diameter, self.start_depth),
+            ]),
+            JobType::Parting(_) => line(&[
+                (self.start_depth, self.start_cut),
+                (self.finish_depth, self.start_cut),
+            ]),
+            JobType::Drilling(_) | JobType::Tapping(_) => {
+                line(&[(0.0, self.start_cut), (0.0, end_z)])
+            }
+            JobType::Threading(ref thread) => line(&[
+                (thread.minor_diameter, self.start_cut),
+                (thread.minor_diameter, end_z),
+            ]),
+        }
+        path
+    }
+
+    // Every problem with the job, empty when it is safe to generate
+    pub fn problems(&self, machine: &MachineParams) -> Vec<JobError> {
+        let mut errors = vec![];
+        // Profiles take their length from the contour, plunges have none
+        let no_length = matches!(
+            self.job_type,
+            JobType::Profile(_)
+                | JobType::Grooving(_)
+                | JobType::FaceGrooving(_)
+                | JobType::Parting(_)
+        );
+        if self.length == 0.0 && !no_length {
+            errors.push(JobError::ZeroLength);
+        }
+        if let Some(feed) = self.finish_feed {
+            check_positive(&mut errors, "finish_feed", feed);
+        }
+        if let Some(speed) = self.finish_speed {
+            check_positive(&mut errors, "finish_speed", speed);
+        }
+        if let PassStrategy::Degressive(degression) = self.pass_strategy {
+            // NaN fails both checks
+            if degression.is_nan() || !DEGRESSION.contains(&degression) {
+                errors.push(JobError::DegressionOutOfRange(degression));
+            }
+        }
+
+        match self.job_type {
+            JobType::Turning
+            | JobType::Taper(_)
+            | JobType::Facing
+            | JobType::FaceBoring
+            | JobType::Boring(_) => {
+                // Boring grows the diameter, everything else reduces it
+                let reversed = match self.job_type {
+                    JobType::Boring(_) => self.finish_depth < self.start_depth,
+                    _ => self.finish_depth > self.start_depth,
+                };
+                if reversed {
+                    errors.push(JobError::FinishBeyondStart {
+                        start: self.start_depth,
+                        finish: self.finish_depth,
+                    });
+                }
+                check_positive(&mut errors, "step", self.step);
+                check_not_negative(&mut errors, "finish_step", self.finish_step);
+                check_positive(&mut errors, "feed", self.feed);
+                if let JobType::Boring(BoringParams {
+                    exit: BoreExit::Dwell(seconds),
+                }) = self.job_type
+                {
+                    check_not_negative(&mut errors, "dwell", seconds);
+                }
+                if let JobType::Taper(ref taper) = self.job_type {
+                    match taper.end {
+                        TaperEnd::Diameter(x) => check_not_negative(&mut errors, "end diameter", x),
+                        TaperEnd::Angle(angle) => {
+                            if angle.is_nan() || angle <= 0.0 || angle >= 180.0 {
+                                errors.push(JobError::AngleOutOfRange { angle, max: 180.0 });
+                            }
+                        }
+                        TaperEnd::PerFoot(per_foot) => {
+                            check_positive(&mut errors, "taper per foot", per_foot)
+                        }
+                        TaperEnd::Morse(number) => {
+                            if number as usize >= MORSE_TAPERS.len() {
+                                errors.push(JobError::UnknownMorseTaper(number));
+                            }
+                        }
+                    }
+                }
+                retract_problems(&mut errors, self, machine);
+                compensation_problems(&mut errors, self, machine);
+            }
+            JobType::Profile(ref profile) => {
+                check_positive(&mut errors, "step", self.step);
+                check_not_negative(&mut errors, "finish_step", self.finish_step);
+                check_positive(&mut errors, "feed", self.feed);
+                if profile.start_x > self.start_depth {
+                    errors.push(JobError::FinishBeyondStart {
+                        start: self.start_depth,
+                        finish: profile.start_x,
+                    });
+                }
+                contour_problems(&mut errors, profile, machine.radius_mode);
+                compensation_problems(&mut errors, self, machine);
+            }
+            JobType::Grooving(ref groove) => {
+                check_positive(&mut errors, "feed", self.feed);
+                check_positive(&mut errors, "tool_width", groove.tool_width);
+                check_not_negative(&mut errors, "finish_step", self.finish_step);
+                check_not_negative(&mut errors, "peck", groove.peck);
+                check_not_negative(&mut errors, "retract", groove.retract);
+                check_not_negative(&mut errors, "overlap", groove.overlap);
+                if self.finish_depth >= self.start_depth {
+                    errors.push(JobError::FinishBeyondStart {
+                        start: self.start_depth,
+                        finish: self.finish_depth,
+                    });
+                }
+                if groove.width < groove.tool_width {
+                    errors.push(JobError::GrooveNarrowerThanTool {
+                        width: groove.width,
+                        tool_width: groove.tool_width,
+                    });
+                }
+                if groove.overlap >= groove.tool_width {
+                    errors.push(JobError::OverlapTooLarge {
+                        overlap: groove.overlap,
+                        tool_width: groove.tool_width,
+                    });
+                }
+            }
+            JobType::FaceGrooving(ref groove) => {
+                check_positive(&mut errors, "feed", self.feed);
+                check_positive(&mut errors, "tool_width", groove.tool_width);
+                check_not_negative(&mut errors, "finish_step", self.finish_step);
+                check_not_negative(&mut errors, "peck", groove.peck);
+                check_not_negative(&mut errors, "retract", groove.retract);
+                check_not_negative(&mut errors, "overlap", groove.overlap);
+                check_not_negative(&mut errors, "inner_diameter", groove.inner_diameter);
+                if self.finish_depth >= self.start_depth {
+                    errors.push(JobError::FinishBeyondStart {
+                        start: self.start_depth,
+                        finish: self.finish_depth,
+                    });
+                }
+                if groove.inner_diameter >= groove.outer_diameter {
+                    errors.push(JobError::InnerAboveOuter {
+                        inner: groove.inner_diameter,
+                        outer: groove.outer_diameter,
+                    });
+                }
+                let x_scale = machine.x_scale();
+                let width = (groove.outer_diameter - groove.inner_diameter) / x_scale;
+                if width < groove.tool_width {
+                    errors.push(JobError::GrooveNarrowerThanTool {
+                        width,
+                        tool_width: groove.tool_width,
+                    });
+                }
+                if groove.overlap >= groove.tool_width {
+                    errors.push(JobError::OverlapTooLarge {
+                        overlap: groove.overlap,
+                        tool_width: groove.tool_width,
+                    });
+                }
+            }
+            JobType::Parting(ref part) => {
+                check_positive(&mut errors, "feed", self.feed);
+                check_positive(&mut errors, "slow_feed", part.slow_feed);
+                check_not_negative(&mut errors, "peck", part.peck);
+                check_not_negative(&mut errors, "retract", part.retract);
+                check_not_negative(&mut errors, "chamfer", part.chamfer);
+                check_not_negative(&mut errors, "finish_depth", self.finish_depth);
+                if self.finish_depth >= self.start_depth {
+                    errors.push(JobError::FinishBeyondStart {
+                        start: self.start_depth,
+                        finish: self.finish_depth,
+                    });
+                }
+            }
+            JobType::Tapping(ref tap) => {
+                check_positive(&mut errors, "pitch", tap.pitch);
+                check_not_negative(&mut errors, "dwell", tap.dwell);
+                if tap.retract < self.start_cut {
+                    errors.push(JobError::RetractBelowHole {
+                        retract: tap.retract,
+                        start: self.start_cut,
+                    });
+                }
+                if let TapMode::Rigid = tap.mode {
+                    if !machine.controller.post_processor().rigid_tap_supported() {
+                        errors.push(JobError::RigidTapUnsupported);
+                    }
+                }
+            }
+            JobType::Drilling(ref drill) => {
+                check_positive(&mut errors, "feed", self.feed);
+                check_not_negative(&mut errors, "dwell", drill.dwell);
+                if let DrillCycle::Peck | DrillCycle::ChipBreak = drill.cycle {
+                    check_positive(&mut errors, "peck", drill.peck);
+                }
+                if drill.retract < self.start_cut {
+                    errors.push(JobError::RetractBelowHole {
+                        retract: drill.retract,
+                        start: self.start_cut,
+                    });
+                }
+            }
+            JobType::Threading(ref thread) => {
+                check_positive(&mut errors, "pitch", thread.pitch);
+                check_positive(&mut errors, "step", self.step);
+                check_positive(&mut errors, "thread_depth", thread.thread_depth);
+                if thread.degression.is_nan() || !DEGRESSION.contains(&thread.degression) {
+                    errors.push(JobError::DegressionOutOfRange(thread.degression));
+                }
+                check_not_negative(&mut errors, "spring_passes", thread.spring_passes as f32);
+                if thread.minor_diameter >= thread.major_diameter {
+                    errors.push(JobError::MinorAboveMajor {
+                        major: thread.major_diameter,
+                        minor: thread.minor_diameter,
+                    });
+                }
+                let angle = thread.infeed_angle;
+                if angle.is_nan() || !(0.0..60.0).contains(&angle) {
+                    errors.push(JobError::AngleOutOfRange { angle, max: 60.0 });
+                }
+                // Canned cycles cut to thread_depth, expanded passes to the minor
+                // diameter, so the two have to agree
+                let x_scale = machine.x_scale();
+                let expected = (thread.major_diameter - thread.minor_diameter) / x_scale;
+                let tolerance = if machine.units_inch { 0.0002 } else { 0.002 };
+                if (thread.thread_depth - expected).abs() > tolerance {
+                    errors.push(JobError::ThreadDepthMismatch {
+                        depth: thread.thread_depth,
+                        expected,
+                    });
+                }
+            }
+        }
+
+        corner_problems(&mut errors, self, machine);
+        schedule_problems(&mut errors, self);
+
+        match spindle_speed(self, machine) {
+            SpindleSpeed::Rpm(rpm) => check_positive(&mut errors, "rpm", rpm as f32),
+            SpindleSpeed::SurfaceSpeed { speed, max_rpm } => {
+                check_positive(&mut errors, "surface_speed", speed);
+                check_positive(&mut errors, "max_rpm", max_rpm as f32);
+                if !machine.controller.post_processor().css_supported() {
+                    errors.push(JobError::CssUnsupported);
+                }
+            }
+        }
+        errors
+    }
+
+    pub fn validate(&self, machine: &MachineParams) -> Result<(), JobError> {
+        let errors = self.problems(machine);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(JobError::Invalid(errors))
+        }
+    }
+}
+
+pub fn validate(jobs: &[JobParams], machine: &MachineParams) -> Result<(), JobError> {
+    let mut errors = vec![];
+    check_positive(&mut errors, "clearance x", machine.clearance.x);
+    check_positive(&mut errors, "clearance z", machine.clearance.z);
+    if let Some(ref limits) = machine.limits {
+        for &(axis, min, max) in &[
+            ('X', limits.x_min, limits.x_max),
+            ('Z', limits.z_min, limits.z_max),
+        ] {
+            if min.is_nan() || max.is_nan() || min >= max {
+                errors.push(JobError::TravelReversed { axis, min, max });
+            }
+        }
+    }
+    for (i, job) in jobs.iter().enumerate() {
+        for error in job.problems(machine) {
+            errors.push(JobError::InOperation {
+                index: i + 1,
+                error: Box::new(error),
+            });
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(JobError::Invalid(errors))
+    }
+}
+
+fn rough_cuts(strategy: &PassStrategy, depth: f32, step: f32) -> Vec<f32> {
+    // Depth removed from the stock after each roughing pass, the last one
+    // reaches the full depth. No pass is deeper than step.
+    if depth <= 0.0 {
+        return vec![];
+    }
+    let count = (depth / step - EPSILON).ceil().max(1.0) as usize;
+    match *strategy {
+        PassStrategy::Equal => (1..=count)
+            .map(|n| depth * n as f32 / count as f32)
+            .collect(),
+        PassStrategy::MaxStep => (1..=count)
+            .map(|n| depth - (count - n) as f32 * step)
+            .collect(),
+        PassStrategy::Degressive(degression) => {
+            // Passes shrink from a full step to MIN_CHIP of one over the cut,
+            // later for low degressions, 1.0 keeps full steps. Passes are
+            // added until they reach depth, then scaled down to end on it.
+            let pass = |n: usize, count: usize| {
+                let x = n as f32 / (count - 1).max(1) as f32;
+                step * (MIN_CHIP + (1.0 - MIN_CHIP) * (1.0 - x).powf(degression - 1.0))
+            };
+            let mut count = count;
+            while (0..count).map(|n| pass(n, count)).sum::<f32>() < depth - EPSILON {
+                count += 1;
+            }
+            let mut reached = 0.0;
+            let cuts: Vec<f32> = (0..count)
+                .map(|n| {
+                    reached += pass(n, count);
+                    reached
+                })
+                .collect();
+            cuts.iter().map(|cut| cut * depth / reached).collect()
+        }
+    }
 }
 
 fn calculate_pass_depths(job: &JobParams) -> Vec<f32> {
-    // Create vec with [finish, finish + finish_step]
-    // Use while loop and add step size to previous val until previous val > start
-    let mut last = job.finish_depth + job.finish_step;
-    let mut passes = vec![job.finish_depth, last];
-    while last < job.start_depth {
-        last += job.step;
+    // Create vec with [finish (and its spring passes), finish + each finishing
+    // depth working outwards], then the roughing passes out to the stock
+    let schedule = job.finish_schedule();
+    let mut last = job.finish_depth;
+    let mut passes = vec![last; schedule.spring_passes.max(0) as usize + 1];
+    for depth in schedule.depths.iter().rev() {
+        last += depth;
         passes.push(last);
     }
+    let rough = rough_cuts(&job.pass_strategy, job.start_depth - last, job.step);
+    if rough.is_empty() {
+        passes.pop(); // Finishing allowance is all there is, nothing to rough
+    }
+    for cut in rough.iter().rev().skip(1) {
+        passes.push(job.start_depth - cut);
+    }
     passes
 }
 
-fn generate_z_cut(pass_depths: Vec<f32>, job: &JobParams) -> String {
-    let mut code = String::new();
+fn finishing_pass(
+    approach: Toolpath,
+    start: (f32, f32),
+    cut: Toolpath,
+    tool_side: (f32, f32),
+    job: &JobParams,
+    machine: &MachineParams,
+) -> Toolpath {
+    // approach ends at start = (x, z), the last approach move is the lead-in.
+    // cut holds the feed moves along the finished surface, the move after it
+    // is the lead-out.
+    let mut path = Toolpath::new();
+    let direction = match cut.moves.first() {
+        Some(Motion::Feed { x, z, .. }) => (
+            z.map_or(0.0, |z| z - start.1),
+            x.map_or(0.0, |x| x - start.0),
+        ),
+        _ => (0.0, 0.0),
+    };
+    let side = side_for(direction, tool_side);
+    let number = job.tool.unwrap_or(machine.tool_num);
+    match (&machine.nose_compensation, machine.tool(number)) {
+        (NoseCompensation::Controller, _) => {
+            let mut approach = approach.moves;
+            let lead_in = approach.pop();
+            path.moves.append(&mut approach);
+            path.push(Motion::NoseCompensation(Some(side)));
+            path.moves.extend(lead_in);
+            path.append(cut);
+            path.push(Motion::NoseCompensation(None));
+        }
+        (NoseCompensation::Computed, Some(tool)) => {
+            let x_scale = machine.x_scale();
+            let (offset_start, moves) = offset_cut(
+                start,
+                &cut.moves,
+                side,
+                tool.nose_radius,
+                tool.orientation,
+                x_scale,
+            );
+            // Move the approach onto the compensated start
+            let retarget = |value: Option<f32>, from: f32, to: f32| {
+                value.map(|v| if v == from { to } else { v })
+            };
+            for motion in approach.moves {
+                path.push(match motion {
+                    Motion::Rapid { x, z } => Motion::Rapid {
+                        x: retarget(x, start.0, offset_start.0),
+                        z: retarget(z, start.1, offset_start.1),
+                    },
+                    Motion::Feed { x, z, feed } => Motion::Feed {
+                        x: retarget(x, start.0, offset_start.0),
+                        z: retarget(z, start.1, offset_start.1),
+                        feed,
+                    },
+                    motion => motion,
+                });
+            }
+            path.moves.extend(moves);
+        }
+        _ => {
+            path.append(approach);
+            path.append(cut);
+        }
+    }
+    path
+}
+
+fn finish_spindle(path: &mut Toolpath, job: &JobParams, machine: &MachineParams) {
+    // Speed up for the finishing pass, the next operation changes back only
+    // if it needs to
+    let speed = match (job.finish_speed, spindle_speed(job, machine)) {
+        (None, _) => return,
+        (Some(rpm), SpindleSpeed::Rpm(_)) => SpindleSpeed::Rpm(rpm as i32),
+        (Some(speed), SpindleSpeed::SurfaceSpeed { max_rpm, .. }) => {
+            SpindleSpeed::SurfaceSpeed { speed, max_rpm }
+        }
+    };
+    if speed != spindle_speed(job, machine) {
+        path.push(Motion::Spindle {
+            speed,
+            cw: machine.spindle_cw,
+        });
+    }
+}
+
+fn corner_move(
+    path: &mut Toolpath,
+    corner: Corner,
+    to: (f32, f32),
+    centre: (f32, f32),
+    cw: bool,
+    feed: f32,
+) {
+    // to is (x, z), centre is the radial (i, k) offset of a radius from the
+    // current position
+    match corner {
+        Corner::Chamfer { .. } => path.feed_xz(to.0, to.1, feed),
+        Corner::Radius(_) => path.push(Motion::Arc {
+            cw,
+            x: to.0,
+            z: to.1,
+            i: centre.0,
+            k: centre.1,
+            feed,
+        }),
+    }
+}
+
+fn pass_retract(
+    path: &mut Toolpath,
+    job: &JobParams,
+    machine: &MachineParams,
+    at: (f32, f32),
+    previous: f32,
+    in_x: bool,
+) {
+    // Leave the end of a roughing pass at (x, z). Passes cut along Z retract
+    // in X, in_x false is facing. previous is the level of the last pass, the
+    // stock for the first one.
+    let x_scale = machine.x_scale();
+    let out = if job.finish_depth < job.start_depth {
+        1.0
+    } else {
+        -1.0 // Boring, retract towards the centreline
+    };
+    let back = if job.length > 0.0 { 1.0 } else { -1.0 }; // Towards the start of the cut
+    let clear = if in_x {
+        machine.clearance.x
+    } else {
+        machine.clearance.z
+    };
+    let feed_to = |path: &mut Toolpath, level: f32| {
+        if in_x {
+            path.feed_x(level, job.feed);
+        } else {
+            path.feed_z(level, job.feed);
+        }
+    };
+    match machine.retract {
+        RetractMode::Stock => feed_to(path, job.start_depth + out * clear),
+        RetractMode::Diameter(x) => {
+            // Facing is clear of the face in Z by now, so any X is safe
+            feed_to(path, job.start_depth + out * clear);
+            path.rapid_x(x);
+        }
+        RetractMode::Previous => feed_to(path, previous + out * clear),
+        RetractMode::Diagonal => {
+            if in_x {
+                path.feed_xz(at.0 + out * clear, at.1 + back * clear / x_scale, job.feed);
+            } else {
+                path.feed_xz(at.0 + back * clear * x_scale, at.1 + out * clear, job.feed);
+            }
+        }
+    }
+}
+
+fn rough_end(job: &JobParams, machine: &MachineParams, level: f32, in_z: bool) -> f32 {
+    // Where a roughing pass at level (X cutting in Z, Z for facing) meets
+    // the end corner, keeping the finishing allowance off it. Passes above
+    // the corner run to the end of the cut.
+    let end = job.start_cut - job.length;
+    let corner = match job.end_corner {
+        Some(corner) => corner,
+        None => return end,
+    };
+    let x_scale = machine.x_scale();
+    let (level_scale, along_scale) = if in_z { (x_scale, 1.0) } else { (1.0, x_scale) };
+    let dir = if job.length > 0.0 { -1.0 } else { 1.0 };
+    let allowance: f32 = job.finish_schedule().depths.iter().sum();
+    let height = ((level - job.finish_depth - allowance) / level_scale).max(0.0);
+    let (along, across) = corner.legs();
+    if height >= across {
+        return end;
+    }
+    let short = match corner {
+        Corner::Chamfer { .. } => along * (1.0 - height / across),
+        Corner::Radius(radius) => radius - (radius * radius - (radius - height).powi(2)).sqrt(),
+    };
+    end - dir * short * along_scale
+}
+
+fn z_finish_cut(job: &JobParams, machine: &MachineParams, i: f32, feed: f32) -> (f32, Toolpath) {
+    // Feed moves along the finished surface of a cut in Z at X i, with its
+    // corners. Returns the X to come in at, below a start corner. The feed
+    // out cuts the shoulder, so it is part of the finished profile.
+    let x_scale = machine.x_scale();
+    let dir = if job.length > 0.0 { -1.0 } else { 1.0 }; // Z direction of the cut
+    let end_z = job.start_cut - job.length;
+    let mut cut = Toolpath::new();
+    let start_x = match job.start_corner {
+        Some(corner) => {
+            // Come in below the corner and feed up to the face
+            let (along, across) = corner.legs();
+            cut.feed_z(job.start_cut, feed);
+            corner_move(
+                &mut cut,
+                corner,
+                (i, job.start_cut + dir * along),
+                (0.0, dir * along),
+                dir > 0.0,
+                feed,
+            );
+            i - across * x_scale
+        }
+        None => i,
+    };
+    match job.end_corner {
+        Some(corner) => {
+            let (along, across) = corner.legs();
+            cut.feed_z(end_z - dir * along, feed);
+            corner_move(
+                &mut cut,
+                corner,
+                (i + across * x_scale, end_z),
+                (across, 0.0),
+                dir < 0.0,
+                feed,
+            );
+        }
+        None => cut.feed_z(end_z, feed),
+    }
+    cut.feed_x(job.start_depth + machine.clearance.x, feed);
+    (start_x, cut)
+}
+
+fn generate_z_cut(pass_depths: Vec<f32>, job: &JobParams, machine: &MachineParams) -> Toolpath {
+    let mut path = Toolpath::new();
     let mut pass_depths = pass_depths;
     pass_depths.reverse();
+    let finish = pass_depths.split_off(pass_depths.len() - job.finishing_passes());
 
+    let mut previous = job.start_depth;
     for i in pass_depths {
-        code += &format!("G0 Z{:.3} \n   X{:.3}\n", job.start_cut + CLEARANCE, i); // Rapid to start + clearance in Z
-        code += &format!(
-            "G1 Z{:.3} F{} \n   X{:.3} \n",
-            job.start_cut - job.length,
-            job.feed,
-            job.start_depth + CLEARANCE
-        ); // Linear cut in Z axis + feed out
+        let end_z = rough_end(job, machine, i, true);
+        path.rapid_z(job.start_cut + machine.clearance.z); // Rapid to start + clearance in Z
+        path.rapid_x(i);
+        path.feed_z(end_z, job.feed); // Linear cut in Z axis
+        pass_retract(&mut path, job, machine, (i, end_z), previous, true);
+        previous = i;
+    }
+    let feed = job.finishing_feed();
+    finish_spindle(&mut path, job, machine);
+    for i in finish {
+        let (start_x, cut) = z_finish_cut(job, machine, i, feed);
+        let mut approach = Toolpath::new();
+        approach.rapid_z(job.start_cut + machine.clearance.z);
+        approach.rapid_x(start_x);
+        path.append(finishing_pass(
+            approach,
+            (start_x, job.start_cut + machine.clearance.z),
+            cut,
+            (0.0, 1.0),
+            job,
+            machine,
+        ));
+    }
+    path.rapid_z(job.start_cut + machine.clearance.z); // Lead out
+    path
+}
+
+fn x_finish_cut(job: &JobParams, machine: &MachineParams, i: f32, feed: f32) -> (f32, Toolpath) {
+    // Feed moves along the finished surface of a cut in X at Z i, with its
+    // corners. Returns the Z to feed down the OD to, above a start corner.
+    let x_scale = machine.x_scale();
+    let dir = if job.length > 0.0 { -1.0 } else { 1.0 }; // X direction of the cut
+    let end_x = job.start_cut - job.length;
+    let mut cut = Toolpath::new();
+    let start_z = match job.start_corner {
+        Some(corner) => {
+            let (along, across) = corner.legs();
+            corner_move(
+                &mut cut,
+                corner,
+                (job.start_cut + dir * along * x_scale, i),
+                (dir * along, 0.0),
+                dir < 0.0,
+                feed,
+            );
+            i - across
+        }
+        None => i,
+    };
+    match job.end_corner {
+        Some(corner) => {
+            let (along, across) = corner.legs();
+            cut.feed_x(end_x - dir * along * x_scale, feed);
+            corner_move(
+                &mut cut,
+                corner,
+                (end_x, i + across),
+                (0.0, across),
+                dir > 0.0,
+                feed,
+            );
+        }
+        None => cut.feed_x(end_x, feed),
     }
-    code
+    (start_z, cut)
 }
 
-fn generate_x_cut(pass_depths: Vec<f32>, job: &JobParams) -> String {
+fn generate_x_cut(pass_depths: Vec<f32>, job: &JobParams, machine: &MachineParams) -> Toolpath {
     // Pass depths are in Z axis
-    let mut code = String::new();
+    let mut path = Toolpath::new();
     let mut pass_depths = pass_depths;
     pass_depths.reverse();
+    let finish = pass_depths.split_off(pass_depths.len() - job.finishing_passes());
 
+    let mut previous = job.start_depth;
     for i in pass_depths {
-        code += &format!("G0 X{:.3}\n   Z{:.3}\n", job.start_cut, i + CLEARANCE); // Rapid to start + clearance in Z
-        code += &format!(
-            "G1 Z{:.3} F{}\n   X{:.3}\n   Z{:.3} \n",
-            i,
-            job.feed,
-            job.start_cut - job.length,
-            job.start_depth + CLEARANCE
-        ); // Linear cut in X axis + feed out
+        let end_x = rough_end(job, machine, i, false);
+        path.rapid_x(job.start_cut); // Rapid to start + clearance in Z
+        path.rapid_z(i + machine.clearance.z);
+        path.feed_z(i, job.feed);
+        path.feed_x(end_x, job.feed); // Linear cut in X axis
+        pass_retract(&mut path, job, machine, (end_x, i), previous, false);
+        previous = i;
     }
-    code
+    let feed = job.finishing_feed();
+    finish_spindle(&mut path, job, machine);
+    for i in finish {
+        let (start_z, cut) = x_finish_cut(job, machine, i, feed);
+        let mut approach = Toolpath::new();
+        approach.rapid_x(job.start_cut);
+        approach.rapid_z(i + machine.clearance.z);
+        approach.feed_z(start_z, feed);
+        path.append(finishing_pass(
+            approach,
+            (job.start_cut, start_z),
+            cut,
+            (1.0, 0.0),
+            job,
+            machine,
+        ));
+        path.feed_z(job.start_depth + machine.clearance.z, feed); // Lead out
+    }
+    path
 }
 
-fn turning_generation(job: &JobParams) -> String {
+fn turning_generation(job: &JobParams, machine: &MachineParams) -> Toolpath {
     // Pass depths correspond to X, cut in Z
     let passes = calculate_pass_depths(job);
-    generate_z_cut(passes, job)
+    generate_z_cut(passes, job, machine)
 }
 
-// fn threading_generation(job: &JobParams) -> String {
-//
-// }
+fn taper_generation(job: &JobParams, taper: &TaperParams, machine: &MachineParams) -> Toolpath {
+    // Passes run parallel to the cone, each one offset by a step in X and
+    // clipped to the stock so no pass cuts air above the bar
+    let mut path = Toolpath::new();
+    let start_z = job.start_cut + machine.clearance.z;
+    let end_z = job.start_cut - job.length;
+    let clear_x = job.start_depth + machine.clearance.x;
+    let end_x = taper.end_x(job, machine.radius_mode);
+    let slope = (end_x - job.finish_depth) / (end_z - job.start_cut); // X per Z
+    let smallest = job.finish_depth.min(end_x);
 
-// fn boring_generation(job: JobParams, machine: MachineParams) -> String {
-//     // Pass depths correspond to X, cut in Z
-//     // Use G85?, change X between cycles
-//     // Use G89
-//     let passes = calculate_pass_depths(job);
-// }
+    // Offsets of the roughing passes follow the pass strategy like the
+    // levels of a straight turning cut
+    let allowance = if job.finish_step > 0.0 {
+        job.finish_step
+    } else {
+        job.step
+    };
+    let depth = job.start_depth - smallest - allowance;
+    let rough = rough_cuts(&job.pass_strategy, depth, job.step);
+    let mut offsets = vec![0.0];
+    if !rough.is_empty() {
+        offsets.push(allowance);
+    }
+    for cut in rough.iter().rev().skip(1) {
+        offsets.push(allowance + depth - cut);
+    }
+    offsets.reverse();
+
+    for offset in offsets {
+        let x_at = |z: f32| job.finish_depth + offset + slope * (z - job.start_cut);
+        let z_at_clear = job.start_cut + (clear_x - job.finish_depth - offset) / slope;
+        let (from_x, to_x) = (x_at(start_z), x_at(end_z));
+        let from = if from_x <= clear_x {
+            (from_x, start_z)
+        } else {
+            (clear_x, z_at_clear)
+        };
+        let (to, shoulder) = if to_x <= clear_x {
+            ((to_x, end_z), true)
+        } else {
+            ((clear_x, z_at_clear), false)
+        };
+
+        let mut approach = Toolpath::new();
+        approach.rapid_z(from.1);
+        approach.rapid_x(from.0);
+        let feed = if offset == 0.0 {
+            job.finishing_feed()
+        } else {
+            job.feed
+        };
+        let mut cut = Toolpath::new();
+        cut.feed_xz(to.0, to.1, feed);
+        if shoulder {
+            cut.feed_x(clear_x, feed);
+        }
+        if offset == 0.0 {
+            finish_spindle(&mut path, job, machine);
+            path.append(finishing_pass(
+                approach,
+                from,
+                cut,
+                (0.0, 1.0),
+                job,
+                machine,
+            ));
+            path.rapid_z(start_z); // Lead out
+        } else {
+            path.append(approach);
+            path.append(cut);
+        }
+    }
+    path
+}
+
+fn profile_generation(
+    job: &JobParams,
+    profile: &ProfileParams,
+    machine: &MachineParams,
+    number: u32,
+) -> Toolpath {
+    // G71 layers down to finish_step from the contour, then G70 along it.
+    // The finishing pass is written out when nose compensation is used so
+    // it gets the same lead-in and offsets as the other operations. The
+    // contour carries the finishing feed, the G71 block the roughing feed.
+    let mut path = Toolpath::new();
+    let cycle_profile = Profile {
+        number,
+        start_x: job.start_depth + machine.clearance.x,
+        start_z: profile.start_z + machine.clearance.z,
+        first_x: profile.start_x,
+        first_z: profile.start_z,
+        contour: profile.moves(job.finishing_feed(), machine.radius_mode),
+        feed: job.feed,
+        finish_feed: job.finishing_feed(),
+        radius_mode: machine.radius_mode,
+    };
+    let rough = Cycle::Rough {
+        profile: cycle_profile.clone(),
+        step: job.step,
+        allowance: job.finish_step,
+        retract: ROUGH_RETRACT,
+    };
+    path.rapid_x(cycle_profile.start_x);
+    path.rapid_z(cycle_profile.start_z);
+    if machine.canned_cycles {
+        path.push(Motion::Cycle(rough));
+    } else {
+        path.append(rough.expand());
+    }
+
+    match machine.nose_compensation {
+        NoseCompensation::Off if machine.canned_cycles => {
+            finish_spindle(&mut path, job, machine);
+            path.push(Motion::Cycle(Cycle::Finish(cycle_profile)));
+        }
+        _ => {
+            // Both cycles leave the tool at the start point
+            finish_spindle(&mut path, job, machine);
+            let mut approach = Toolpath::new();
+            approach.rapid_x(cycle_profile.first_x);
+            let mut cut = cycle_profile.path();
+            cut.moves.remove(0); // Approach X is the lead-in
+            path.append(finishing_pass(
+                approach,
+                (cycle_profile.first_x, cycle_profile.start_z),
+                cut,
+                (0.0, 1.0),
+                job,
+                machine,
+            ));
+            path.rapid_z(cycle_profile.start_z); // Lead out
+        }
+    }
+    path
+}
+
+fn generate_plunge(
+    path: &mut Toolpath,
+    in_z: bool,
+    top: f32,
+    bottom: f32,
+    peck: f32,
+    retract: f32,
+    feed: (f32, Option<(f32, f32)>),
+) {
+    // Feed in X (or Z for face grooves) from top down to bottom. With a peck
+    // the tool backs off by retract after each one. feed is the feed rate
+    // and, for parting, the level below which the slow feed takes over.
+    let feed_to = |path: &mut Toolpath, level: f32, rate: f32| {
+        if in_z {
+            path.feed_z(level, rate);
+        } else {
+            path.feed_x(level, rate);
+        }
+    };
+    let (rate, slow) = feed;
+    let mut at = top;
+    while at > bottom {
+        let next = if peck > 0.0 {
+            (at - peck).max(bottom)
+        } else {
+            bottom
+        };
+        match slow {
+            Some((level, slow_rate)) if next < level => {
+                // Split the plunge where it crosses into the slow zone
+                if at > level {
+                    feed_to(path, level, rate);
+                }
+                feed_to(path, next, slow_rate);
+            }
+            _ => feed_to(path, next, rate),
+        }
+        if next > bottom && retract > 0.0 {
+            if in_z {
+                path.rapid_z(next + retract);
+            } else {
+                path.rapid_x(next + retract);
+            }
+        }
+        at = next;
+    }
+}
+
+fn grooving_generation(
+    job: &JobParams,
+    groove: &GrooveParams,
+    machine: &MachineParams,
+) -> Toolpath {
+    // Plunges step towards the chuck by tool_width - overlap, the last one
+    // sits on the far wall. finish_step is left on the floor and taken off
+    // in one pass across it.
+    let mut path = Toolpath::new();
+    let clear_x = job.start_depth + machine.clearance.x;
+    let first_z = job.start_cut;
+    let last_z = job.start_cut - groove.width + groove.tool_width;
+    let floor_x = job.finish_depth + job.finish_step;
+
+    let mut plunges = vec![];
+    let mut z = first_z;
+    while z > last_z {
+        plunges.push(z);
+        z -= groove.tool_width - groove.overlap;
+    }
+    plunges.push(last_z);
+
+    path.rapid_x(clear_x);
+    for z in plunges {
+        path.rapid_z(z);
+        path.rapid_x(job.start_depth + groove.retract);
+        generate_plunge(
+            &mut path,
+            false,
+            job.start_depth + groove.retract,
+            floor_x,
+            groove.peck,
+            groove.retract,
+            (job.feed, None),
+        );
+        path.rapid_x(clear_x);
+    }
+    if job.finish_step > 0.0 {
+        let feed = job.finishing_feed();
+        finish_spindle(&mut path, job, machine);
+        path.rapid_z(first_z);
+        path.rapid_x(job.start_depth + groove.retract);
+        path.feed_x(job.finish_depth, feed);
+        path.feed_z(last_z, feed);
+        path.feed_x(clear_x, feed);
+    }
+    path
+}
+
+fn face_grooving_generation(
+    job: &JobParams,
+    groove: &FaceGrooveParams,
+    machine: &MachineParams,
+) -> Toolpath {
+    // Plunges step in from the outer diameter by tool_width - overlap, the
+    // last one puts the blade's inner corner on the inner diameter. The
+    // floor finish runs inwards across the bottom.
+    let mut path = Toolpath::new();
+    let x_scale = machine.x_scale();
+    let clear_z = job.start_depth + machine.clearance.z;
+    let top_z = job.start_depth + groove.retract;
+    let first_x = groove.outer_diameter;
+    let last_x = groove.inner_diameter + groove.tool_width * x_scale;
+    let floor_z = job.finish_depth + job.finish_step;
+
+    let mut plunges = vec![];
+    let mut x = first_x;
+    while x > last_x {
+        plunges.push(x);
+        x -= (groove.tool_width - groove.overlap) * x_scale;
+    }
+    plunges.push(last_x);
+
+    path.rapid_z(clear_z);
+    for x in plunges {
+        path.rapid_x(x);
+        path.rapid_z(top_z);
+        generate_plunge(
+            &mut path,
+            true,
+            top_z,
+            floor_z,
+            groove.peck,
+            groove.retract,
+            (job.feed, None),
+        );
+        path.rapid_z(clear_z);
+    }
+    if job.finish_step > 0.0 {
+        let feed = job.finishing_feed();
+        finish_spindle(&mut path, job, machine);
+        path.rapid_x(first_x);
+        path.rapid_z(top_z);
+        path.feed_z(job.finish_depth, feed);
+        path.feed_x(last_x, feed);
+        path.feed_z(clear_z, feed);
+    }
+    path
+}
+
+fn parting_generation(job: &JobParams, part: &PartParams, machine: &MachineParams) -> Toolpath {
+    // The part is on the tailstock side of start_cut. A chamfer is cut with
+    // the blade's tailstock corner after a plunge to the chamfer depth.
+    let mut path = Toolpath::new();
+    let x_scale = machine.x_scale();
+    let clear_x = job.start_depth + machine.clearance.x;
+    let top_x = job.start_depth + part.retract;
+
+    path.rapid_x(clear_x);
+    path.rapid_z(job.start_cut);
+    let from_x = if part.chamfer > 0.0 {
+        let chamfer_x = job.start_depth - part.chamfer * x_scale;
+        path.rapid_x(top_x);
+        path.feed_x(chamfer_x, job.feed);
+        path.rapid_x(clear_x);
+        path.rapid_z(job.start_cut + part.chamfer);
+        path.rapid_x(top_x);
+        path.feed_x(job.start_depth, job.feed);
+        path.feed_xz(chamfer_x, job.start_cut, job.feed);
+        chamfer_x
+    } else {
+        path.rapid_x(top_x);
+        top_x
+    };
+    generate_plunge(
+        &mut path,
+        false,
+        from_x,
+        job.finish_depth,
+        part.peck,
+        part.retract,
+        (job.feed, Some((part.slow_diameter, part.slow_feed))),
+    );
+    path.rapid_x(clear_x);
+    path
+}
 
-fn facing_generation(job: &JobParams) -> String {
+fn threading_generation(
+    job: &JobParams,
+    thread: &ThreadParams,
+    machine: &MachineParams,
+) -> Toolpath {
+    // Drive line sits the X clearance outside the crest. A finishing schedule
+    // replaces the thread's own spring passes.
+    let mut path = Toolpath::new();
+    let (finish_depths, spring_passes) = match job.finishing {
+        Some(ref schedule) => (schedule.depths.clone(), schedule.spring_passes),
+        None => (vec![], thread.spring_passes),
+    };
+    let cycle = Cycle::Thread {
+        drive_x: thread.major_diameter + machine.clearance.x,
+        start_z: job.start_cut + machine.clearance.z,
+        z: job.start_cut - job.length,
+        crest_x: thread.major_diameter,
+        root_x: thread.minor_diameter,
+        pitch: thread.pitch,
+        first_depth: job.step,
+        depth: thread.thread_depth,
+        infeed_angle: thread.infeed_angle,
+        finish_depths,
+        spring_passes,
+        degression: thread.degression,
+    };
+    path.rapid_x(thread.major_diameter + machine.clearance.x);
+    path.rapid_z(job.start_cut + machine.clearance.z);
+    if machine.canned_cycles {
+        path.push(Motion::Cycle(cycle));
+    } else {
+        path.append(cycle.expand());
+    }
+    path
+}
+
+fn calculate_bore_depths(job: &JobParams) -> Vec<f32> {
+    // Mirror of calculate_pass_depths, the diameter grows from the pilot hole
+    let schedule = job.finish_schedule();
+    let mut last = job.finish_depth;
+    let mut passes = vec![last; schedule.spring_passes.max(0) as usize + 1];
+    for depth in schedule.depths.iter().rev() {
+        last -= depth;
+        passes.push(last);
+    }
+    let rough = rough_cuts(&job.pass_strategy, last - job.start_depth, job.step);
+    if rough.is_empty() {
+        passes.pop();
+    }
+    for cut in rough.iter().rev().skip(1) {
+        passes.push(job.start_depth + cut);
+    }
+    passes
+}
+
+fn bore_exit(
+    path: &mut Toolpath,
+    job: &JobParams,
+    boring: &BoringParams,
+    machine: &MachineParams,
+    pass: f32,
+    previous: f32,
+) {
+    let retract_x = job.start_depth - machine.clearance.x;
+    let retract_z = job.start_cut + machine.clearance.z;
+    match boring.exit {
+        BoreExit::Retract => {
+            let at = (pass, job.start_cut - job.length);
+            pass_retract(path, job, machine, at, previous, true);
+            path.rapid_z(retract_z);
+        }
+        BoreExit::FeedOut => {
+            path.feed_z(retract_z, job.feed);
+            path.rapid_x(retract_x);
+        }
+        BoreExit::Dwell(seconds) => {
+            path.push(Motion::Dwell(seconds));
+            path.feed_z(retract_z, job.feed);
+            path.rapid_x(retract_x);
+        }
+    }
+}
+
+fn generate_bore_cut(
+    pass_depths: Vec<f32>,
+    job: &JobParams,
+    boring: &BoringParams,
+    machine: &MachineParams,
+) -> Toolpath {
+    // Retracts stay inside the pilot hole, away from the cut wall
+    let mut path = Toolpath::new();
+    let mut pass_depths = pass_depths;
+    pass_depths.reverse();
+    let finish = pass_depths.split_off(pass_depths.len() - job.finishing_passes());
+    let retract_z = job.start_cut + machine.clearance.z;
+
+    let mut previous = job.start_depth;
+    for i in pass_depths {
+        path.rapid_z(retract_z); // Rapid to start + clearance in Z
+        path.rapid_x(i);
+        path.feed_z(job.start_cut - job.length, job.feed); // Linear cut in Z axis
+        bore_exit(&mut path, job, boring, machine, i, previous);
+        previous = i;
+    }
+    let feed = job.finishing_feed();
+    finish_spindle(&mut path, job, machine);
+    let retract = matches!(boring.exit, BoreExit::Retract);
+    for i in finish {
+        let mut approach = Toolpath::new();
+        approach.rapid_z(retract_z);
+        approach.rapid_x(i);
+        let mut cut = Toolpath::new();
+        cut.feed_z(job.start_cut - job.length, feed);
+        if retract {
+            // Stepping off cuts the bottom shoulder
+            cut.feed_x(job.start_depth - machine.clearance.x, feed);
+        }
+        path.append(finishing_pass(
+            approach,
+            (i, retract_z),
+            cut,
+            (0.0, -1.0),
+            job,
+            machine,
+        ));
+        if !retract {
+            bore_exit(&mut path, job, boring, machine, i, i);
+        }
+    }
+    if retract {
+        path.rapid_z(retract_z);
+    }
+    path
+}
+
+fn boring_generation(job: &JobParams, boring: &BoringParams, machine: &MachineParams) -> Toolpath {
+    // Pass depths correspond to X, cut in Z
+    let passes = calculate_bore_depths(job);
+    generate_bore_cut(passes, job, boring, machine)
+}
+
+fn drilling_generation(job: &JobParams, drill: &DrillParams, machine: &MachineParams) -> Toolpath {
+    // Drilling is on the spindle centreline, cut in Z
+    let mut path = Toolpath::new();
+    let cycle = Cycle::Drill {
+        kind: drill.cycle,
+        start_z: job.start_cut,
+        z: job.start_cut - job.length,
+        retract: drill.retract,
+        peck: drill.peck,
+        dwell: drill.dwell,
+        feed: job.feed,
+    };
+    path.rapid_x(0.0);
+    path.rapid_z(drill.retract);
+    if machine.canned_cycles {
+        path.push(Motion::Cycle(cycle));
+    } else {
+        path.append(cycle.expand());
+    }
+    path
+}
+
+fn tapping_generation(job: &JobParams, tap: &TapParams, machine: &MachineParams) -> Toolpath {
+    // Floating holders take up the difference between the feed and the
+    // spindle, so feed per minute matched to the pitch is enough
+    let mut path = Toolpath::new();
+    let rpm = job.rpm.unwrap_or(machine.rpm);
+    let z = job.start_cut - job.length;
+    path.rapid_x(0.0);
+    path.rapid_z(tap.retract);
+    match tap.mode {
+        TapMode::Rigid => path.push(Motion::RigidTap {
+            z,
+            pitch: tap.pitch,
+        }),
+        TapMode::Floating => {
+            let feed = tap.pitch * rpm as f32;
+            let speed = SpindleSpeed::Rpm(rpm);
+            path.feed_z(z, feed);
+            if tap.dwell > 0.0 {
+                path.push(Motion::Dwell(tap.dwell));
+            }
+            path.push(Motion::Spindle {
+                speed,
+                cw: !machine.spindle_cw,
+            });
+            path.feed_z(tap.retract, feed);
+            path.push(Motion::Spindle {
+                speed,
+                cw: machine.spindle_cw,
+            });
+        }
+    }
+    path
+}
+
+fn facing_generation(job: &JobParams, machine: &MachineParams) -> Toolpath {
     // Pass depths correspond to Z, cut in X
     // Do the following outside before the data is passed by referenceL
     // let mut job1 = job.clone();
     // job1.length = job1.start_cut + 0.2;
     let passes = calculate_pass_depths(job);
-    generate_x_cut(passes, job)
+    generate_x_cut(passes, job, machine)
 }
 
-fn face_boring_generation(job: &JobParams) -> String {
+fn face_boring_generation(job: &JobParams, machine: &MachineParams) -> Toolpath {
     // Pass depths correspond to Z, cut in X
     let passes = calculate_pass_depths(job);
-    generate_x_cut(passes, job)
+    generate_x_cut(passes, job, machine)
 }
 
-fn machine_settings_generation(machine: &MachineParams) -> String {
+fn machine_settings_generation(machine: &MachineParams) -> Toolpath {
     // Set up machine options (units, diameter/radius etc.)
-    let units: &str = if machine.units_inch { "G20" } else { "G21" };
-    let lathe_mode: &str = if machine.radius_mode { "G8" } else { "G7" };
-    let tool_code = format!("M6 T{} G43\n", machine.tool_num);
-    let dir: &str = if machine.spindle_cw { "M3" } else { "M4" };
-    let spindle_code: String = format!("G97 S{} {}", machine.rpm, dir);
-
-    // let spindle_code: String = match surface_mode {
-    //     true => format!("G96 D{} S{} {}\n", rpm, surface_speed, dir),
-    //     false => format!("G97 S{} {}\n", rpm, dir),
-    //     _ => String::from("ERROR"),
-    // };
-
-    // G64 - path blending, G18 - Plane select
-    let machine_code = format!(
-        "G90 {} G64 G18 {}\n{}{}\n",
-        units, lathe_mode, tool_code, spindle_code
-    );
-    machine_code
+    let mut path = Toolpath::new();
+    path.push(Motion::Setup {
+        units_inch: machine.units_inch,
+        radius_mode: machine.radius_mode,
+        feed_per_rev: false,
+    });
+    path
+}
+
+fn tool_change_position_generation(machine: &MachineParams) -> Toolpath {
+    // Safe position at the start and end of the program
+    let mut path = Toolpath::new();
+    match machine.tool_change {
+        Some(ToolChangePosition::G28) => path.push(Motion::Reference { second: false }),
+        Some(ToolChangePosition::G30) => path.push(Motion::Reference { second: true }),
+        Some(ToolChangePosition::Position { x, z }) => {
+            path.rapid_x(x);
+            path.rapid_z(z);
+        }
+        None => (),
+    }
+    path
+}
+
+struct ActiveTool {
+    tool: Option<i32>,
+    speed: Option<SpindleSpeed>,
 }
 
-pub fn generate_gcode(job: &JobParams, machine: &MachineParams) -> String {
-    let cut_code: String = match job.job_type {
-        JobType::Boring => "ERROR: Boring functionality not yet available".to_string(), //boring_generation(job, machine),
-        JobType::Facing => facing_generation(job),
-        JobType::FaceBoring => face_boring_generation(job),
-        JobType::Turning => turning_generation(job),
-        JobType::Drilling => "ERROR: Drilling functionality not yet available".to_string(),
+fn clear_of_work(jobs: &[JobParams], machine: &MachineParams) -> Toolpath {
+    // Safe position between operations. Every operation ends outside its own
+    // work in X or in front of it in Z, so going out in X past all of them
+    // and then in front of all of them in Z can't cut anything.
+    if machine.tool_change.is_some() {
+        return tool_change_position_generation(machine);
+    }
+    let (mut x, mut z) = (0.0f32, 0.0f32);
+    for job in jobs {
+        let (job_x, job_z) = match job.job_type {
+            JobType::Facing | JobType::FaceBoring => (
+                job.start_cut.max(job.start_cut - job.length),
+                job.start_depth,
+            ),
+            JobType::FaceGrooving(ref groove) => (groove.outer_diameter, job.start_depth),
+            JobType::Drilling(ref drill) => (0.0, drill.retract),
+            JobType::Tapping(ref tap) => (0.0, tap.retract),
+            JobType::Profile(ref profile) => (job.start_depth, profile.start_z),
+            _ => (
+                job.start_depth.max(job.finish_depth),
+                job.start_cut.max(job.start_cut - job.length),
+            ),
+        };
+        x = x.max(job_x);
+        z = z.max(job_z);
+    }
+    let mut path = Toolpath::new();
+    path.rapid_x(x + machine.clearance.x);
+    path.rapid_z(z + machine.clearance.z);
+    path
+}
+
+fn spindle_speed(job: &JobParams, machine: &MachineParams) -> SpindleSpeed {
+    // Threading and centreline drilling need a fixed rpm: CSS would run the
+    // spindle up to max_rpm at X0 or lose sync between thread passes
+    let rpm = job.rpm.unwrap_or(machine.rpm);
+    match (&machine.spindle_mode, &job.job_type) {
+        (_, JobType::Threading(_)) | (_, JobType::Drilling(_)) | (_, JobType::Tapping(_)) => {
+            SpindleSpeed::Rpm(rpm)
+        }
+        (SpindleMode::Rpm, _) => SpindleSpeed::Rpm(rpm),
+        (
+            SpindleMode::Css {
+                surface_speed,
+                max_rpm,
+            },
+            _,
+        ) => SpindleSpeed::SurfaceSpeed {
+            speed: *surface_speed,
+            max_rpm: *max_rpm,
+        },
+    }
+}
+
+fn tool_change_generation(
+    job: &JobParams,
+    jobs: &[JobParams],
+    machine: &MachineParams,
+    active: &mut ActiveTool,
+) -> Toolpath {
+    // Only change tool or speed when the operation needs something different
+    let mut path = Toolpath::new();
+    let tool = job.tool.unwrap_or(machine.tool_num);
+    let speed = spindle_speed(job, machine);
+    if active.tool != Some(tool) {
+        if active.tool.is_some() {
+            path.append(clear_of_work(jobs, machine));
+            path.push(Motion::SpindleStop);
+        }
+        path.push(Motion::ToolChange(tool));
+        active.tool = Some(tool);
+        active.speed = None;
+    }
+    if active.speed != Some(speed) {
+        path.push(Motion::Spindle {
+            speed,
+            cw: machine.spindle_cw,
+        });
+        active.speed = Some(speed);
+    }
+    path
+}
+
+fn operation_generation(job: &JobParams, machine: &MachineParams, number: u32) -> Toolpath {
+    match job.job_type {
+        JobType::Boring(ref boring) => boring_generation(job, boring, machine),
+        JobType::Facing => facing_generation(job, machine),
+        JobType::FaceBoring => face_boring_generation(job, machine),
+        JobType::Turning => turning_generation(job, machine),
+        JobType::Taper(ref taper) => taper_generation(job, taper, machine),
+        JobType::Profile(ref profile) => profile_generation(job, profile, machine, number),
+        JobType::Grooving(ref groove) => grooving_generation(job, groove, machine),
+        JobType::FaceGrooving(ref groove) => face_grooving_generation(job, groove, machine),
+        JobType::Parting(ref part) => parting_generation(job, part, machine),
+        JobType::Drilling(ref drill) => drilling_generation(job, drill, machine),
+        JobType::Tapping(ref tap) => tapping_generation(job, tap, machine),
+        JobType::Threading(ref thread) => threading_generation(job, thread, machine),
+    }
+}
+
+// Moves that leave the travel or hit the workholding. They refuse the
+// program unless the machine only warns about them.
+pub fn check_envelope(
+    sections: &[Toolpath],
+    machine: &MachineParams,
+) -> Result<Vec<JobError>, JobError> {
+    let collisions = envelope::check(sections, machine);
+    match machine.on_collision {
+        CollisionAction::Refuse if !collisions.is_empty() => Err(JobError::Invalid(collisions)),
+        _ => Ok(collisions),
+    }
+}
+
+pub fn generate_sections(
+    jobs: &[JobParams],
+    machine: &MachineParams,
+) -> Result<(Vec<Toolpath>, Vec<JobError>), JobError> {
+    // The program split where each operation starts: the machine setup, one
+    // section per operation including its tool change, then the program end.
+    // Collisions that are only warned about come back with the sections.
+    validate(jobs, machine)?;
+    let mut setup = machine_settings_generation(machine);
+    setup.append(tool_change_position_generation(machine));
+    let mut sections = vec![setup];
+    let mut active = ActiveTool {
+        tool: None,
+        speed: None,
     };
-    let machine_setings = machine_settings_generation(machine);
-    format!("{}\n{}\nM2", machine_setings, cut_code)
+    for (i, job) in jobs.iter().enumerate() {
+        let mut path = tool_change_generation(job, jobs, machine, &mut active);
+        let operation = operation_generation(job, machine, i as u32 + 1);
+        // A finishing speed is still running when the next operation starts
+        if let Some(speed) = operation
+            .moves
+            .iter()
+            .rev()
+            .find_map(|motion| match motion {
+                Motion::Spindle { speed, .. } => Some(*speed),
+                _ => None,
+            })
+        {
+            active.speed = Some(speed);
+        }
+        path.append(operation);
+        sections.push(path);
+    }
+    let mut end = tool_change_position_generation(machine);
+    end.push(Motion::End);
+    sections.push(end);
+    let warnings = check_envelope(&sections, machine)?;
+    Ok((sections, warnings))
+}
+
+pub fn generate_toolpath(
+    jobs: &[JobParams],
+    machine: &MachineParams,
+) -> Result<Toolpath, JobError> {
+    // One program, operations run in order with tool changes between them
+    let mut path = Toolpath::new();
+    for section in generate_sections(jobs, machine)?.0 {
+        path.append(section);
+    }
+    Ok(path)
+}
+
+pub fn generate_gcode(jobs: &[JobParams], machine: &MachineParams) -> Result<String, JobError> {
+    let post = machine.controller.post_processor();
+    Ok(emit_gcode(
+        &generate_toolpath(jobs, machine)?,
+        post.as_ref(),
+    ))
 }
 
 // fn generate_gcode(
@@ -202,3 +2367,1011 @@ pub fn generate_gcode(job: &JobParams, machine: &MachineParams) -> String {
 //
 //     write_file(code, fname);
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emitter::contour_lines;
+    use crate::post::LinuxCnc;
+
+    fn machine() -> MachineParams {
+        MachineParams {
+            rpm: 1000,
+            spindle_mode: SpindleMode::Rpm,
+            spindle_cw: true,
+            units_inch: false,
+            tool_num: 1,
+            radius_mode: false,
+            canned_cycles: false,
+            controller: Controller::LinuxCnc,
+            tools: vec![],
+            nose_compensation: NoseCompensation::Off,
+            clearance: Clearance::default(),
+            retract: RetractMode::Stock,
+            tool_change: None,
+            rates: Rates::default(),
+            limits: None,
+            chuck: None,
+            tailstock: None,
+            on_collision: CollisionAction::Refuse,
+        }
+    }
+
+    fn near(actual: &[f32], expected: &[f32]) -> bool {
+        actual.len() == expected.len()
+            && actual
+                .iter()
+                .zip(expected)
+                .all(|(a, b)| (a - b).abs() < 1e-3)
+    }
+
+    // LinuxCNC blocks without the continuation indent
+    fn lines(path: &Toolpath) -> Vec<String> {
+        contour_lines(&path.moves, &LinuxCnc)
+            .iter()
+            .map(|line| line.trim().to_string())
+            .collect()
+    }
+
+    fn strategies() -> Vec<PassStrategy> {
+        vec![
+            PassStrategy::Equal,
+            PassStrategy::MaxStep,
+            PassStrategy::Degressive(1.0),
+            PassStrategy::Degressive(2.0),
+            PassStrategy::Degressive(3.0),
+        ]
+    }
+
+    fn turning(pass_strategy: PassStrategy) -> JobParams {
+        JobParams {
+            job_type: JobType::Turning,
+            start_depth: 20.0,
+            finish_depth: 12.0,
+            step: 1.5,
+            finish_step: 0.3,
+            start_cut: 0.0,
+            length: 30.0,
+            feed: 100.0,
+            tool: None,
+            rpm: None,
+            start_corner: None,
+            end_corner: None,
+            finish_feed: None,
+            finish_speed: None,
+            finishing: None,
+            pass_strategy,
+        }
+    }
+
+    // Each level is at most step below the one before and the last is the end
+    fn assert_steps(levels: &[f32], end: f32, step: f32) {
+        assert!(
+            (levels[levels.len() - 1] - end).abs() < 1e-4,
+            "{:?}",
+            levels
+        );
+        for pair in levels.windows(2) {
+            let cut = (pair[1] - pair[0]).abs();
+            assert!(cut > 0.0 && cut <= step + 1e-4, "{:?}", levels);
+        }
+    }
+
+    #[test]
+    fn rough_cuts_reach_the_depth_in_steps() {
+        for strategy in strategies() {
+            for &(depth, step) in &[(10.0, 1.0), (10.0, 3.0), (0.3, 1.0), (7.25, 0.8)] {
+                let mut cuts = vec![0.0];
+                cuts.extend(rough_cuts(&strategy, depth, step));
+                assert_steps(&cuts, depth, step);
+            }
+        }
+    }
+
+    #[test]
+    fn rough_cuts_without_depth() {
+        for strategy in strategies() {
+            assert!(rough_cuts(&strategy, 0.0, 1.0).is_empty());
+        }
+    }
+
+    #[test]
+    fn degressive_cuts_get_lighter() {
+        let max_step = rough_cuts(&PassStrategy::MaxStep, 10.0, 1.0);
+        let full = rough_cuts(&PassStrategy::Degressive(1.0), 10.0, 1.0);
+        assert_eq!(full.len(), max_step.len());
+        let cuts = rough_cuts(&PassStrategy::Degressive(2.0), 10.0, 1.0);
+        assert!(cuts.len() > max_step.len());
+        let mut reached = 0.0;
+        let passes: Vec<f32> = cuts
+            .iter()
+            .map(|cut| {
+                let pass = cut - reached;
+                reached = *cut;
+                pass
+            })
+            .collect();
+        for pair in passes.windows(2) {
+            assert!(pair[1] <= pair[0] + 1e-4, "{:?}", passes);
+        }
+        assert!(passes[passes.len() - 1] >= MIN_CHIP * 0.9);
+    }
+
+    #[test]
+    fn pass_depths_run_from_the_stock_to_the_finish() {
+        for strategy in strategies() {
+            let job = turning(strategy);
+            let passes = calculate_pass_depths(&job);
+            // Deepest first, the finishing allowance sits on top of the finish
+            assert_eq!(passes[0], job.finish_depth);
+            assert!((passes[1] - job.finish_depth - job.finish_step).abs() < 1e-4);
+            let mut levels = vec![job.start_depth];
+            levels.extend(passes[1..].iter().rev());
+            assert_steps(&levels, passes[1], job.step);
+        }
+    }
+
+    #[test]
+    fn pass_depths_with_a_finishing_schedule() {
+        let mut job = turning(PassStrategy::MaxStep);
+        job.finishing = Some(FinishSchedule {
+            depths: vec![0.5, 0.2],
+            spring_passes: 1,
+        });
+        let passes = calculate_pass_depths(&job);
+        assert_eq!(&passes[..4], &[12.0, 12.0, 12.2, 12.7]);
+        let mut levels = vec![job.start_depth];
+        levels.extend(passes[3..].iter().rev());
+        assert_steps(&levels, 12.7, job.step);
+    }
+
+    fn boring(exit: BoreExit) -> JobParams {
+        JobParams {
+            job_type: JobType::Boring(BoringParams { exit }),
+            start_depth: 10.0,
+            finish_depth: 14.0,
+            step: 2.0,
+            finish_step: 0.5,
+            length: 20.0,
+            ..turning(PassStrategy::MaxStep)
+        }
+    }
+
+    #[test]
+    fn boring_retracts_towards_the_centreline() {
+        let job = boring(BoreExit::Retract);
+        let path = operation_generation(&job, &machine(), 1);
+        assert_eq!(
+            lines(&path),
+            [
+                "G0 Z1.000",
+                "X11.500",
+                "G1 Z-20.000 F100",
+                "X9.000",
+                "G0 Z1.000",
+                "Z1.000",
+                "X13.500",
+                "G1 Z-20.000",
+                "X9.000",
+                "G0 Z1.000",
+                "Z1.000",
+                "X14.000",
+                "G1 Z-20.000",
+                "X9.000",
+                "G0 Z1.000",
+            ]
+        );
+    }
+
+    #[test]
+    fn boring_feeds_out_along_the_bore() {
+        let job = boring(BoreExit::Dwell(0.5));
+        let path = operation_generation(&job, &machine(), 1);
+        assert_eq!(
+            &lines(&path)[..6],
+            [
+                "G0 Z1.000",
+                "X11.500",
+                "G1 Z-20.000 F100",
+                "G4 P0.50",
+                "Z1.000",
+                "G0 X9.000",
+            ]
+        );
+    }
+
+    fn css() -> MachineParams {
+        MachineParams {
+            spindle_mode: SpindleMode::Css {
+                surface_speed: 150.0,
+                max_rpm: 2000,
+            },
+            ..machine()
+        }
+    }
+
+    fn drilling() -> JobParams {
+        JobParams {
+            job_type: JobType::Drilling(DrillParams {
+                cycle: DrillCycle::Simple,
+                peck: 0.0,
+                retract: 1.0,
+                dwell: 0.0,
+            }),
+            length: 10.0,
+            ..turning(PassStrategy::MaxStep)
+        }
+    }
+
+    #[test]
+    fn constant_surface_speed_except_on_the_centreline() {
+        let machine = css();
+        let jobs = [turning(PassStrategy::MaxStep), drilling()];
+        let path = generate_toolpath(&jobs, &machine).unwrap();
+        let speeds: Vec<String> = lines(&path)
+            .into_iter()
+            .filter(|line| line.ends_with("M3"))
+            .collect();
+        assert_eq!(speeds, ["G96 D2000 S150 M3", "G97 S1000 M3"]);
+    }
+
+    #[test]
+    fn constant_surface_speed_needs_a_clamp_and_a_controller() {
+        let mut machine = css();
+        machine.spindle_mode = SpindleMode::Css {
+            surface_speed: 150.0,
+            max_rpm: 0,
+        };
+        machine.controller = Controller::Grbl;
+        let problems = turning(PassStrategy::MaxStep).problems(&machine);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].to_string().contains("max_rpm"));
+        assert!(matches!(problems[1], JobError::CssUnsupported));
+        // Drilling runs at a fixed rpm, so GRBL can do it
+        assert!(drilling().problems(&machine).is_empty());
+    }
+
+    #[test]
+    fn problems_of_a_job() {
+        let mut job = turning(PassStrategy::Degressive(4.0));
+        assert!(turning(PassStrategy::MaxStep)
+            .problems(&machine())
+            .is_empty());
+        job.finish_depth = 22.0;
+        job.step = 0.0;
+        job.finish_feed = Some(-1.0);
+        assert_eq!(
+            job.problems(&machine()),
+            [
+                JobError::NotPositive {
+                    field: "finish_feed",
+                    value: -1.0
+                },
+                JobError::DegressionOutOfRange(4.0),
+                JobError::FinishBeyondStart {
+                    start: 20.0,
+                    finish: 22.0
+                },
+                JobError::NotPositive {
+                    field: "step",
+                    value: 0.0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn problems_of_a_program() {
+        let mut machine = machine();
+        machine.clearance.z = 0.0;
+        let mut job = drilling();
+        job.length = 0.0;
+        let jobs = [turning(PassStrategy::MaxStep), job];
+        assert_eq!(
+            validate(&jobs, &machine),
+            Err(JobError::Invalid(vec![
+                JobError::NotPositive {
+                    field: "clearance z",
+                    value: 0.0
+                },
+                JobError::InOperation {
+                    index: 2,
+                    error: Box::new(JobError::ZeroLength)
+                },
+            ]))
+        );
+        // Nothing is generated from an invalid program
+        assert!(generate_gcode(&jobs, &machine).is_err());
+    }
+
+    #[test]
+    fn controller_compensation_on_the_finishing_pass() {
+        let mut machine = machine();
+        machine.nose_compensation = NoseCompensation::Controller;
+        let path = turning_generation(&turning(PassStrategy::MaxStep), &machine);
+        let lines = lines(&path);
+        let on = lines.iter().position(|line| line == "G42").unwrap();
+        assert_eq!(
+            &lines[on - 1..],
+            [
+                "G0 Z1.000",
+                "G42",
+                "G0 X12.000",
+                "G1 Z-30.000",
+                "X21.000",
+                "G40",
+                "G0 Z1.000",
+            ]
+        );
+    }
+
+    #[test]
+    fn finishing_cut_with_corners() {
+        let mut job = turning(PassStrategy::MaxStep);
+        job.start_corner = Some(Corner::Chamfer {
+            size: 1.0,
+            angle: 45.0,
+        });
+        job.end_corner = Some(Corner::Radius(0.5));
+        let (start_x, cut) = z_finish_cut(&job, &machine(), 12.0, 100.0);
+        assert_eq!(start_x, 10.0);
+        assert_eq!(
+            lines(&cut),
+            [
+                "G1 Z0.000 F100",
+                "X12.000 Z-1.000",
+                "Z-29.500",
+                "G2 X13.000 Z-30.000 I0.500 K0.000",
+                "G1 X21.000",
+            ]
+        );
+    }
+
+    #[test]
+    fn roughing_stops_short_of_the_end_corner() {
+        let mut job = turning(PassStrategy::MaxStep);
+        let machine = machine();
+        job.end_corner = Some(Corner::Radius(0.5));
+        let ends: Vec<f32> = [13.3, 12.8, 12.3]
+            .iter()
+            .map(|&level| rough_end(&job, &machine, level, true))
+            .collect();
+        assert!(near(&ends, &[-30.0, -29.933, -29.5]), "{:?}", ends);
+        job.end_corner = Some(Corner::Chamfer {
+            size: 1.0,
+            angle: 45.0,
+        });
+        assert!((rough_end(&job, &machine, 12.8, true) + 29.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn corners_must_fit_the_cut() {
+        let mut job = turning(PassStrategy::MaxStep);
+        job.start_corner = Some(Corner::Radius(20.0));
+        job.end_corner = Some(Corner::Radius(15.0));
+        assert_eq!(
+            job.problems(&machine()),
+            [JobError::CornersTooLong {
+                corners: 35.0,
+                length: 30.0
+            }]
+        );
+        let mut job = drilling();
+        job.end_corner = Some(Corner::Radius(1.0));
+        assert_eq!(job.problems(&machine()), [JobError::CornerNotSupported]);
+    }
+
+    fn taper(end: TaperEnd, pass_strategy: PassStrategy) -> JobParams {
+        JobParams {
+            job_type: JobType::Taper(TaperParams { end }),
+            ..turning(pass_strategy)
+        }
+    }
+
+    #[test]
+    fn taper_ends() {
+        let end_x = |end: TaperEnd, radius_mode: bool| {
+            let job = taper(end, PassStrategy::MaxStep);
+            match job.job_type {
+                JobType::Taper(ref taper) => taper.end_x(&job, radius_mode),
+                _ => unreachable!(),
+            }
+        };
+        let ends = [
+            end_x(TaperEnd::Diameter(15.0), false),
+            end_x(TaperEnd::Angle(10.0), false),
+            end_x(TaperEnd::PerFoot(1.2), false),
+            end_x(TaperEnd::PerFoot(1.2), true),
+            end_x(TaperEnd::Morse(2), false),
+        ];
+        assert!(
+            near(&ends, &[15.0, 17.249, 15.0, 13.5, 13.499]),
+            "{:?}",
+            ends
+        );
+    }
+
+    // Offset from the finished cone of each cut along it, outermost first
+    fn taper_offsets(pass_strategy: PassStrategy) -> Vec<f32> {
+        let job = taper(TaperEnd::PerFoot(1.2), pass_strategy);
+        let path = operation_generation(&job, &machine(), 1);
+        let (mut x, mut z) = (0.0, 0.0);
+        let mut offsets = vec![];
+        for motion in &path.moves {
+            match *motion {
+                Motion::Feed {
+                    x: Some(to_x),
+                    z: Some(to_z),
+                    ..
+                } => {
+                    // Every cut runs parallel to the cone
+                    assert!(((to_x - x) / (to_z - z) + 0.1).abs() < 1e-4);
+                    offsets.push(x - job.finish_depth + 0.1 * z);
+                    x = to_x;
+                    z = to_z;
+                }
+                Motion::Rapid { x: to_x, z: to_z }
+                | Motion::Feed {
+                    x: to_x, z: to_z, ..
+                } => {
+                    x = to_x.unwrap_or(x);
+                    z = to_z.unwrap_or(z);
+                }
+                _ => (),
+            }
+        }
+        offsets
+    }
+
+    #[test]
+    fn taper_passes_follow_the_cone() {
+        let offsets = taper_offsets(PassStrategy::MaxStep);
+        assert!(
+            near(&offsets, &[7.8, 6.3, 4.8, 3.3, 1.8, 0.3, 0.0]),
+            "{:?}",
+            offsets
+        );
+        let offsets = taper_offsets(PassStrategy::Equal);
+        assert!(
+            near(&offsets, &[6.717, 5.433, 4.15, 2.867, 1.583, 0.3, 0.0]),
+            "{:?}",
+            offsets
+        );
+        let offsets = taper_offsets(PassStrategy::Degressive(2.0));
+        assert!(offsets.len() > 7);
+        for pair in offsets.windows(2) {
+            assert!(
+                pair[1] < pair[0] && pair[0] - pair[1] <= 1.5 + 1e-4,
+                "{:?}",
+                offsets
+            );
+        }
+    }
+
+    #[test]
+    fn contour_from_text() {
+        let profile: ProfileParams = "x10 z0; X12 Z-1;X12 Z-20; X16 Z-22 R2 G3; X18 Z-23 R1 G2;"
+            .parse()
+            .unwrap();
+        assert_eq!((profile.start_x, profile.start_z), (10.0, 0.0));
+        assert_eq!(profile.contour.len(), 4);
+        assert!(matches!(
+            profile.contour[0],
+            ContourElement::Line { x, z } if x == 12.0 && z == -1.0
+        ));
+        assert!(matches!(
+            profile.contour[2],
+            ContourElement::Arc { x, z, radius, cw: false } if x == 16.0 && z == -22.0 && radius == 2.0
+        ));
+        assert!(matches!(
+            profile.contour[3],
+            ContourElement::Arc { cw: true, .. }
+        ));
+    }
+
+    #[test]
+    fn contour_text_errors() {
+        let cases = [
+            ("", "empty contour"),
+            ("X10 Z0; X12", "contour block needs X and Z: X12"),
+            ("X10 Z0; X1a Z-1", "bad number in contour: X1A"),
+            ("X10 Z0 F100", "unknown word in contour: F100"),
+            ("X10 Z0 G1", "unknown word in contour: G1"),
+            ("X10 Z0 ÄX1", "unknown word in contour: ÄX1"),
+        ];
+        for &(text, error) in &cases {
+            assert_eq!(text.parse::<ProfileParams>().err().unwrap(), error);
+        }
+    }
+
+    fn profile(text: &str) -> JobParams {
+        JobParams {
+            job_type: JobType::Profile(text.parse().unwrap()),
+            length: 0.0,
+            start_depth: 22.0,
+            ..turning(PassStrategy::MaxStep)
+        }
+    }
+
+    #[test]
+    fn undercut_contours_are_rejected() {
+        let machine = machine();
+        assert!(profile("X10 Z0; X12 Z-1; X12 Z-20; X16 Z-22 R2 G3")
+            .problems(&machine)
+            .is_empty());
+        // X falls into a groove, Z turns back towards the tailstock
+        for text in &["X10 Z0; X12 Z-5; X8 Z-10", "X10 Z0; X12 Z-5; X14 Z-4"] {
+            assert_eq!(
+                profile(text).problems(&machine),
+                [JobError::ProfileNotMonotonic { element: 2 }]
+            );
+        }
+        // An arc between two points on one diameter bulges past it
+        assert_eq!(
+            profile("X10 Z0; X10 Z-10 R6 G2").problems(&machine),
+            [JobError::ProfileNotMonotonic { element: 1 }]
+        );
+        assert_eq!(
+            profile("X10 Z0; X20 Z-10 R1 G3").problems(&machine),
+            [JobError::ArcTooSmall {
+                element: 1,
+                radius: 1.0
+            }]
+        );
+    }
+
+    fn grooving(peck: f32) -> JobParams {
+        JobParams {
+            job_type: JobType::Grooving(GrooveParams {
+                width: 7.0,
+                tool_width: 3.0,
+                overlap: 0.5,
+                peck,
+                retract: 0.5,
+            }),
+            start_depth: 20.0,
+            finish_depth: 16.0,
+            finish_step: 0.2,
+            start_cut: -5.0,
+            length: 0.0,
+            feed: 50.0,
+            ..turning(PassStrategy::MaxStep)
+        }
+    }
+
+    #[test]
+    fn groove_plunges_step_across_the_width() {
+        let path = operation_generation(&grooving(1.5), &machine(), 1);
+        let lines = lines(&path);
+        assert_eq!(
+            &lines[..9],
+            [
+                "G0 X21.000",
+                "Z-5.000",
+                "X20.500",
+                "G1 X19.000 F50",
+                "G0 X19.500",
+                "G1 X17.500",
+                "G0 X18.000",
+                "G1 X16.200",
+                "G0 X21.000",
+            ]
+        );
+        let plunges: Vec<&str> = lines[..lines.len() - 4]
+            .iter()
+            .filter(|line| line.starts_with('Z'))
+            .map(String::as_str)
+            .collect();
+        assert_eq!(plunges, ["Z-5.000", "Z-7.500", "Z-9.000", "Z-5.000"]);
+        // The floor is finished across the width from the first plunge
+        assert_eq!(
+            &lines[lines.len() - 4..],
+            ["X20.500", "G1 X16.000", "Z-9.000", "X21.000"]
+        );
+    }
+
+    #[test]
+    fn groove_plunge_without_pecks() {
+        let path = operation_generation(&grooving(0.0), &machine(), 1);
+        assert_eq!(
+            &lines(&path)[..5],
+            [
+                "G0 X21.000",
+                "Z-5.000",
+                "X20.500",
+                "G1 X16.200 F50",
+                "G0 X21.000",
+            ]
+        );
+    }
+
+    fn parting(chamfer: f32) -> JobParams {
+        JobParams {
+            job_type: JobType::Parting(PartParams {
+                peck: 0.0,
+                retract: 0.5,
+                slow_diameter: 4.0,
+                slow_feed: 20.0,
+                chamfer,
+            }),
+            start_depth: 20.0,
+            finish_depth: 0.0,
+            start_cut: -30.0,
+            length: 0.0,
+            feed: 60.0,
+            ..turning(PassStrategy::MaxStep)
+        }
+    }
+
+    #[test]
+    fn parting_slows_down_near_the_centre() {
+        let path = operation_generation(&parting(0.0), &machine(), 1);
+        assert_eq!(
+            lines(&path),
+            [
+                "G0 X21.000",
+                "Z-30.000",
+                "X20.500",
+                "G1 X4.000 F60",
+                "X0.000 F20",
+                "G0 X21.000",
+            ]
+        );
+    }
+
+    #[test]
+    fn parting_chamfers_the_part_first() {
+        let path = operation_generation(&parting(0.5), &machine(), 1);
+        assert_eq!(
+            &lines(&path)[..10],
+            [
+                "G0 X21.000",
+                "Z-30.000",
+                "X20.500",
+                "G1 X19.000 F60",
+                "G0 X21.000",
+                "Z-29.500",
+                "X20.500",
+                "G1 X20.000",
+                "X19.000 Z-30.000",
+                "X4.000",
+            ]
+        );
+    }
+
+    fn face_grooving(inner_diameter: f32) -> JobParams {
+        JobParams {
+            job_type: JobType::FaceGrooving(FaceGrooveParams {
+                inner_diameter,
+                outer_diameter: 30.0,
+                tool_width: 2.0,
+                overlap: 0.5,
+                peck: 1.5,
+                retract: 0.5,
+            }),
+            start_depth: 0.0,
+            finish_depth: -3.0,
+            finish_step: 0.2,
+            start_cut: 30.0,
+            length: 0.0,
+            feed: 40.0,
+            ..turning(PassStrategy::MaxStep)
+        }
+    }
+
+    #[test]
+    fn face_groove_plunges_step_inwards() {
+        let path = operation_generation(&face_grooving(20.0), &machine(), 1);
+        let lines = lines(&path);
+        assert_eq!(
+            &lines[..10],
+            [
+                "G0 Z1.000",
+                "X30.000",
+                "Z0.500",
+                "G1 Z-1.000 F40",
+                "G0 Z-0.500",
+                "G1 Z-2.500",
+                "G0 Z-2.000",
+                "G1 Z-2.800",
+                "G0 Z1.000",
+                "X27.000",
+            ]
+        );
+        assert_eq!(lines[17], "X24.000");
+        // Floor finished inwards to the inner diameter less the blade
+        assert_eq!(
+            &lines[lines.len() - 5..],
+            ["X30.000", "Z0.500", "G1 Z-3.000", "X24.000", "Z1.000"]
+        );
+    }
+
+    #[test]
+    fn face_groove_must_fit_the_blade() {
+        assert!(face_grooving(20.0).problems(&machine()).is_empty());
+        assert_eq!(
+            face_grooving(28.0).problems(&machine()),
+            [JobError::GrooveNarrowerThanTool {
+                width: 1.0,
+                tool_width: 2.0
+            }]
+        );
+    }
+
+    fn tapping(mode: TapMode) -> JobParams {
+        JobParams {
+            job_type: JobType::Tapping(TapParams {
+                pitch: 1.25,
+                mode,
+                retract: 3.0,
+                dwell: 0.3,
+            }),
+            start_cut: 0.0,
+            length: 12.0,
+            ..turning(PassStrategy::MaxStep)
+        }
+    }
+
+    #[test]
+    fn rigid_tapping() {
+        let path = operation_generation(&tapping(TapMode::Rigid), &machine(), 1);
+        assert_eq!(
+            lines(&path),
+            ["G0 X0.000", "Z3.000", "G33.1 Z-12.000 K1.2500"]
+        );
+    }
+
+    #[test]
+    fn floating_tapping_reverses_the_spindle() {
+        let path = operation_generation(&tapping(TapMode::Floating), &machine(), 1);
+        assert_eq!(
+            lines(&path),
+            [
+                "G0 X0.000",
+                "Z3.000",
+                "G1 Z-12.000 F1250",
+                "G4 P0.30",
+                "G97 S1000 M4",
+                "Z3.000",
+                "G97 S1000 M3",
+            ]
+        );
+    }
+
+    #[test]
+    fn tapping_checks() {
+        let mut machine = machine();
+        machine.controller = Controller::Fanuc;
+        assert_eq!(
+            tapping(TapMode::Rigid).problems(&machine),
+            [JobError::RigidTapUnsupported]
+        );
+        assert!(tapping(TapMode::Floating).problems(&machine).is_empty());
+        let mut job = tapping(TapMode::Floating);
+        job.start_cut = 5.0;
+        assert_eq!(
+            job.problems(&machine),
+            [JobError::RetractBelowHole {
+                retract: 3.0,
+                start: 5.0
+            }]
+        );
+    }
+
+    fn finishing_speed_and_feed() -> JobParams {
+        JobParams {
+            finish_feed: Some(40.0),
+            finish_speed: Some(1500.0),
+            ..turning(PassStrategy::MaxStep)
+        }
+    }
+
+    #[test]
+    fn finishing_pass_speed_and_feed() {
+        let path = turning_generation(&finishing_speed_and_feed(), &machine());
+        let blocks = lines(&path);
+        let speed = blocks
+            .iter()
+            .position(|line| line == "G97 S1500 M3")
+            .unwrap();
+        assert_eq!(
+            &blocks[speed..],
+            [
+                "G97 S1500 M3",
+                "G0 Z1.000",
+                "X12.000",
+                "G1 Z-30.000 F40",
+                "X21.000",
+                "G0 Z1.000",
+            ]
+        );
+        // Roughing keeps the job's feed
+        assert!(blocks[..speed]
+            .iter()
+            .filter(|line| line.contains(" F"))
+            .all(|line| line.ends_with("F100")));
+
+        let path = turning_generation(&finishing_speed_and_feed(), &css());
+        assert!(lines(&path).contains(&"G96 D2000 S1500 M3".to_string()));
+    }
+
+    #[test]
+    fn next_operation_restores_the_speed() {
+        let jobs = [finishing_speed_and_feed(), turning(PassStrategy::MaxStep)];
+        let (sections, _) = generate_sections(&jobs, &machine()).unwrap();
+        assert_eq!(lines(&sections[2])[0], "G97 S1000 M3");
+        // A job running at the finishing speed carries on with it
+        let jobs = [
+            finishing_speed_and_feed(),
+            JobParams {
+                rpm: Some(1500),
+                ..turning(PassStrategy::MaxStep)
+            },
+        ];
+        let (sections, _) = generate_sections(&jobs, &machine()).unwrap();
+        assert_eq!(lines(&sections[2])[0], "G0 Z1.000");
+    }
+
+    #[test]
+    fn finishing_and_spring_passes() {
+        let mut job = turning(PassStrategy::MaxStep);
+        job.finishing = Some(FinishSchedule {
+            depths: vec![0.5, 0.2],
+            spring_passes: 1,
+        });
+        let path = turning_generation(&job, &machine());
+        let blocks = lines(&path);
+        let levels: Vec<&str> = blocks
+            .windows(2)
+            .filter(|pair| pair[0] == "G0 Z1.000" && pair[1].starts_with('X'))
+            .map(|pair| pair[1].as_str())
+            .collect();
+        assert_eq!(
+            levels,
+            [
+                "X18.700", "X17.200", "X15.700", "X14.200", "X12.700", "X12.200", "X12.000",
+                "X12.000",
+            ]
+        );
+    }
+
+    fn threading(finishing: Option<FinishSchedule>) -> JobParams {
+        JobParams {
+            job_type: JobType::Threading(ThreadParams {
+                pitch: 1.5,
+                major_diameter: 12.0,
+                minor_diameter: 10.16,
+                thread_depth: 0.92,
+                infeed_angle: 29.5,
+                spring_passes: 5,
+                degression: 1.0,
+            }),
+            start_depth: 12.0,
+            finish_depth: 10.16,
+            step: 0.3,
+            length: 20.0,
+            finishing,
+            ..turning(PassStrategy::MaxStep)
+        }
+    }
+
+    #[test]
+    fn thread_schedule_replaces_its_spring_passes() {
+        let passes = |job: &JobParams| {
+            let path = operation_generation(job, &machine(), 1);
+            lines(&path)
+                .iter()
+                .filter(|line| line.starts_with("G33"))
+                .count()
+        };
+        assert_eq!(passes(&threading(None)), 9);
+        let schedule = FinishSchedule {
+            depths: vec![0.05],
+            spring_passes: 2,
+        };
+        let job = threading(Some(schedule));
+        assert!(job.problems(&machine()).is_empty());
+        assert_eq!(passes(&job), 6);
+    }
+
+    fn retract(job: &JobParams, retract: RetractMode, at: (f32, f32), in_x: bool) -> Vec<String> {
+        let machine = MachineParams {
+            retract,
+            ..machine()
+        };
+        let mut path = Toolpath::new();
+        pass_retract(&mut path, job, &machine, at, job.start_depth - 2.5, in_x);
+        lines(&path)
+    }
+
+    #[test]
+    fn turning_retracts() {
+        let job = turning(PassStrategy::MaxStep);
+        let at = (16.0, -30.0);
+        assert_eq!(
+            retract(&job, RetractMode::Stock, at, true),
+            ["G1 X21.000 F100"]
+        );
+        assert_eq!(
+            retract(&job, RetractMode::Diameter(25.0), at, true),
+            ["G1 X21.000 F100", "G0 X25.000"]
+        );
+        assert_eq!(
+            retract(&job, RetractMode::Previous, at, true),
+            ["G1 X18.500 F100"]
+        );
+        // Clearance is radial, half of it in diameter mode along Z
+        assert_eq!(
+            retract(&job, RetractMode::Diagonal, at, true),
+            ["G1 X17.000 Z-29.500 F100"]
+        );
+    }
+
+    #[test]
+    fn facing_retracts() {
+        let job = JobParams {
+            job_type: JobType::Facing,
+            start_depth: 3.0,
+            finish_depth: 0.0,
+            start_cut: 12.0,
+            length: 13.0,
+            ..turning(PassStrategy::MaxStep)
+        };
+        let at = (-1.0, 1.5);
+        assert_eq!(
+            retract(&job, RetractMode::Stock, at, false),
+            ["G1 Z4.000 F100"]
+        );
+        assert_eq!(
+            retract(&job, RetractMode::Previous, at, false),
+            ["G1 Z1.500 F100"]
+        );
+        assert_eq!(
+            retract(&job, RetractMode::Diagonal, at, false),
+            ["G1 X1.000 Z2.500 F100"]
+        );
+    }
+
+    #[test]
+    fn safe_diameter_must_be_clear_of_the_stock() {
+        let machine = MachineParams {
+            retract: RetractMode::Diameter(20.5),
+            ..machine()
+        };
+        assert_eq!(
+            turning(PassStrategy::MaxStep).problems(&machine),
+            [JobError::SafeDiameterInStock {
+                x: 20.5,
+                limit: 21.0,
+                boring: false
+            }]
+        );
+        assert_eq!(
+            boring(BoreExit::Retract).problems(&machine),
+            [JobError::SafeDiameterInStock {
+                x: 20.5,
+                limit: 9.0,
+                boring: true
+            }]
+        );
+        // Other exits leave the bore along it
+        assert!(boring(BoreExit::FeedOut).problems(&machine).is_empty());
+    }
+
+    #[test]
+    fn collisions_refuse_or_warn() {
+        let mut machine = MachineParams {
+            chuck: Some(Chuck { x: 15.0, z: -25.0 }),
+            ..machine()
+        };
+        let jobs = [turning(PassStrategy::MaxStep)];
+        let collision = match generate_sections(&jobs, &machine) {
+            Err(JobError::Invalid(errors)) => errors,
+            _ => panic!("collision not refused"),
+        };
+        assert_eq!(collision.len(), 1);
+        machine.on_collision = CollisionAction::Warn;
+        let (_, warnings) = generate_sections(&jobs, &machine).unwrap();
+        assert_eq!(warnings, collision);
+    }
+}
@@ -0,0 +1,386 @@
+// Plane geometry for lathe paths in (z, radius) coordinates, shared by
+// nose compensation, contour roughing and the replays of a toolpath.
+
+use std::f32::consts::PI;
+
+use crate::toolpath::Motion;
+
+pub const EPSILON: f32 = 1e-5;
+
+#[derive(Clone, Copy)]
+pub struct Point {
+    pub z: f32,
+    pub r: f32,
+}
+
+impl Point {
+    pub fn add(self, other: Point, scale: f32) -> Point {
+        Point {
+            z: self.z + other.z * scale,
+            r: self.r + other.r * scale,
+        }
+    }
+
+    pub fn minus(self, other: Point) -> Point {
+        Point {
+            z: self.z - other.z,
+            r: self.r - other.r,
+        }
+    }
+
+    pub fn length(self) -> f32 {
+        (self.z * self.z + self.r * self.r).sqrt()
+    }
+
+    pub fn unit(self) -> Point {
+        let length = self.length();
+        Point {
+            z: self.z / length,
+            r: self.r / length,
+        }
+    }
+
+    pub fn cross(self, other: Point) -> f32 {
+        self.z * other.r - self.r * other.z
+    }
+}
+
+#[derive(Clone)]
+pub enum Element {
+    Line,
+    Arc { cw: bool, centre: Point },
+}
+
+#[derive(Clone)]
+pub struct Segment {
+    pub element: Element,
+    pub start: Point,
+    pub end: Point,
+    pub feed: f32,
+}
+
+impl Segment {
+    pub fn tangent(&self, at: Point) -> Point {
+        match self.element {
+            Element::Line => self.end.minus(self.start).unit(),
+            Element::Arc { cw, centre } => {
+                // G2/G3 as seen from +Y, where Z is right and X is up
+                let radial = at.minus(centre).unit();
+                if cw {
+                    Point {
+                        z: radial.r,
+                        r: -radial.z,
+                    }
+                } else {
+                    Point {
+                        z: -radial.r,
+                        r: radial.z,
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Feed and arc moves from start as segments, other moves are skipped
+pub fn segments(start: Point, cut: &[Motion], x_scale: f32) -> Vec<Segment> {
+    let mut position = start;
+    let mut segments = vec![];
+    for motion in cut {
+        let segment = match *motion {
+            Motion::Feed { x, z, feed } => Segment {
+                element: Element::Line,
+                start: position,
+                end: Point {
+                    z: z.unwrap_or(position.z),
+                    r: x.map(|x| x / x_scale).unwrap_or(position.r),
+                },
+                feed,
+            },
+            Motion::Arc {
+                cw,
+                x,
+                z,
+                i,
+                k,
+                feed,
+            } => Segment {
+                element: Element::Arc {
+                    cw,
+                    centre: Point {
+                        z: position.z + k,
+                        r: position.r + i,
+                    },
+                },
+                start: position,
+                end: Point { z, r: x / x_scale },
+                feed,
+            },
+            _ => continue,
+        };
+        position = segment.end;
+        segments.push(segment);
+    }
+    segments
+}
+
+// Back to feed and arc moves in program units, lines only name the axes
+// that move
+pub fn motions(segments: &[Segment], x_scale: f32) -> Vec<Motion> {
+    let changed = |from: f32, to: f32| {
+        if (to - from).abs() > EPSILON {
+            Some(to)
+        } else {
+            None
+        }
+    };
+    segments
+        .iter()
+        .map(|segment| match segment.element {
+            Element::Line => Motion::Feed {
+                x: changed(segment.start.r, segment.end.r).map(|r| r * x_scale),
+                z: changed(segment.start.z, segment.end.z),
+                feed: segment.feed,
+            },
+            Element::Arc { cw, centre } => Motion::Arc {
+                cw,
+                x: segment.end.r * x_scale,
+                z: segment.end.z,
+                i: centre.r - segment.start.r,
+                k: centre.z - segment.start.z,
+                feed: segment.feed,
+            },
+        })
+        .collect()
+}
+
+impl Segment {
+    // Same segment moved by offset
+    pub fn translate(&self, offset: Point) -> Segment {
+        Segment {
+            element: match self.element {
+                Element::Line => Element::Line,
+                Element::Arc { cw, centre } => Element::Arc {
+                    cw,
+                    centre: centre.add(offset, 1.0),
+                },
+            },
+            start: self.start.add(offset, 1.0),
+            end: self.end.add(offset, 1.0),
+            feed: self.feed,
+        }
+    }
+
+    // Angle an arc turns through, negative when clockwise
+    fn sweep(&self, centre: Point, cw: bool) -> f32 {
+        let from = self.start.minus(centre);
+        let to = self.end.minus(centre);
+        let mut sweep = to.r.atan2(to.z) - from.r.atan2(from.z);
+        if cw && sweep > 0.0 {
+            sweep -= 2.0 * PI;
+        } else if !cw && sweep < 0.0 {
+            sweep += 2.0 * PI;
+        }
+        sweep
+    }
+
+    // Distance along the segment, arcs go the way they turn
+    pub fn length(&self) -> f32 {
+        match self.element {
+            Element::Line => self.end.minus(self.start).length(),
+            Element::Arc { cw, centre } => {
+                self.start.minus(centre).length() * self.sweep(centre, cw).abs()
+            }
+        }
+    }
+
+    // Shortest distance from point to the segment
+    pub fn distance(&self, point: Point) -> f32 {
+        let ends = point
+            .minus(self.start)
+            .length()
+            .min(point.minus(self.end).length());
+        match self.element {
+            Element::Line => {
+                let edge = self.end.minus(self.start);
+                let length = edge.length();
+                if length < EPSILON {
+                    return ends;
+                }
+                let offset = point.minus(self.start);
+                let t = (offset.z * edge.z + offset.r * edge.r) / (length * length);
+                point
+                    .minus(self.start.add(edge, t.clamp(0.0, 1.0)))
+                    .length()
+            }
+            Element::Arc { cw, centre } => {
+                // The nearest point is on the circle when point is within the sweep
+                let sweep = self.sweep(centre, cw);
+                let from = self.start.minus(centre);
+                let offset = point.minus(centre);
+                let mut angle = offset.r.atan2(offset.z) - from.r.atan2(from.z);
+                if sweep < 0.0 && angle > 0.0 {
+                    angle -= 2.0 * PI;
+                } else if sweep > 0.0 && angle < 0.0 {
+                    angle += 2.0 * PI;
+                }
+                if angle.abs() <= sweep.abs() {
+                    (offset.length() - from.length()).abs()
+                } else {
+                    ends
+                }
+            }
+        }
+    }
+
+    // Point a fraction t of the way along
+    pub fn point_at(&self, t: f32) -> Point {
+        match self.element {
+            Element::Line => self.start.add(self.end.minus(self.start), t),
+            Element::Arc { cw, centre } => {
+                let from = self.start.minus(centre);
+                let angle = from.r.atan2(from.z) + self.sweep(centre, cw) * t;
+                let radius = from.length();
+                Point {
+                    z: centre.z + radius * angle.cos(),
+                    r: centre.r + radius * angle.sin(),
+                }
+            }
+        }
+    }
+}
+
+// X words are diameters unless the machine is in radius mode
+pub fn x_scale(radius_mode: bool) -> f32 {
+    if radius_mode {
+        1.0
+    } else {
+        2.0
+    }
+}
+
+// Where a toolpath has got to, shared by everything that replays one
+#[derive(Clone, Copy)]
+pub struct Position {
+    pub x_scale: f32,
+    pub x: Option<f32>, // Program units, unknown until a move names the axis
+    pub z: Option<f32>,
+}
+
+impl Position {
+    pub fn new(radius_mode: bool) -> Position {
+        Position {
+            x_scale: x_scale(radius_mode),
+            x: None,
+            z: None,
+        }
+    }
+
+    // In (z, radius)
+    pub fn point(&self) -> Option<Point> {
+        Some(Point {
+            z: self.z?,
+            r: self.x? / self.x_scale,
+        })
+    }
+
+    // In program units, X for r
+    pub fn program(&self) -> Option<Point> {
+        Some(Point {
+            z: self.z?,
+            r: self.x?,
+        })
+    }
+
+    // Takes up the position at the end of a move
+    pub fn follow(&mut self, motion: &Motion) {
+        match *motion {
+            Motion::Setup { radius_mode, .. } => self.x_scale = x_scale(radius_mode),
+            Motion::Reference { .. } => {
+                // Somewhere the toolpath doesn't say
+                self.x = None;
+                self.z = None;
+            }
+            Motion::Rapid { x, z } | Motion::Feed { x, z, .. } => {
+                self.x = x.or(self.x);
+                self.z = z.or(self.z);
+            }
+            Motion::Arc { x, z, .. } => {
+                self.x = Some(x);
+                self.z = Some(z);
+            }
+            Motion::Thread { z, .. } => self.z = Some(z),
+            // Taps back out to where they started
+            Motion::RigidTap { .. } => (),
+            Motion::Cycle(ref cycle) => {
+                for motion in &cycle.expand().moves {
+                    self.follow(motion);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    pub fn after(mut self, motion: &Motion) -> Position {
+        self.follow(motion);
+        self
+    }
+
+    // A feed or arc move as segments, none when the start isn't known
+    pub fn segments(&self, motion: &Motion) -> Vec<Segment> {
+        match self.point() {
+            Some(start) => segments(start, std::slice::from_ref(motion), self.x_scale),
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::DrillCycle;
+    use crate::toolpath::Cycle;
+
+    #[test]
+    fn position_follows_the_moves() {
+        let mut position = Position::new(false);
+        position.follow(&Motion::Rapid {
+            x: Some(20.0),
+            z: None,
+        });
+        assert!(position.point().is_none());
+        position.follow(&Motion::Feed {
+            x: None,
+            z: Some(-5.0),
+            feed: 100.0,
+        });
+        let point = position.point().unwrap();
+        assert_eq!((point.z, point.r), (-5.0, 10.0));
+
+        // Taps come back, drilling cycles finish at the R plane
+        position.follow(&Motion::RigidTap {
+            z: -20.0,
+            pitch: 1.0,
+        });
+        assert_eq!(position.z, Some(-5.0));
+        position.follow(&Motion::Cycle(Cycle::Drill {
+            kind: DrillCycle::Simple,
+            start_z: 2.0,
+            z: -10.0,
+            retract: 2.0,
+            peck: 0.0,
+            dwell: 0.0,
+            feed: 50.0,
+        }));
+        assert_eq!((position.x, position.z), (Some(20.0), Some(2.0)));
+
+        position.follow(&Motion::Setup {
+            units_inch: false,
+            radius_mode: true,
+            feed_per_rev: false,
+        });
+        assert_eq!(position.point().unwrap().r, 20.0);
+        position.follow(&Motion::Reference { second: false });
+        assert!(position.x.is_none() && position.z.is_none());
+    }
+}
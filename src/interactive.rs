@@ -0,0 +1,284 @@
+// Interactive prompts, used with --interactive
+
+use std::io;
+
+use lathe::generator;
+use lathe::post::Controller;
+
+// fn get_string() -> io::Result<String> {
+//     let mut buffer = String::new();
+//
+//     io::stdin().read_line(&mut buffer)?;
+//
+//     Ok(buffer)
+// }
+
+fn get_string(s: &str) -> String {
+    //https://doc.rust-lang.org/std/io/type.Result.html
+    let mut input = String::new();
+    println!("{}", s);
+    io::stdin()
+        .read_line(&mut input)
+        .expect("couldn't read from stdin");
+    String::from(input.trim())
+}
+
+fn get_bool(s: &str) -> bool {
+    let input = get_string(s);
+    let mut bool_val: Option<bool> = match input.as_ref() {
+        "y" => Some(true),
+        "n" => Some(false),
+        _ => None,
+    };
+    while bool_val.is_none() {
+        bool_val = Some(get_bool(s));
+    }
+    bool_val.unwrap()
+}
+
+fn get_f32(s: &str) -> f32 {
+    let input = get_string(s);
+    input.parse::<f32>().unwrap()
+}
+
+fn get_i32(s: &str) -> i32 {
+    let input = get_string(s);
+    input.parse::<i32>().unwrap()
+}
+
+fn get_bore_exit() -> generator::BoreExit {
+    let input = get_string("Bore exit (retract/feedout/dwell):    ");
+    match input.to_lowercase().as_ref() {
+        "retract" => generator::BoreExit::Retract,
+        "feedout" => generator::BoreExit::FeedOut,
+        "dwell" => generator::BoreExit::Dwell(get_f32("Dwell (s)   :    ")),
+        _ => get_bore_exit(),
+    }
+}
+
+fn get_taper_end() -> generator::TaperEnd {
+    let input = get_string("Taper end (diameter/angle/tpf/morse):    ");
+    match input.to_lowercase().as_ref() {
+        "diameter" => generator::TaperEnd::Diameter(get_f32("End diameter:    ")),
+        "angle" => generator::TaperEnd::Angle(get_f32("Included angle:  ")),
+        "tpf" => generator::TaperEnd::PerFoot(get_f32("Taper per foot:  ")),
+        "morse" => generator::TaperEnd::Morse(get_i32("Morse number:    ") as u8),
+        _ => get_taper_end(),
+    }
+}
+
+fn get_contour() -> generator::ProfileParams {
+    let input = get_string("Contour (X10 Z0; X12 Z-1; X16 Z-22 R2 G3):    ");
+    match input.parse() {
+        Ok(profile) => profile,
+        Err(why) => {
+            println!("{}", why);
+            get_contour()
+        }
+    }
+}
+
+fn get_drill_cycle() -> generator::DrillCycle {
+    let input = get_string("Drill cycle (simple/dwell/peck/chipbreak):    ");
+    match input.parse() {
+        Ok(cycle) => cycle,
+        Err(_) => get_drill_cycle(),
+    }
+}
+
+fn get_tap_mode() -> generator::TapMode {
+    let input = get_string("Tapping mode (rigid/floating):    ");
+    match input.parse() {
+        Ok(mode) => mode,
+        Err(_) => get_tap_mode(),
+    }
+}
+
+fn get_controller() -> Controller {
+    let input = get_string("Controller (linuxcnc/fanuc/grbl):    ");
+    match input.parse() {
+        Ok(controller) => controller,
+        Err(_) => get_controller(),
+    }
+}
+
+fn get_nose_compensation() -> generator::NoseCompensation {
+    let input = get_string("Nose compensation (off/controller/computed):    ");
+    match input.parse() {
+        Ok(compensation) => compensation,
+        Err(_) => get_nose_compensation(),
+    }
+}
+
+pub fn get_job() -> (generator::JobParams, generator::MachineParams) {
+    let input = get_string("Job type:    ");
+    let job_type: generator::JobType = match input.trim().to_lowercase().as_ref() {
+        "boring" => generator::JobType::Boring(generator::BoringParams {
+            exit: get_bore_exit(),
+        }),
+        "facing" => generator::JobType::Facing,
+        "faceboring" => generator::JobType::FaceBoring,
+        "turning" => generator::JobType::Turning,
+        "grooving" => generator::JobType::Grooving(generator::GrooveParams {
+            width: get_f32("Groove width:    "),
+            tool_width: get_f32("Tool width  :    "),
+            overlap: get_f32("Overlap     :    "),
+            peck: get_f32("Peck depth  :    "),
+            retract: get_f32("Retract     :    "),
+        }),
+        "facegrooving" => generator::JobType::FaceGrooving(generator::FaceGrooveParams {
+            inner_diameter: get_f32("Inner dia   :    "),
+            outer_diameter: get_f32("Outer dia   :    "),
+            tool_width: get_f32("Tool width  :    "),
+            overlap: get_f32("Overlap     :    "),
+            peck: get_f32("Peck depth  :    "),
+            retract: get_f32("Retract     :    "),
+        }),
+        "parting" => generator::JobType::Parting(generator::PartParams {
+            peck: get_f32("Peck depth  :    "),
+            retract: get_f32("Retract     :    "),
+            slow_diameter: get_f32("Slow below  :    "),
+            slow_feed: get_f32("Slow feed   :    "),
+            chamfer: get_f32("Chamfer     :    "),
+        }),
+        "profile" => generator::JobType::Profile(get_contour()),
+        "taper" => generator::JobType::Taper(generator::TaperParams {
+            end: get_taper_end(),
+        }),
+        "drilling" => generator::JobType::Drilling(generator::DrillParams {
+            cycle: get_drill_cycle(),
+            peck: get_f32("Peck depth  :    "),
+            retract: get_f32("Retract Z   :    "),
+            dwell: get_f32("Dwell (s)   :    "),
+        }),
+        "tapping" => generator::JobType::Tapping(generator::TapParams {
+            pitch: get_f32("Pitch       :    "),
+            mode: get_tap_mode(),
+            retract: get_f32("Retract Z   :    "),
+            dwell: get_f32("Dwell (s)   :    "),
+        }),
+        "threading" => generator::JobType::Threading(generator::ThreadParams {
+            pitch: get_f32("Pitch       :    "),
+            major_diameter: get_f32("Major dia   :    "),
+            minor_diameter: get_f32("Minor dia   :    "),
+            thread_depth: get_f32("Thread depth:    "),
+            infeed_angle: get_f32("Infeed angle:    "),
+            spring_passes: get_i32("Spring passes:   "),
+            degression: get_f32("Degression  :    "),
+        }),
+        _ => {
+            println!("Error: job type not recognised:");
+            println!("  {}", input);
+            std::process::exit(0)
+        }
+    };
+
+    let radius = get_bool("Are you entering values as radius? (y/n):      ");
+    let depth_start = get_f32("Start  depth:    ");
+    let depth_end = get_f32("Finish depth:    ");
+    let step = get_f32("Step   size :    ");
+    let finish_step = get_f32("Finish step :    ");
+    let start_cut = get_f32("Start  cut  :    ");
+    let length = get_f32("Length      :    ");
+    let feed = get_f32("Feed rate   :    ");
+
+    let job = generator::JobParams {
+        job_type,
+        start_depth: depth_start,
+        finish_depth: depth_end,
+        step,
+        finish_step,
+        start_cut,
+        length,
+        feed,
+        tool: None,
+        rpm: None,
+        start_corner: None,
+        end_corner: None,
+        finish_feed: None,
+        finish_speed: None,
+        finishing: None,
+        pass_strategy: generator::PassStrategy::MaxStep,
+    };
+
+    let rpm = get_i32("RPM:    ");
+    let spindle_mode = if get_bool("Constant surface speed (y/n):   ") {
+        generator::SpindleMode::Css {
+            surface_speed: get_f32("Surface speed   :      "),
+            max_rpm: get_i32("Max RPM         :      "),
+        }
+    } else {
+        generator::SpindleMode::Rpm
+    };
+    let cw_dir = get_bool("Spindle CW (y/n):      ");
+    let use_inch = !get_bool("Use mm     (y/n):      ");
+    let tool = get_i32("Tool num        :      ");
+    let canned_cycles = get_bool("Canned cycles (y/n):   ");
+    let controller = get_controller();
+    let nose_compensation = get_nose_compensation();
+    let tools = match nose_compensation {
+        generator::NoseCompensation::Computed => vec![generator::Tool {
+            number: tool,
+            nose_radius: get_f32("Nose radius     :      "),
+            orientation: get_i32("Tip orientation :      "),
+        }],
+        _ => vec![],
+    };
+
+    let machine = generator::MachineParams {
+        rpm,
+        spindle_mode,
+        spindle_cw: cw_dir,
+        units_inch: use_inch,
+        tool_num: tool,
+        radius_mode: radius,
+        canned_cycles,
+        controller,
+        tools,
+        nose_compensation,
+        clearance: generator::Clearance {
+            x: get_f32("Clearance X     :      "),
+            z: get_f32("Clearance Z     :      "),
+        },
+        retract: generator::RetractMode::Stock,
+        tool_change: None,
+        rates: generator::Rates::default(),
+        limits: None,
+        chuck: None,
+        tailstock: None,
+        on_collision: generator::CollisionAction::default(),
+    };
+
+    (job, machine)
+}
+
+// println!("Spindle CW (y/n):      ");
+// io::stdin().read_line(&mut input);
+// let cw_dir = match input.as_ref() {
+//     "y" => true,
+//     "n" => false,
+//     _ => {
+//         print!("Error: spindle dir not recognised!");
+//         std::process::exit(0)
+//     }
+// };
+// println!("Use mm     (y/n):      ");
+// io::stdin().read_line(&mut input);
+// let use_inch =  match input.as_ref() {
+//     "y" => false,
+//     "n" => true,
+//     _ => {
+//         print!("Error: units not recognised!");
+//         std::process::exit(0)
+//     }
+// };
+// println!("Use radius (y/n):      ");
+// io::stdin().read_line(&mut input);
+// let radius =  match input.as_ref() {
+//     "y" => true,
+//     "n" => false,
+//     _ => {
+//         print!("Error: spindle dir not recognised!");
+//         std::process::exit(0)
+//     }
+// };
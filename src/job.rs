@@ -0,0 +1,105 @@
+// Job description files: the stock, the machine settings and an ordered list
+// of operations, read from TOML or JSON depending on the file extension.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+use crate::error::JobError;
+use crate::generator::{generate_gcode, JobParams, JobType, MachineParams};
+
+#[derive(Serialize, Deserialize)]
+pub struct Stock {
+    pub diameter: f32,
+    pub length: f32, // Stick out from the chuck face
+}
+
+impl Stock {
+    // Z of the end of the stock: Z0, or the start of the first facing cut
+    pub fn face(jobs: &[JobParams]) -> f32 {
+        jobs.iter()
+            .filter_map(|job| match job.job_type {
+                JobType::Facing => Some(job.start_depth),
+                _ => None,
+            })
+            .fold(0.0, f32::max)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Program {
+    pub stock: Stock,
+    pub machine: MachineParams,
+    pub operations: Vec<JobParams>, // Run in order, each may set its own tool and rpm
+}
+
+impl Program {
+    pub fn from_toml(text: &str) -> Result<Program, String> {
+        toml::from_str(text).map_err(|e| e.to_string())
+    }
+
+    pub fn from_json(text: &str) -> Result<Program, String> {
+        serde_json::from_str(text).map_err(|e| e.to_string())
+    }
+
+    pub fn load(path: &Path) -> Result<Program, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Program::from_json(&text),
+            _ => Program::from_toml(&text),
+        }
+        .map_err(|e| format!("couldn't parse {}: {}", path.display(), e))
+    }
+
+    pub fn generate_gcode(&self) -> Result<String, JobError> {
+        generate_gcode(&self.operations, &self.machine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHAFT: &str = include_str!("../examples/shaft.toml");
+
+    #[test]
+    fn examples_load_and_generate() {
+        for text in &[SHAFT, include_str!("../examples/spindle.toml")] {
+            let program = Program::from_toml(text).unwrap();
+            assert!(!program.operations.is_empty());
+            program.generate_gcode().unwrap();
+        }
+    }
+
+    #[test]
+    fn json_gives_the_same_program() {
+        let program = Program::from_toml(SHAFT).unwrap();
+        let json = serde_json::to_string(&program).unwrap();
+        let read = Program::from_json(&json).unwrap();
+        assert_eq!(read.operations.len(), program.operations.len());
+        assert_eq!(
+            read.generate_gcode().unwrap(),
+            program.generate_gcode().unwrap()
+        );
+    }
+
+    #[test]
+    fn stock_face_is_the_start_of_facing() {
+        let program = Program::from_toml(SHAFT).unwrap();
+        assert_eq!(Stock::face(&program.operations), 1.0);
+        assert_eq!(Stock::face(&program.operations[1..]), 0.0);
+    }
+
+    #[test]
+    fn bad_files_are_reported() {
+        let error = Program::from_toml("[stock]\ndiameter = 10.0\n")
+            .err()
+            .unwrap();
+        assert!(error.contains("length"), "{}", error);
+        let error = Program::from_json("{").err().unwrap();
+        assert!(error.contains("EOF"), "{}", error);
+        let error = Program::load(Path::new("missing.toml")).err().unwrap();
+        assert!(error.starts_with("couldn't read missing.toml"), "{}", error);
+    }
+}
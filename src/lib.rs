@@ -0,0 +1,14 @@
+pub mod backplot;
+pub mod compensation;
+pub mod cycle_time;
+pub mod emitter;
+pub mod envelope;
+pub mod error;
+pub mod generator;
+pub mod geometry;
+pub mod job;
+pub mod parser;
+pub mod post;
+pub mod profile;
+pub mod simulation;
+pub mod toolpath;
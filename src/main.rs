@@ -1,279 +1,1218 @@
-use std::error::Error;
-use std::fs::File;
-use std::io;
+extern crate structopt;
+use std::fs::{self, File};
 use std::io::prelude::*;
 use std::path::Path;
+use structopt::StructOpt;
 
-mod generator;
+use lathe::backplot::Backplot;
+use lathe::cycle_time::{section_names, Estimate};
+use lathe::emitter::emit_gcode;
+use lathe::generator;
+use lathe::job::{Program, Stock};
+use lathe::parser::read_program;
+use lathe::post::Controller;
+use lathe::simulation::simulate;
+use lathe::toolpath::Toolpath;
 
-fn write_file(gcode: String, name: String) {
-    let path = Path::new(&name);
-    let display = path.display();
+mod interactive;
 
-    let mut file = match File::create(&path) {
-        Err(why) => panic!("couldn't create {}: {}", display, why.description()),
-        Ok(file) => file,
-    };
+#[derive(StructOpt)]
+#[structopt(name = "lathe")]
+/// Script to generate lathe GCode
+struct Cli {
+    #[structopt(long = "interactive", raw(global = "true"))]
+    /// Ask for the job on stdin instead of using a subcommand
+    interactive: bool,
+    #[structopt(short = "n", long = "file-name", raw(global = "true"))]
+    /// Filename for output, prints to stdout if not given
+    fname: Option<String>,
+    #[structopt(long = "svg", raw(global = "true"))]
+    /// Also write a backplot of the toolpath to this SVG file
+    svg: Option<String>,
+    #[structopt(long = "cycle-time", raw(global = "true"))]
+    /// Print the estimated cycle time of each operation
+    cycle_time: bool,
+    #[structopt(long = "time-header", raw(global = "true"))]
+    /// Put the estimated cycle time at the start of the program as comments
+    time_header: bool,
+    #[structopt(long = "simulate", raw(global = "true"))]
+    /// Cut the stock with the toolpath and report leftover stock, gouges and rapids through stock
+    simulate: bool,
+    #[structopt(long = "resolution", default_value = "0.05", raw(global = "true"))]
+    /// Cell size of the simulated stock
+    resolution: f32,
+    #[structopt(long = "stock-diameter", raw(global = "true"))]
+    /// Stock diameter for the simulation and backplot, job files give their own
+    stock_diameter: Option<f32>,
+    #[structopt(long = "stock-length", raw(global = "true"))]
+    /// Stock stick out from the chuck face, job files give their own
+    stock_length: Option<f32>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
 
-    match file.write_all(gcode.as_bytes()) {
-        Err(why) => panic!("couldn't write to {}: {}", display, why.description()),
-        Ok(_) => println!("successfully wrote to {}", display),
+#[derive(StructOpt)]
+enum Command {
+    #[structopt(name = "job")]
+    /// Generate a multi-operation program from a TOML or JSON job file
+    Job {
+        #[structopt(parse(from_os_str))]
+        /// Job description file [REQUIRED]
+        file: std::path::PathBuf,
+    },
+    #[structopt(name = "read")]
+    /// Read back a LinuxCNC program to plot, time or rewrite it for another controller
+    Read {
+        #[structopt(parse(from_os_str))]
+        /// G-code file [REQUIRED]
+        file: std::path::PathBuf,
+        #[structopt(flatten)]
+        machine: MachineArgs,
+    },
+    #[structopt(name = "turn")]
+    /// Turn an outside diameter, cut in Z
+    Turn {
+        #[structopt(flatten)]
+        job: JobArgs,
+        #[structopt(flatten)]
+        corners: CornerArgs,
+        #[structopt(flatten)]
+        machine: MachineArgs,
+    },
+    #[structopt(name = "taper")]
+    /// Turn a taper, finish depth is X at the start of the cut
+    Taper {
+        #[structopt(flatten)]
+        job: JobArgs,
+        #[structopt(
+            long = "end-diameter",
+            raw(conflicts_with_all = r#"&["angle", "per_foot", "morse"]"#)
+        )]
+        /// X at the end of the cut
+        end_diameter: Option<f32>,
+        #[structopt(long = "angle", raw(conflicts_with_all = r#"&["per_foot", "morse"]"#))]
+        /// Included angle in degrees
+        angle: Option<f32>,
+        #[structopt(long = "tpf", raw(conflicts_with = r#""morse""#))]
+        /// Taper per foot
+        per_foot: Option<f32>,
+        #[structopt(long = "morse")]
+        /// Morse taper number 0-7
+        morse: Option<u8>,
+        #[structopt(flatten)]
+        machine: MachineArgs,
+    },
+    #[structopt(name = "profile")]
+    /// Rough and finish a contour of lines and arcs (G71/G70)
+    Profile {
+        #[structopt(long = "contour")]
+        /// Contour blocks, the first is the start: "X10 Z0; X12 Z-1; X16 Z-22 R2 G3" [REQUIRED]
+        contour: generator::ProfileParams,
+        #[structopt(short = "i", long = "start-depth")]
+        /// Stock diameter [REQUIRED]
+        start_depth: f32,
+        #[structopt(short = "c", long = "cut-depth")]
+        /// Roughing cut depth (DOC) [REQUIRED]
+        step: f32,
+        #[structopt(short = "s", long = "finish-cut-depth", default_value = "0")]
+        /// Stock left for the finishing pass
+        finish_step: f32,
+        #[structopt(short = "r", long = "feed-rate")]
+        /// Feed rate [REQUIRED]
+        feed: f32,
+        #[structopt(long = "finish-feed")]
+        /// Feed rate for the finishing pass, defaults to the feed rate
+        finish_feed: Option<f32>,
+        #[structopt(long = "finish-speed")]
+        /// RPM for the finishing pass, surface speed with --constant-surface
+        finish_speed: Option<f32>,
+        #[structopt(flatten)]
+        machine: MachineArgs,
+    },
+    #[structopt(name = "groove")]
+    /// Plunge a groove with a grooving blade, wider grooves take several plunges
+    Groove {
+        #[structopt(short = "i", long = "start-depth")]
+        /// Diameter the groove is cut from [REQUIRED]
+        start_depth: f32,
+        #[structopt(short = "f", long = "finish-depth")]
+        /// Diameter at the bottom of the groove [REQUIRED]
+        finish_depth: f32,
+        #[structopt(short = "s", long = "finish-cut-depth", default_value = "0")]
+        /// Stock left on the floor for a finishing pass
+        finish_step: f32,
+        #[structopt(short = "z", long = "z-begin", default_value = "0")]
+        /// Z of the groove's tailstock side wall
+        start_cut: f32,
+        #[structopt(long = "width")]
+        /// Groove width towards the chuck [REQUIRED]
+        width: f32,
+        #[structopt(long = "tool-width")]
+        /// Blade width [REQUIRED]
+        tool_width: f32,
+        #[structopt(long = "overlap", default_value = "0")]
+        /// Overlap between plunges
+        overlap: f32,
+        #[structopt(long = "peck", default_value = "0")]
+        /// Peck depth, 0 plunges in one go
+        peck: f32,
+        #[structopt(long = "retract", default_value = "0.5")]
+        /// Back off after each peck
+        retract: f32,
+        #[structopt(short = "r", long = "feed-rate")]
+        /// Feed rate [REQUIRED]
+        feed: f32,
+        #[structopt(flatten)]
+        machine: MachineArgs,
+    },
+    #[structopt(name = "face-groove")]
+    /// Plunge an axial groove into the face, wider grooves take several plunges
+    FaceGroove {
+        #[structopt(short = "i", long = "start-depth", default_value = "0")]
+        /// Z of the face the groove is cut from
+        start_depth: f32,
+        #[structopt(short = "f", long = "finish-depth")]
+        /// Z at the bottom of the groove [REQUIRED]
+        finish_depth: f32,
+        #[structopt(short = "s", long = "finish-cut-depth", default_value = "0")]
+        /// Stock left on the floor for a finishing pass
+        finish_step: f32,
+        #[structopt(long = "inner")]
+        /// Inner diameter of the groove [REQUIRED]
+        inner_diameter: f32,
+        #[structopt(long = "outer")]
+        /// Outer diameter of the groove [REQUIRED]
+        outer_diameter: f32,
+        #[structopt(long = "tool-width")]
+        /// Blade width [REQUIRED]
+        tool_width: f32,
+        #[structopt(long = "overlap", default_value = "0")]
+        /// Overlap between plunges
+        overlap: f32,
+        #[structopt(long = "peck", default_value = "0")]
+        /// Peck depth, 0 plunges in one go
+        peck: f32,
+        #[structopt(long = "retract", default_value = "0.5")]
+        /// Back off after each peck
+        retract: f32,
+        #[structopt(short = "r", long = "feed-rate")]
+        /// Feed rate [REQUIRED]
+        feed: f32,
+        #[structopt(flatten)]
+        machine: MachineArgs,
+    },
+    #[structopt(name = "part")]
+    /// Part off with a parting blade
+    Part {
+        #[structopt(short = "i", long = "start-depth")]
+        /// Diameter of the bar [REQUIRED]
+        start_depth: f32,
+        #[structopt(short = "f", long = "stop-diameter", default_value = "0")]
+        /// Stop short at this diameter, 0 parts through
+        finish_depth: f32,
+        #[structopt(short = "z", long = "z-begin", default_value = "0")]
+        /// Z of the blade's tailstock side, the part is beyond it
+        start_cut: f32,
+        #[structopt(long = "peck", default_value = "0")]
+        /// Peck depth, 0 plunges in one go
+        peck: f32,
+        #[structopt(long = "retract", default_value = "0.5")]
+        /// Back off after each peck
+        retract: f32,
+        #[structopt(long = "slow-diameter", default_value = "0")]
+        /// Below this diameter the feed drops to --slow-feed
+        slow_diameter: f32,
+        #[structopt(long = "slow-feed", default_value = "0")]
+        /// Feed rate near the centre, defaults to the feed rate
+        slow_feed: f32,
+        #[structopt(long = "chamfer", default_value = "0")]
+        /// Chamfer the part before it comes off
+        chamfer: f32,
+        #[structopt(short = "r", long = "feed-rate")]
+        /// Feed rate [REQUIRED]
+        feed: f32,
+        #[structopt(flatten)]
+        machine: MachineArgs,
+    },
+    #[structopt(name = "face")]
+    /// Face the end of the part, cut in X
+    Face {
+        #[structopt(flatten)]
+        job: JobArgs,
+        #[structopt(flatten)]
+        corners: CornerArgs,
+        #[structopt(flatten)]
+        machine: MachineArgs,
+    },
+    #[structopt(name = "face-bore")]
+    /// Face bore, cut in X
+    FaceBore {
+        #[structopt(flatten)]
+        job: JobArgs,
+        #[structopt(flatten)]
+        machine: MachineArgs,
+    },
+    #[structopt(name = "bore")]
+    /// Bore an inside diameter from a pilot hole, cut in Z
+    Bore {
+        #[structopt(flatten)]
+        job: JobArgs,
+        #[structopt(
+            long = "exit",
+            default_value = "retract",
+            raw(possible_values = r#"&["retract", "feedout", "dwell"]"#)
+        )]
+        /// How each pass leaves the bore
+        exit: String,
+        #[structopt(long = "dwell", default_value = "0")]
+        /// Dwell at the bottom in seconds, used with --exit dwell
+        dwell: f32,
+        #[structopt(flatten)]
+        machine: MachineArgs,
+    },
+    #[structopt(name = "drill")]
+    /// Drill on the spindle centreline
+    Drill {
+        #[structopt(
+            long = "cycle",
+            default_value = "simple",
+            raw(possible_values = r#"&["simple", "dwell", "peck", "chipbreak"]"#)
+        )]
+        /// Drilling cycle
+        cycle: generator::DrillCycle,
+        #[structopt(short = "z", long = "z-begin", default_value = "0")]
+        /// Z value of the hole top
+        start_z: f32,
+        #[structopt(short = "l", long = "length")]
+        /// Depth of the hole [REQUIRED]
+        length: f32,
+        #[structopt(short = "r", long = "feed-rate")]
+        /// Feed rate [REQUIRED]
+        feed: f32,
+        #[structopt(long = "retract")]
+        /// Retract plane, absolute Z [REQUIRED]
+        retract: f32,
+        #[structopt(long = "peck", default_value = "0")]
+        /// Peck depth for peck and chipbreak cycles
+        peck: f32,
+        #[structopt(long = "dwell", default_value = "0")]
+        /// Dwell at the bottom in seconds
+        dwell: f32,
+        #[structopt(flatten)]
+        machine: MachineArgs,
+    },
+    #[structopt(name = "tap")]
+    /// Tap on the spindle centreline, the thread hand follows the spindle direction
+    Tap {
+        #[structopt(short = "p", long = "pitch")]
+        /// Thread pitch [REQUIRED]
+        pitch: f32,
+        #[structopt(
+            long = "mode",
+            default_value = "rigid",
+            raw(possible_values = r#"&["rigid", "floating"]"#)
+        )]
+        /// Rigid (G33.1) or floating holder
+        mode: generator::TapMode,
+        #[structopt(short = "z", long = "z-begin", default_value = "0")]
+        /// Z value of the hole top
+        start_z: f32,
+        #[structopt(short = "l", long = "length")]
+        /// Depth of the thread [REQUIRED]
+        length: f32,
+        #[structopt(long = "retract")]
+        /// Start and return plane, absolute Z [REQUIRED]
+        retract: f32,
+        #[structopt(long = "dwell", default_value = "0")]
+        /// Floating holder dwell at the bottom in seconds
+        dwell: f32,
+        #[structopt(flatten)]
+        machine: MachineArgs,
+    },
+    #[structopt(name = "thread")]
+    /// Single point threading
+    Thread {
+        #[structopt(short = "p", long = "pitch")]
+        /// Thread pitch [REQUIRED]
+        pitch: f32,
+        #[structopt(long = "major")]
+        /// Major diameter [REQUIRED]
+        major_diameter: f32,
+        #[structopt(long = "minor")]
+        /// Minor diameter [REQUIRED]
+        minor_diameter: f32,
+        #[structopt(long = "thread-depth")]
+        /// Full thread depth, radial [REQUIRED]
+        thread_depth: f32,
+        #[structopt(short = "c", long = "first-cut")]
+        /// Depth of the first pass, radial [REQUIRED]
+        first_cut: f32,
+        #[structopt(long = "infeed-angle", default_value = "29.5")]
+        /// Compound infeed angle in degrees
+        infeed_angle: f32,
+        #[structopt(long = "spring-passes", default_value = "1")]
+        /// Extra passes at full depth
+        spring_passes: i32,
+        #[structopt(long = "finish-passes", raw(use_delimiter = "true"))]
+        /// Radial depths of finishing passes after the degressive ones: 0.05,0.02
+        finish_passes: Vec<f32>,
+        #[structopt(long = "degression", default_value = "1.0")]
+        /// 1.0 for constant depth passes, 2.0 for constant area
+        degression: f32,
+        #[structopt(short = "z", long = "z-begin", default_value = "0")]
+        /// Z value that threading begins at
+        start_z: f32,
+        #[structopt(short = "l", long = "length")]
+        /// Length of thread in negative Z direction [REQUIRED]
+        length: f32,
+        #[structopt(flatten)]
+        machine: MachineArgs,
+    },
+}
+
+#[derive(StructOpt)]
+struct JobArgs {
+    #[structopt(short = "i", long = "start-depth")]
+    /// Initial dimension: stock OD, pilot hole or face Z [REQUIRED]
+    start_depth: f32,
+    #[structopt(short = "f", long = "finish-depth")]
+    /// Final dimension [REQUIRED]
+    finish_depth: f32,
+    #[structopt(short = "c", long = "cut-depth")]
+    /// Roughing cut depth (DOC) [REQUIRED]
+    step: f32,
+    #[structopt(short = "s", long = "finish-cut-depth", default_value = "0")]
+    /// Finishing cut depth (DOC)
+    finish_step: f32,
+    #[structopt(short = "z", long = "z-begin", default_value = "0")]
+    /// Value the cut begins at, Z for turning and boring, X for facing
+    start_cut: f32,
+    #[structopt(short = "l", long = "length")]
+    /// Length of cut, -ve implies cutting dir is away from chuck [REQUIRED]
+    length: f32,
+    #[structopt(short = "r", long = "feed-rate")]
+    /// Feed rate [REQUIRED]
+    feed: f32,
+    #[structopt(long = "finish-feed")]
+    /// Feed rate for the finishing pass, defaults to the feed rate
+    finish_feed: Option<f32>,
+    #[structopt(long = "finish-speed")]
+    /// RPM for the finishing pass, surface speed with --constant-surface
+    finish_speed: Option<f32>,
+    #[structopt(long = "finish-passes", raw(use_delimiter = "true"))]
+    /// Depths of several finishing passes, last is the final pass: 0.2,0.05
+    finish_passes: Vec<f32>,
+    #[structopt(long = "spring-passes", default_value = "0")]
+    /// Repeats of the final pass without any depth
+    spring_passes: i32,
+    #[structopt(
+        long = "passes",
+        default_value = "maxstep",
+        raw(possible_values = r#"&["equal", "maxstep", "degressive"]"#)
+    )]
+    /// Roughing passes: all equal, full steps with the remainder first, or lighter with depth
+    passes: String,
+    #[structopt(long = "degression", default_value = "2.0")]
+    /// Used with --passes degressive, 1.0 to 3.0: passes shrink from a full step to half a step, sooner the higher it is
+    degression: f32,
+}
+
+#[derive(StructOpt)]
+struct CornerArgs {
+    #[structopt(long = "start-chamfer", raw(conflicts_with = r#""start_radius""#))]
+    /// Chamfer size at the start of the cut, along the cut
+    start_chamfer: Option<f32>,
+    #[structopt(long = "start-radius")]
+    /// Corner radius at the start of the cut
+    start_radius: Option<f32>,
+    #[structopt(long = "end-chamfer", raw(conflicts_with = r#""end_radius""#))]
+    /// Chamfer size where the cut meets the shoulder
+    end_chamfer: Option<f32>,
+    #[structopt(long = "end-radius")]
+    /// Fillet radius where the cut meets the shoulder
+    end_radius: Option<f32>,
+    #[structopt(long = "chamfer-angle", default_value = "45")]
+    /// Chamfer angle in degrees from the cut surface
+    chamfer_angle: f32,
+}
+
+#[derive(StructOpt)]
+struct MachineArgs {
+    #[structopt(long = "rpm", default_value = "0")]
+    /// Spindle RPM, also used for threading and drilling in constant surface mode
+    rpm: i32,
+    #[structopt(long = "constant-surface")]
+    /// Use constant surface instead of RPM - G96. Default is RPM mode - G97
+    surface_mode: bool,
+    #[structopt(long = "ss", default_value = "0")]
+    /// Surface Speed, m/min or ft/min
+    surface_speed: f32,
+    #[structopt(long = "max-rpm", default_value = "0")]
+    /// Spindle speed clamp in constant surface mode
+    max_rpm: i32,
+    #[structopt(long = "ccw")]
+    /// Spindle direction. This will add M4 - counter clockwise rotation. M3 is default
+    ccw: bool,
+    #[structopt(long = "inch")]
+    /// Use inch as unit of measure - G20. Default is mm - G21
+    inch: bool,
+    #[structopt(long = "diameter")]
+    /// Use diameter mode - G7. Default is radius mode - G8
+    diameter_mode: bool,
+    #[structopt(short = "t", long = "tool", default_value = "0")]
+    /// Tool number to use
+    tool: i32,
+    #[structopt(long = "no-canned-cycles")]
+    /// Expand canned cycles (G76, G8x) into explicit moves
+    no_canned_cycles: bool,
+    #[structopt(
+        long = "post",
+        default_value = "linuxcnc",
+        raw(possible_values = r#"&["linuxcnc", "fanuc", "grbl"]"#)
+    )]
+    /// Controller dialect of the output
+    controller: Controller,
+    #[structopt(
+        long = "nose-comp",
+        default_value = "off",
+        raw(possible_values = r#"&["off", "controller", "computed"]"#)
+    )]
+    /// Nose radius compensation on finishing passes: G41/G42 or a computed offset path
+    nose_compensation: generator::NoseCompensation,
+    #[structopt(long = "nose-radius", default_value = "0")]
+    /// Tool nose radius
+    nose_radius: f32,
+    #[structopt(long = "orientation", default_value = "3")]
+    /// Tool tip orientation 1-9
+    orientation: i32,
+    #[structopt(long = "clearance-x", default_value = "1")]
+    /// Clearance from the stock in X, in program units
+    clearance_x: f32,
+    #[structopt(long = "clearance-z", default_value = "1")]
+    /// Clearance from the stock in Z
+    clearance_z: f32,
+    #[structopt(
+        long = "retract-mode",
+        default_value = "stock",
+        raw(possible_values = r#"&["stock", "diameter", "previous", "diagonal"]"#)
+    )]
+    /// Roughing retract: clear of the stock, to --safe-diameter, above the previous pass or at 45 degrees
+    retract_mode: String,
+    #[structopt(
+        long = "safe-diameter",
+        raw(required_if = r#""retract_mode", "diameter""#)
+    )]
+    /// X to retract to, needed with --retract-mode diameter
+    safe_diameter: Option<f32>,
+    #[structopt(long = "tool-change")]
+    /// Tool change position at the start and end of the program: g28, g30 or "X100 Z50"
+    tool_change: Option<generator::ToolChangePosition>,
+    #[structopt(long = "rapid-x", default_value = "5000")]
+    /// X rapid rate per minute, radial, for the cycle time estimate
+    rapid_x: f32,
+    #[structopt(long = "rapid-z", default_value = "5000")]
+    /// Z rapid rate per minute, for the cycle time estimate
+    rapid_z: f32,
+    #[structopt(long = "acceleration", default_value = "500")]
+    /// Axis acceleration per second squared, for the cycle time estimate
+    acceleration: f32,
+    #[structopt(long = "tool-change-time", default_value = "3")]
+    /// Seconds per tool change, for the cycle time estimate
+    tool_change_time: f32,
+    #[structopt(
+        long = "travel-x",
+        raw(number_of_values = "2", allow_hyphen_values = "true")
+    )]
+    /// X travel of the machine as MIN MAX, in program units
+    travel_x: Vec<f32>,
+    #[structopt(
+        long = "travel-z",
+        raw(number_of_values = "2", allow_hyphen_values = "true")
+    )]
+    /// Z travel of the machine as MIN MAX
+    travel_z: Vec<f32>,
+    #[structopt(long = "chuck")]
+    /// Chuck jaws to keep clear of: "X60 Z-40" for the X over the jaws and Z of their faces
+    chuck: Option<generator::Chuck>,
+    #[structopt(long = "tailstock")]
+    /// Tailstock to keep clear of: "X20 Z5" for the X over the quill and Z of the centre point
+    tailstock: Option<generator::Tailstock>,
+    #[structopt(long = "warn-collisions")]
+    /// Warn about moves beyond the travel or into the chuck or tailstock instead of refusing
+    warn_collisions: bool,
+}
+
+impl JobArgs {
+    fn job(self, job_type: generator::JobType) -> generator::JobParams {
+        let pass_strategy = match self.passes.as_ref() {
+            "equal" => generator::PassStrategy::Equal,
+            "degressive" => generator::PassStrategy::Degressive(self.degression),
+            _ => generator::PassStrategy::MaxStep,
+        };
+        generator::JobParams {
+            job_type,
+            start_depth: self.start_depth,
+            finish_depth: self.finish_depth,
+            step: self.step,
+            finish_step: self.finish_step,
+            start_cut: self.start_cut,
+            length: self.length,
+            feed: self.feed,
+            tool: None,
+            rpm: None,
+            start_corner: None,
+            end_corner: None,
+            finish_feed: self.finish_feed,
+            finish_speed: self.finish_speed,
+            finishing: if self.finish_passes.is_empty() && self.spring_passes == 0 {
+                None
+            } else if self.finish_passes.is_empty() {
+                Some(generator::FinishSchedule {
+                    depths: vec![self.finish_step],
+                    spring_passes: self.spring_passes,
+                })
+            } else {
+                Some(generator::FinishSchedule {
+                    depths: self.finish_passes,
+                    spring_passes: self.spring_passes,
+                })
+            },
+            pass_strategy,
+        }
     }
 }
 
-// fn get_string() -> io::Result<String> {
-//     let mut buffer = String::new();
-//
-//     io::stdin().read_line(&mut buffer)?;
-//
-//     Ok(buffer)
-// }
+impl CornerArgs {
+    fn corner(&self, chamfer: Option<f32>, radius: Option<f32>) -> Option<generator::Corner> {
+        match (chamfer, radius) {
+            (Some(size), _) => Some(generator::Corner::Chamfer {
+                size,
+                angle: self.chamfer_angle,
+            }),
+            (None, Some(radius)) => Some(generator::Corner::Radius(radius)),
+            (None, None) => None,
+        }
+    }
 
-fn get_string(s: &str) -> String {
-    //https://doc.rust-lang.org/std/io/type.Result.html
-    let mut input = String::new();
-    println!("{}", s);
-    io::stdin().read_line(&mut input);
-    String::from(input.trim())
+    fn apply(self, mut job: generator::JobParams) -> generator::JobParams {
+        job.start_corner = self.corner(self.start_chamfer, self.start_radius);
+        job.end_corner = self.corner(self.end_chamfer, self.end_radius);
+        job
+    }
 }
 
-fn get_bool(s: &str) -> bool {
-    let input = get_string(s);
-    let mut bool_val: Option<bool> = match input.as_ref() {
-        "y" => Some(true),
-        "n" => Some(false),
-        _ => None,
-    };
-    while bool_val == None {
-        bool_val = Some(get_bool(s));
+impl MachineArgs {
+    fn machine(self) -> generator::MachineParams {
+        let spindle_mode = if self.surface_mode {
+            generator::SpindleMode::Css {
+                surface_speed: self.surface_speed,
+                max_rpm: self.max_rpm,
+            }
+        } else {
+            generator::SpindleMode::Rpm
+        };
+        generator::MachineParams {
+            rpm: self.rpm,
+            spindle_mode,
+            spindle_cw: !self.ccw,
+            units_inch: self.inch,
+            tool_num: self.tool,
+            radius_mode: !self.diameter_mode,
+            canned_cycles: !self.no_canned_cycles,
+            controller: self.controller,
+            tools: vec![generator::Tool {
+                number: self.tool,
+                nose_radius: self.nose_radius,
+                orientation: self.orientation,
+            }],
+            nose_compensation: self.nose_compensation,
+            clearance: generator::Clearance {
+                x: self.clearance_x,
+                z: self.clearance_z,
+            },
+            retract: match (self.retract_mode.as_ref(), self.safe_diameter) {
+                ("diameter", Some(x)) => generator::RetractMode::Diameter(x),
+                ("previous", _) => generator::RetractMode::Previous,
+                ("diagonal", _) => generator::RetractMode::Diagonal,
+                _ => generator::RetractMode::Stock,
+            },
+            tool_change: self.tool_change,
+            rates: generator::Rates {
+                rapid_x: self.rapid_x,
+                rapid_z: self.rapid_z,
+                acceleration: self.acceleration,
+                tool_change: self.tool_change_time,
+            },
+            // An axis without limits can go anywhere
+            limits: match (&self.travel_x[..], &self.travel_z[..]) {
+                (&[], &[]) => None,
+                (x, z) => Some(generator::Limits {
+                    x_min: x.first().cloned().unwrap_or(-f32::INFINITY),
+                    x_max: x.get(1).cloned().unwrap_or(f32::INFINITY),
+                    z_min: z.first().cloned().unwrap_or(-f32::INFINITY),
+                    z_max: z.get(1).cloned().unwrap_or(f32::INFINITY),
+                }),
+            },
+            chuck: self.chuck,
+            tailstock: self.tailstock,
+            on_collision: if self.warn_collisions {
+                generator::CollisionAction::Warn
+            } else {
+                generator::CollisionAction::Refuse
+            },
+        }
     }
-    bool_val.unwrap()
 }
 
-fn get_f32(s: &str) -> f32 {
-    let input = get_string(s);
-    input.parse::<f32>().unwrap()
+// What the program is made from
+enum Source {
+    Program(Toolpath), // Read back, a single section
+    Jobs(Vec<generator::JobParams>, Option<Stock>),
 }
 
-fn get_i32(s: &str) -> i32 {
-    let input = get_string(s);
-    input.parse::<i32>().unwrap()
+fn read_file(file: &Path) -> Toolpath {
+    match fs::read_to_string(file)
+        .map_err(|e| format!("couldn't read {}: {}", file.display(), e))
+        .and_then(|text| read_program(&text))
+    {
+        Ok(path) => path,
+        Err(why) => {
+            eprintln!("Error: {}", why);
+            std::process::exit(1)
+        }
+    }
 }
 
-fn main() {
-    // Check args
-    let input = get_string("Job type:    ");
-    let job_type: generator::JobType = match input.trim().to_lowercase().as_ref() {
-        "boring" => generator::JobType::Boring,
-        "facing" => generator::JobType::Facing,
-        "faceboring" => generator::JobType::FaceBoring,
-        "turning" => generator::JobType::Turning,
-        "drilling" => generator::JobType::Drilling,
-        _ => {
-            println!("Error: job type not recognised:");
-            println!("  {}", input);
-            std::process::exit(0)
+fn command_source(command: Command) -> (Source, generator::MachineParams) {
+    let (jobs, machine, stock) = match command {
+        // Already a toolpath, there is nothing to generate
+        Command::Read { file, machine } => {
+            return (Source::Program(read_file(&file)), machine.machine())
+        }
+        Command::Job { file } => match Program::load(&file) {
+            Ok(program) => (program.operations, program.machine, Some(program.stock)),
+            Err(why) => {
+                eprintln!("Error: {}", why);
+                std::process::exit(1)
+            }
+        },
+        Command::Turn {
+            job,
+            corners,
+            machine,
+        } => (
+            vec![corners.apply(job.job(generator::JobType::Turning))],
+            machine.machine(),
+            None,
+        ),
+        Command::Taper {
+            job,
+            end_diameter,
+            angle,
+            per_foot,
+            morse,
+            machine,
+        } => {
+            let end = match (end_diameter, angle, per_foot, morse) {
+                (Some(x), _, _, _) => generator::TaperEnd::Diameter(x),
+                (_, Some(angle), _, _) => generator::TaperEnd::Angle(angle),
+                (_, _, Some(per_foot), _) => generator::TaperEnd::PerFoot(per_foot),
+                (_, _, _, Some(number)) => generator::TaperEnd::Morse(number),
+                _ => {
+                    eprintln!("Error: taper needs --end-diameter, --angle, --tpf or --morse");
+                    std::process::exit(1)
+                }
+            };
+            let taper = generator::TaperParams { end };
+            (
+                vec![job.job(generator::JobType::Taper(taper))],
+                machine.machine(),
+                None,
+            )
+        }
+        Command::Profile {
+            contour,
+            start_depth,
+            step,
+            finish_step,
+            feed,
+            finish_feed,
+            finish_speed,
+            machine,
+        } => {
+            let job = generator::JobParams {
+                job_type: generator::JobType::Profile(contour),
+                start_depth,
+                finish_depth: 0.0,
+                step,
+                finish_step,
+                start_cut: 0.0,
+                length: 0.0,
+                feed,
+                tool: None,
+                rpm: None,
+                start_corner: None,
+                end_corner: None,
+                finish_feed,
+                finish_speed,
+                finishing: None,
+                pass_strategy: generator::PassStrategy::MaxStep,
+            };
+            (vec![job], machine.machine(), None)
+        }
+        Command::Groove {
+            start_depth,
+            finish_depth,
+            finish_step,
+            start_cut,
+            width,
+            tool_width,
+            overlap,
+            peck,
+            retract,
+            feed,
+            machine,
+        } => {
+            let groove = generator::GrooveParams {
+                width,
+                tool_width,
+                overlap,
+                peck,
+                retract,
+            };
+            let job = generator::JobParams {
+                job_type: generator::JobType::Grooving(groove),
+                start_depth,
+                finish_depth,
+                step: peck,
+                finish_step,
+                start_cut,
+                length: 0.0,
+                feed,
+                tool: None,
+                rpm: None,
+                start_corner: None,
+                end_corner: None,
+                finish_feed: None,
+                finish_speed: None,
+                finishing: None,
+                pass_strategy: generator::PassStrategy::MaxStep,
+            };
+            (vec![job], machine.machine(), None)
+        }
+        Command::FaceGroove {
+            start_depth,
+            finish_depth,
+            finish_step,
+            inner_diameter,
+            outer_diameter,
+            tool_width,
+            overlap,
+            peck,
+            retract,
+            feed,
+            machine,
+        } => {
+            let groove = generator::FaceGrooveParams {
+                inner_diameter,
+                outer_diameter,
+                tool_width,
+                overlap,
+                peck,
+                retract,
+            };
+            let job = generator::JobParams {
+                job_type: generator::JobType::FaceGrooving(groove),
+                start_depth,
+                finish_depth,
+                step: peck,
+                finish_step,
+                start_cut: outer_diameter,
+                length: 0.0,
+                feed,
+                tool: None,
+                rpm: None,
+                start_corner: None,
+                end_corner: None,
+                finish_feed: None,
+                finish_speed: None,
+                finishing: None,
+                pass_strategy: generator::PassStrategy::MaxStep,
+            };
+            (vec![job], machine.machine(), None)
+        }
+        Command::Part {
+            start_depth,
+            finish_depth,
+            start_cut,
+            peck,
+            retract,
+            slow_diameter,
+            slow_feed,
+            chamfer,
+            feed,
+            machine,
+        } => {
+            let part = generator::PartParams {
+                peck,
+                retract,
+                slow_diameter,
+                slow_feed: if slow_feed > 0.0 { slow_feed } else { feed },
+                chamfer,
+            };
+            let job = generator::JobParams {
+                job_type: generator::JobType::Parting(part),
+                start_depth,
+                finish_depth,
+                step: peck,
+                finish_step: 0.0,
+                start_cut,
+                length: 0.0,
+                feed,
+                tool: None,
+                rpm: None,
+                start_corner: None,
+                end_corner: None,
+                finish_feed: None,
+                finish_speed: None,
+                finishing: None,
+                pass_strategy: generator::PassStrategy::MaxStep,
+            };
+            (vec![job], machine.machine(), None)
+        }
+        Command::Face {
+            job,
+            corners,
+            machine,
+        } => (
+            vec![corners.apply(job.job(generator::JobType::Facing))],
+            machine.machine(),
+            None,
+        ),
+        Command::FaceBore { job, machine } => (
+            vec![job.job(generator::JobType::FaceBoring)],
+            machine.machine(),
+            None,
+        ),
+        Command::Bore {
+            job,
+            exit,
+            dwell,
+            machine,
+        } => {
+            let exit = match exit.as_ref() {
+                "feedout" => generator::BoreExit::FeedOut,
+                "dwell" => generator::BoreExit::Dwell(dwell),
+                _ => generator::BoreExit::Retract,
+            };
+            let boring = generator::BoringParams { exit };
+            (
+                vec![job.job(generator::JobType::Boring(boring))],
+                machine.machine(),
+                None,
+            )
+        }
+        Command::Drill {
+            cycle,
+            start_z,
+            length,
+            feed,
+            retract,
+            peck,
+            dwell,
+            machine,
+        } => {
+            let drill = generator::DrillParams {
+                cycle,
+                peck,
+                retract,
+                dwell,
+            };
+            let job = generator::JobParams {
+                job_type: generator::JobType::Drilling(drill),
+                start_depth: 0.0,
+                finish_depth: 0.0,
+                step: peck,
+                finish_step: 0.0,
+                start_cut: start_z,
+                length,
+                feed,
+                tool: None,
+                rpm: None,
+                start_corner: None,
+                end_corner: None,
+                finish_feed: None,
+                finish_speed: None,
+                finishing: None,
+                pass_strategy: generator::PassStrategy::MaxStep,
+            };
+            (vec![job], machine.machine(), None)
+        }
+        Command::Tap {
+            pitch,
+            mode,
+            start_z,
+            length,
+            retract,
+            dwell,
+            machine,
+        } => {
+            let tap = generator::TapParams {
+                pitch,
+                mode,
+                retract,
+                dwell,
+            };
+            let job = generator::JobParams {
+                job_type: generator::JobType::Tapping(tap),
+                start_depth: 0.0,
+                finish_depth: 0.0,
+                step: 0.0,
+                finish_step: 0.0,
+                start_cut: start_z,
+                length,
+                feed: 0.0,
+                tool: None,
+                rpm: None,
+                start_corner: None,
+                end_corner: None,
+                finish_feed: None,
+                finish_speed: None,
+                finishing: None,
+                pass_strategy: generator::PassStrategy::MaxStep,
+            };
+            (vec![job], machine.machine(), None)
+        }
+        Command::Thread {
+            pitch,
+            major_diameter,
+            minor_diameter,
+            thread_depth,
+            first_cut,
+            infeed_angle,
+            spring_passes,
+            finish_passes,
+            degression,
+            start_z,
+            length,
+            machine,
+        } => {
+            let finishing = if finish_passes.is_empty() {
+                None
+            } else {
+                Some(generator::FinishSchedule {
+                    depths: finish_passes,
+                    spring_passes,
+                })
+            };
+            let thread = generator::ThreadParams {
+                pitch,
+                major_diameter,
+                minor_diameter,
+                thread_depth,
+                infeed_angle,
+                spring_passes,
+                degression,
+            };
+            let job = generator::JobParams {
+                job_type: generator::JobType::Threading(thread),
+                start_depth: major_diameter,
+                finish_depth: minor_diameter,
+                step: first_cut,
+                finish_step: 0.0,
+                start_cut: start_z,
+                length,
+                feed: 0.0,
+                tool: None,
+                rpm: None,
+                start_corner: None,
+                end_corner: None,
+                finish_feed: None,
+                finish_speed: None,
+                finishing,
+                pass_strategy: generator::PassStrategy::MaxStep,
+            };
+            (vec![job], machine.machine(), None)
         }
     };
+    (Source::Jobs(jobs, stock), machine)
+}
 
+// The status goes to stderr, the program may be going to stdout
+fn write_file(gcode: String, name: String) {
+    let path = Path::new(&name);
+    let display = path.display();
 
-    let radius = get_bool("Are you entering values as radius? (y/n):      ");
-    let depth_start = get_f32("Start  depth:    ");
-    let depth_end = get_f32("Finish depth:    ");
-    let step = get_f32("Step   size :    ");
-    let finish_step = get_f32("Finish step :    ");
-    let start_cut = get_f32("Start  cut  :    ");
-    let length = get_f32("Length      :    ");
-    let feed = get_f32("Feed rate   :    ");
-
-    let job = generator::JobParams {
-        job_type: job_type,
-        start_depth: depth_start,
-        finish_depth: depth_end,
-        step: step,
-        finish_step: finish_step,
-        start_cut: start_cut,
-        length: length,
-        feed: feed,
+    let mut file = match File::create(path) {
+        Err(why) => panic!("couldn't create {}: {}", display, why),
+        Ok(file) => file,
     };
 
-    let rpm = get_i32("RPM:    ");
-    let cw_dir = get_bool("Spindle CW (y/n):      ");
-    let use_inch = !get_bool("Use mm     (y/n):      ");
-    let tool = get_i32("Tool num        :      ");
+    match file.write_all(gcode.as_bytes()) {
+        Err(why) => panic!("couldn't write to {}: {}", display, why),
+        Ok(_) => eprintln!("successfully wrote to {}", display),
+    }
+}
+
+fn main() {
+    let args = Cli::from_args();
+
+    let (source, machine) = match args.command {
+        _ if args.interactive => {
+            let (job, machine) = interactive::get_job();
+            (Source::Jobs(vec![job], None), machine)
+        }
+        Some(command) => command_source(command),
+        None => {
+            Cli::clap().print_help().expect("couldn't print help");
+            println!();
+            std::process::exit(0)
+        }
+    };
 
-    let machine = generator::MachineParams {
-        rpm: rpm,
-        spindle_cw: cw_dir,
-        units_inch: use_inch,
-        tool_num: tool,
-        radius_mode: radius,
+    // A program read back is a single section, generated ones are checked
+    // against the machine envelope as they are generated
+    let (jobs, stock, generated) = match source {
+        Source::Program(path) => {
+            let sections = vec![path];
+            let generated = generator::check_envelope(&sections, &machine)
+                .map(|warnings| (vec!["program".to_string()], sections, warnings));
+            (vec![], None, generated)
+        }
+        Source::Jobs(jobs, stock) => {
+            let generated = generator::generate_sections(&jobs, &machine)
+                .map(|(sections, warnings)| (section_names(&jobs), sections, warnings));
+            (jobs, stock, generated)
+        }
+    };
+    let (names, sections) = match generated {
+        Ok((names, sections, warnings)) => {
+            for warning in warnings {
+                eprintln!("Warning: {}", warning);
+            }
+            (names, sections)
+        }
+        Err(why) => {
+            eprintln!("Error: {}", why);
+            std::process::exit(1)
+        }
+    };
+    let estimate = Estimate::new(names, &sections, &machine.rates);
+    let stock = match (args.stock_diameter, args.stock_length) {
+        (Some(diameter), Some(length)) => Some(Stock { diameter, length }),
+        _ => stock,
+    };
+    if args.simulate {
+        match stock {
+            Some(ref stock) => {
+                let findings = match simulate(&sections, &jobs, &machine, stock, args.resolution) {
+                    Ok(findings) => findings,
+                    Err(why) => {
+                        eprintln!("Error: {}", why);
+                        std::process::exit(1)
+                    }
+                };
+                if findings.is_empty() {
+                    eprintln!("Simulation found no problems");
+                }
+                for finding in findings {
+                    eprintln!("Warning: {}", finding);
+                }
+            }
+            None => {
+                eprintln!("Warning: no stock to simulate, give --stock-diameter and --stock-length")
+            }
+        }
+    }
+    let mut path = if args.time_header {
+        estimate.header()
+    } else {
+        Toolpath::new()
     };
+    for section in sections {
+        path.append(section);
+    }
 
-    let g_code = generator::generate_gcode(&job, &machine);
-    println!("{}", g_code);
+    if let Some(svg) = args.svg {
+        let plot = Backplot::new(&jobs, &machine, stock.as_ref());
+        write_file(plot.svg(&path), svg);
+    }
+    if args.cycle_time {
+        for line in estimate.summary() {
+            eprintln!("{}", line);
+        }
+    }
+    let g_code = emit_gcode(&path, machine.controller.post_processor().as_ref());
+    match args.fname {
+        Some(fname) => write_file(g_code, fname),
+        None => println!("{}", g_code),
+    }
 }
 
-// println!("Spindle CW (y/n):      ");
-// io::stdin().read_line(&mut input);
-// let cw_dir = match input.as_ref() {
-//     "y" => true,
-//     "n" => false,
-//     _ => {
-//         print!("Error: spindle dir not recognised!");
-//         std::process::exit(0)
-//     }
-// };
-// println!("Use mm     (y/n):      ");
-// io::stdin().read_line(&mut input);
-// let use_inch =  match input.as_ref() {
-//     "y" => false,
-//     "n" => true,
-//     _ => {
-//         print!("Error: units not recognised!");
-//         std::process::exit(0)
-//     }
-// };
-// println!("Use radius (y/n):      ");
-// io::stdin().read_line(&mut input);
-// let radius =  match input.as_ref() {
-//     "y" => true,
-//     "n" => false,
-//     _ => {
-//         print!("Error: spindle dir not recognised!");
-//         std::process::exit(0)
-//     }
-// };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-// ------------------------------------------
+    fn cli(args: &str) -> Result<Cli, structopt::clap::Error> {
+        Cli::from_iter_safe(std::iter::once("lathe").chain(args.split_whitespace()))
+    }
 
-// fn main1() {
-//     let args = Cli::from_args();
-//     // add .ngc to filename
-//     // consider a radius bool cl arg for clarity
-//     // pass args to function, but do checking here.
-//
-//     // Options to add
-//     // - clearance
-//     // - different feedrate for final pass
-//
-//     if args.final_od > args.initial_od {
-//         println!("Final outside dimension must be smaller than initial outside dimension.");
-//         println!("Initial OD: {}", args.initial_od);
-//         println!("Final   OD: {}", args.final_od);
-//         std::process::exit(0);
-//     }
-//
-//     generate_gcode(
-//         args.initial_od,
-//         args.final_od,
-//         args.doc,
-//         args.finish_doc,
-//         args.start_z,
-//         args.length,
-//         args.feed,
-//         args.rpm,
-//         args.dir,
-//         args.surface_mode,
-//         args.surface_speed,
-//         args.tool,
-//         args.inch,
-//         args.diameter_mode,
-//         args.clearance,
-//         args.chamfer,
-//         args.fname,
-//     );
-// }
-//
-// fn generate_gcode(
-//     initial_od: f32,
-//     final_od: f32,
-//     doc: f32,
-//     finish_doc: f32,
-//     start_z: f32,
-//     length: f32,
-//     feed: f32,
-//     rpm: i32,
-//     dir: bool,          // true for counter clock wise spindle direction
-//     surface_mode: bool, // true for constant surface
-//     surface_speed: f32,
-//     tool: i32,
-//     inch: bool,          // true for inch
-//     diameter_mode: bool, // true for diameter - G7
-//     clearance: f32,
-//     chamfer: bool,
-//     fname: String,
-// ) {
-//     // G & M codes from command line arguments:
-//     let dir: &str = if dir { "M4" } else { "M3" };
-//     let spindle_code: String = match surface_mode {
-//         true => format!("G96 D{} S{} {}\n", rpm, surface_speed, dir),
-//         false => format!("G97 S{} {}\n", rpm, dir),
-//         _ => String::from("ERROR"),
-//     };
-//     let tool_code = format!("M6 T{} G43\n", tool);
-//     let units: &str = if inch { "G20" } else { "G21" };
-//     let lathe_mode: &str = if diameter_mode { "G7" } else { "G8" };
-//
-//     // G64 - path blending, G18 - Plane select
-//     let start_code = format!("G90 {} G64 G18 {}\n", units, lathe_mode);
-//     let finish_code = "M2";
-//
-//     // Get pass depths
-//     let mut pass_depths = calculate_passes(initial_od, final_od, doc, finish_doc);
-//     let clearance: f32 = if clearance == 0.0 {
-//         doc + initial_od
-//     } else {
-//         clearance + initial_od
-//     };
-//
-//     // generate GCode
-//     let mut code = generate_surfacing_code(&mut pass_depths, start_z, length, feed, clearance);
-//     code = start_code + &tool_code + &spindle_code + &code + &finish_code;
-//
-//     println!("{}", code);
-//
-//     write_file(code, fname);
-// }
+    fn jobs(args: &str) -> (Vec<generator::JobParams>, generator::MachineParams) {
+        match command_source(cli(args).unwrap().command.unwrap()) {
+            (Source::Jobs(jobs, _), machine) => (jobs, machine),
+            (Source::Program(_), _) => panic!("{} doesn't generate jobs", args),
+        }
+    }
 
-// fn generate_surfacing_code(
-//     pass_depths: &mut Vec<f32>,
-//     start_z: f32,
-//     length: f32,
-//     feed: f32,
-//     clearance: f32,
-// ) -> String {
-//     // Generate G gcode
-//     let mut code = String::new();
-//
-//     while !pass_depths.is_empty() {
-//         let x_val = pass_depths.pop().unwrap(); // Get depth from stack
-//         code += &format!("G0 Z{:.3} \n   X{:.3}\n", start_z, x_val); // Rapid to start
-//         code += &format!("G1 Z-{:.3} F{} \n   X{:.3} \n", length, feed, clearance); // Linear cut
-//     }
-//     code
-// }
-//
-// fn calculate_passes(initial_od: f32, final_od: f32, doc: f32, finish_doc: f32) -> Vec<f32> {
-//     // Find amount of material to remove
-//     let roughing_length = initial_od - (final_od + finish_doc);
-//     let roughing_passes: i32 = { roughing_length / doc }.ceil() as i32;
-//
-//     // Create array -> first value finish_doc, add 'doc' to prev element 'roughing_passes' times
-//     // Can turn this into a stack since you will be reversing it
-//     let mut pass_depths = vec![finish_doc + final_od];
-//     for i in 0..roughing_passes {
-//         let prev_pass = pass_depths[i as usize];
-//         pass_depths.push(prev_pass + doc);
-//     }
-//     return pass_depths;
-// }
+    #[test]
+    fn turn_command() {
+        let (jobs, machine) = jobs("turn -i 20 -f 16 -c 1 -s 0.2 -l 10 -r 100 --rpm 800 -t 2");
+        let job = &jobs[0];
+        assert!(matches!(job.job_type, generator::JobType::Turning));
+        assert_eq!((job.start_depth, job.finish_depth), (20.0, 16.0));
+        assert_eq!((job.step, job.finish_step, job.length), (1.0, 0.2, 10.0));
+        assert!(job.finishing.is_none());
+        assert!(matches!(
+            job.pass_strategy,
+            generator::PassStrategy::MaxStep
+        ));
+        assert_eq!((machine.rpm, machine.tool_num), (800, 2));
+        assert!(machine.radius_mode && machine.spindle_cw && machine.canned_cycles);
+        let gcode = generator::generate_gcode(&jobs, &machine).unwrap();
+        assert!(gcode.starts_with("G90 G21 G64 G18 G8\nM6 T2 G43\nG97 S800 M3\n"));
+    }
 
-// fn generate_facing_code(
-//     face_depths: &mut Vec<f32>,
-//     start_x: f32,
-//     feed: f32,
-//     clearance: f32,
-// ) -> String {
-//     //Generate facing passes here
-// }
+    #[test]
+    fn machine_options() {
+        let (jobs, machine) = jobs(
+            "face -i 1 -f 0 -c 0.5 -s 0.1 -z 12 -l 13 -r 80 --diameter --inch --ccw \
+             --post fanuc --constant-surface --ss 150 --max-rpm 2000 \
+             --passes degressive --degression 1.5 --spring-passes 1",
+        );
+        let job = &jobs[0];
+        assert!(matches!(job.job_type, generator::JobType::Facing));
+        assert!(matches!(
+            job.pass_strategy,
+            generator::PassStrategy::Degressive(degression) if degression == 1.5
+        ));
+        let schedule = job.finishing.as_ref().unwrap();
+        assert_eq!(
+            (&schedule.depths[..], schedule.spring_passes),
+            (&[0.1][..], 1)
+        );
+        assert!(!machine.radius_mode && machine.units_inch && !machine.spindle_cw);
+        assert!(matches!(machine.controller, Controller::Fanuc));
+        assert!(matches!(
+            machine.spindle_mode,
+            generator::SpindleMode::Css { max_rpm: 2000, .. }
+        ));
+    }
+
+    #[test]
+    fn required_arguments() {
+        assert!(cli("turn -i 20 -f 16 -c 1 -r 100").is_err());
+        assert!(cli("turn -i 20 -f 16 -c 1 -l 10 -r 100 --post haas").is_err());
+        assert!(cli("turn -i 20 -f 16 -c 1 -l 10 -r 100 --retract-mode diameter").is_err());
+        assert!(cli("").unwrap().command.is_none());
+    }
+}
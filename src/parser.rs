@@ -0,0 +1,713 @@
+// Reads G-code back into a Toolpath: the RS274/LinuxCNC subset the LinuxCNC
+// post writes, so emitted or hand edited programs can be plotted, timed and
+// compared with the generator. Programs must be absolute and in the XZ plane,
+// drilling cycles may switch to G17 around themselves.
+
+use std::collections::HashMap;
+
+use crate::generator::{arc_centre, DrillCycle};
+use crate::geometry::{x_scale, Position};
+use crate::toolpath::{CompSide, Cycle, Motion, Profile, SpindleSpeed, Toolpath, DEGRESSION};
+
+// G-codes are kept as ten times their number so G33.1 is 331
+fn code(value: f32) -> i32 {
+    (value * 10.0).round() as i32
+}
+
+struct Block {
+    words: Vec<(char, f32)>,
+    comments: Vec<String>,
+}
+
+impl Block {
+    fn word(&self, letter: char) -> Option<f32> {
+        self.words
+            .iter()
+            .find(|(l, _)| *l == letter)
+            .map(|(_, value)| *value)
+    }
+
+    fn has(&self, letter: char, number: i32) -> bool {
+        self.words
+            .iter()
+            .any(|(l, value)| *l == letter && code(*value) == number * 10)
+    }
+
+    fn g_codes(&self) -> impl Iterator<Item = i32> + '_ {
+        self.words
+            .iter()
+            .filter(|(l, _)| *l == 'G')
+            .map(|(_, value)| code(*value))
+    }
+}
+
+fn parse_block(line: &str) -> Result<Block, String> {
+    let mut block = Block {
+        words: vec![],
+        comments: vec![],
+    };
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => {
+                let mut comment = String::new();
+                loop {
+                    match chars.next() {
+                        Some(')') => break,
+                        Some(c) => comment.push(c),
+                        None => return Err("unclosed comment".to_string()),
+                    }
+                }
+                block.comments.push(comment);
+            }
+            ';' => {
+                block
+                    .comments
+                    .push(chars.by_ref().collect::<String>().trim().to_string());
+            }
+            _ if c.is_whitespace() => (),
+            _ if c.is_ascii_alphabetic() => {
+                let mut number = String::new();
+                while let Some(&next) = chars.peek() {
+                    if next.is_ascii_digit() || next == '.' || next == '-' || next == '+' {
+                        number.push(next);
+                        chars.next();
+                    } else if next == ' ' && number.is_empty() {
+                        chars.next(); // Spaces are allowed between letter and number
+                    } else {
+                        break;
+                    }
+                }
+                let value = number
+                    .parse::<f32>()
+                    .map_err(|_| format!("bad number after {}: {:?}", c, number))?;
+                block.words.push((c.to_ascii_uppercase(), value));
+            }
+            _ => return Err(format!("unexpected character {:?}", c)),
+        }
+    }
+    Ok(block)
+}
+
+// What an o-word line does
+enum OWord {
+    Sub(u32),
+    EndSub(u32),
+}
+
+fn parse_o_word(line: &str) -> Option<Result<OWord, String>> {
+    let line = line.trim();
+    if !line.starts_with(['o', 'O']) {
+        return None;
+    }
+    let mut parts = line[1..].split_whitespace();
+    let number = match parts.next().map(|number| number.parse::<u32>()) {
+        Some(Ok(number)) => number,
+        _ => return Some(Err(format!("bad o-word: {}", line))),
+    };
+    Some(match parts.next().map(|keyword| keyword.to_lowercase()) {
+        Some(ref keyword) if keyword == "sub" => Ok(OWord::Sub(number)),
+        Some(ref keyword) if keyword == "endsub" => Ok(OWord::EndSub(number)),
+        _ => Err(format!("unsupported o-word: {}", line)),
+    })
+}
+
+// Modal state that a subroutine body starts afresh with
+struct Modal {
+    moves: Vec<Motion>,
+    motion: Option<i32>,
+    feed: Option<f32>,
+    position: Position, // Only the axes are kept, X scale is the reader's
+}
+
+impl Modal {
+    fn new() -> Modal {
+        Modal {
+            moves: vec![],
+            motion: None,
+            feed: None,
+            position: Position::new(false),
+        }
+    }
+}
+
+struct Reader {
+    modal: Modal,
+    units_inch: bool,
+    radius_mode: bool,
+    feed_per_rev: bool,
+    tool: Option<i32>,
+    speed: Option<f32>, // S word
+    max_rpm: i32,       // D word, used with G96
+    css: bool,
+    spindle: Option<bool>, // Running, true when clockwise
+    subs: HashMap<u32, Vec<Motion>>,
+    sub: Option<(u32, Modal)>, // Body being read and the modal state outside it
+}
+
+impl Reader {
+    fn push(&mut self, motion: Motion) {
+        self.modal.position.follow(&motion);
+        self.modal.moves.push(motion);
+    }
+
+    fn spindle_speed(&self) -> Result<SpindleSpeed, String> {
+        let speed = self.speed.ok_or("spindle started with no S word")?;
+        Ok(if self.css {
+            SpindleSpeed::SurfaceSpeed {
+                speed,
+                max_rpm: self.max_rpm,
+            }
+        } else {
+            SpindleSpeed::Rpm(speed as i32)
+        })
+    }
+
+    fn feed(&self, block: &Block) -> Result<f32, String> {
+        block
+            .word('F')
+            .or(self.modal.feed)
+            .ok_or_else(|| "feed move with no F word".to_string())
+    }
+
+    fn position(&self) -> Result<(f32, f32), String> {
+        match (self.modal.position.x, self.modal.position.z) {
+            (Some(x), Some(z)) => Ok((x, z)),
+            _ => Err("position unknown, give X and Z first".to_string()),
+        }
+    }
+
+    fn profile(&self, number: u32, feed: f32) -> Result<Profile, String> {
+        // The body is the contour blocks Profile::blocks writes: X to the
+        // first point, Z onto it, the contour, then X back out to the start
+        let body = self
+            .subs
+            .get(&number)
+            .ok_or_else(|| format!("no subroutine o{}", number))?;
+        let (start_x, start_z) = self.position()?;
+        match body[..] {
+            [Motion::Rapid {
+                x: Some(first_x),
+                z: None,
+            }, Motion::Feed {
+                x: None,
+                z: Some(first_z),
+                feed: finish_feed,
+            }, ref contour @ .., Motion::Feed {
+                x: Some(_),
+                z: None,
+                ..
+            }] => Ok(Profile {
+                number: number.saturating_sub(100),
+                start_x,
+                start_z,
+                first_x,
+                first_z,
+                contour: contour.to_vec(),
+                feed,
+                finish_feed,
+                radius_mode: self.radius_mode,
+            }),
+            _ => Err(format!("subroutine o{} isn't a contour", number)),
+        }
+    }
+
+    fn o_word(&mut self, o_word: OWord) -> Result<(), String> {
+        match (o_word, self.sub.take()) {
+            (OWord::Sub(number), None) => {
+                let outside = std::mem::replace(&mut self.modal, Modal::new());
+                self.sub = Some((number, outside));
+            }
+            (OWord::EndSub(number), Some((open, outside))) if number == open => {
+                let body = std::mem::replace(&mut self.modal, outside);
+                self.subs.insert(number, body.moves);
+            }
+            (OWord::Sub(number), Some(_)) => {
+                return Err(format!("o{} sub inside another subroutine", number))
+            }
+            (OWord::EndSub(number), _) => return Err(format!("o{} endsub without sub", number)),
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, block: Block) -> Result<(), String> {
+        for comment in &block.comments {
+            self.push(Motion::Comment(comment.clone()));
+        }
+        for (letter, value) in &block.words {
+            match letter {
+                'G' | 'M' | 'X' | 'Z' | 'I' | 'J' | 'K' | 'F' | 'S' | 'T' | 'D' | 'P' | 'Q'
+                | 'R' | 'H' | 'N' => (),
+                _ => return Err(format!("unsupported word {}{}", letter, value)),
+            }
+        }
+        if let Some(feed) = block.word('F') {
+            self.modal.feed = Some(feed);
+        }
+        if let Some(tool) = block.word('T') {
+            self.tool = Some(tool as i32);
+        }
+
+        // Modes, checked before anything moves
+        let mut setup = false;
+        let mut motion = None;
+        let mut spindle_mode = false;
+        for g in block.g_codes() {
+            match g {
+                0 | 10 | 20 | 30 | 330 | 331 | 700 | 710 | 730 | 760 | 810 | 820 | 830 => {
+                    motion = Some(g)
+                }
+                800 => self.modal.motion = None,
+                70 | 80 => {
+                    self.radius_mode = g == 80;
+                    setup = true;
+                }
+                200 | 210 => {
+                    self.units_inch = g == 200;
+                    setup = true;
+                }
+                940 | 950 => {
+                    self.feed_per_rev = g == 950;
+                    setup = true;
+                }
+                960 | 970 => {
+                    self.css = g == 960;
+                    spindle_mode = true;
+                }
+                // Plane, path blending, absolute, tool length offset
+                170 | 180 | 640 | 900 | 430 => (),
+                40 | 280 | 300 | 400 | 410 | 420 => (),
+                _ => return Err(format!("unsupported G{}", g as f32 / 10.0)),
+            }
+        }
+        for (letter, value) in &block.words {
+            if *letter == 'M' {
+                match code(*value) {
+                    20 | 30 | 40 | 50 | 60 | 80 | 90 | 300 => (),
+                    _ => return Err(format!("unsupported M{}", value)),
+                }
+            }
+        }
+        if setup {
+            self.push(Motion::Setup {
+                units_inch: self.units_inch,
+                radius_mode: self.radius_mode,
+                feed_per_rev: self.feed_per_rev,
+            });
+        }
+
+        // Tool, spindle and coolant
+        if block.has('M', 6) {
+            let tool = self.tool.ok_or("tool change with no T word")?;
+            self.push(Motion::ToolChange(tool));
+        }
+        if let Some(speed) = block.word('S') {
+            self.speed = Some(speed);
+            spindle_mode = true;
+        }
+        if let Some(max_rpm) = block.word('D') {
+            self.max_rpm = max_rpm as i32;
+        }
+        if block.has('M', 3) || block.has('M', 4) {
+            self.spindle = Some(block.has('M', 3));
+        }
+        if let Some(cw) = self.spindle {
+            if spindle_mode || block.has('M', 3) || block.has('M', 4) {
+                let speed = self.spindle_speed()?;
+                self.push(Motion::Spindle { speed, cw });
+            }
+        }
+        if block.has('M', 5) {
+            self.spindle = None;
+            self.push(Motion::SpindleStop);
+        }
+        if block.has('M', 8) {
+            self.push(Motion::Coolant(true));
+        }
+        if block.has('M', 9) {
+            self.push(Motion::Coolant(false));
+        }
+
+        // Non-modal codes and compensation
+        for g in block.g_codes() {
+            match g {
+                40 => {
+                    let seconds = block.word('P').ok_or("G4 with no P word")?;
+                    self.push(Motion::Dwell(seconds));
+                }
+                280 | 300 => self.push(Motion::Reference { second: g == 300 }),
+                400 => self.push(Motion::NoseCompensation(None)),
+                410 => self.push(Motion::NoseCompensation(Some(CompSide::Left))),
+                420 => self.push(Motion::NoseCompensation(Some(CompSide::Right))),
+                _ => (),
+            }
+        }
+
+        // Motion, axis words alone repeat the modal G0/G1/G2/G3
+        let x = block.word('X');
+        let z = block.word('Z');
+        let motion = match motion {
+            Some(motion) => Some(motion),
+            None if x.is_some() || z.is_some() => match self.modal.motion {
+                Some(motion) => Some(motion),
+                None => return Err("axis words with no motion mode".to_string()),
+            },
+            None => None,
+        };
+        if let Some(motion) = motion {
+            self.motion(motion, &block, x, z)?;
+        }
+
+        if block.has('M', 2) || block.has('M', 30) {
+            self.push(Motion::End);
+        }
+        Ok(())
+    }
+
+    fn motion(
+        &mut self,
+        motion: i32,
+        block: &Block,
+        x: Option<f32>,
+        z: Option<f32>,
+    ) -> Result<(), String> {
+        let x_scale = x_scale(self.radius_mode);
+        match motion {
+            0 | 10 => {
+                if x.is_some() || z.is_some() {
+                    self.push(if motion == 0 {
+                        Motion::Rapid { x, z }
+                    } else {
+                        Motion::Feed {
+                            x,
+                            z,
+                            feed: self.feed(block)?,
+                        }
+                    });
+                }
+                self.modal.motion = Some(motion);
+            }
+            20 | 30 => {
+                let (x, z) = match (x.or(self.modal.position.x), z.or(self.modal.position.z)) {
+                    (Some(x), Some(z)) => (x, z),
+                    _ => return Err("arc end point unknown".to_string()),
+                };
+                let cw = motion == 20;
+                let (start_x, start_z) = self.position()?;
+                let start = (start_x / x_scale, start_z);
+                let end = (x / x_scale, z);
+                // R takes the shorter arc, negative R the longer one
+                let (i, k) = match block.word('R') {
+                    Some(_) if block.word('I').is_some() || block.word('K').is_some() => {
+                        return Err("arc with both R and I/K words".to_string())
+                    }
+                    Some(radius) => {
+                        match arc_centre(start, end, radius.abs(), cw == (radius > 0.0)) {
+                            Some(centre) => (centre.0 - start.0, centre.1 - start.1),
+                            None => {
+                                return Err(format!(
+                                    "arc radius R{} can't reach the end point",
+                                    radius
+                                ))
+                            }
+                        }
+                    }
+                    None => (
+                        block.word('I').unwrap_or(0.0),
+                        block.word('K').unwrap_or(0.0),
+                    ),
+                };
+                let centre = (start.0 + i, start.1 + k);
+                let radius = |point: (f32, f32)| {
+                    ((point.0 - centre.0).powi(2) + (point.1 - centre.1).powi(2)).sqrt()
+                };
+                let tolerance = if self.units_inch { 0.0002 } else { 0.002 };
+                if radius(start) <= tolerance {
+                    return Err("arc with no centre, give I/K or R".to_string());
+                }
+                if (radius(start) - radius(end)).abs() > tolerance {
+                    return Err(format!(
+                        "arc radius {:.4} at the start differs from {:.4} at the end",
+                        radius(start),
+                        radius(end)
+                    ));
+                }
+                self.push(Motion::Arc {
+                    cw,
+                    x,
+                    z,
+                    i,
+                    k,
+                    feed: self.feed(block)?,
+                });
+                self.modal.motion = Some(motion);
+            }
+            330 | 331 => {
+                let z = z.ok_or("G33 with no Z word")?;
+                let pitch = block.word('K').ok_or("G33 with no K word")?;
+                if motion == 330 {
+                    self.push(Motion::Thread { z, pitch });
+                } else {
+                    // Taps back out to the start
+                    self.push(Motion::RigidTap { z, pitch });
+                }
+                self.modal.motion = None;
+            }
+            760 => {
+                // I/J/K are radial, I from the drive line to the crest
+                let (drive_x, start_z) = self.position()?;
+                let word = |letter: char| {
+                    block
+                        .word(letter)
+                        .ok_or_else(|| format!("G76 with no {} word", letter))
+                };
+                // The passes only end once they reach K, stepping from J
+                let positive = |letter: char, value: f32| {
+                    if value > 0.0 {
+                        Ok(value)
+                    } else {
+                        Err(format!("G76 {} word must be positive", letter))
+                    }
+                };
+                let crest_x = drive_x + word('I')? * x_scale;
+                let depth = positive('K', word('K')?)?;
+                let root_x = if crest_x < drive_x {
+                    crest_x - depth * x_scale
+                } else {
+                    crest_x + depth * x_scale
+                };
+                self.push(Motion::Cycle(Cycle::Thread {
+                    drive_x,
+                    start_z,
+                    z: z.ok_or("G76 with no Z word")?,
+                    crest_x,
+                    root_x,
+                    pitch: word('P')?,
+                    first_depth: positive('J', word('J')?)?,
+                    depth,
+                    infeed_angle: block.word('Q').unwrap_or(0.0),
+                    finish_depths: vec![],
+                    spring_passes: block.word('H').unwrap_or(0.0) as i32,
+                    degression: match block.word('R').unwrap_or(1.0) {
+                        r if DEGRESSION.contains(&r) => r,
+                        _ => {
+                            return Err(format!(
+                                "G76 R word must be between {} and {}",
+                                DEGRESSION.start(),
+                                DEGRESSION.end()
+                            ))
+                        }
+                    },
+                }));
+                self.modal.motion = None;
+            }
+            730 | 810 | 820 | 830 => {
+                // Drilled from the R plane, which the cycle returns to
+                let retract = block.word('R').ok_or("drilling cycle with no R word")?;
+                let kind = match motion {
+                    810 => DrillCycle::Simple,
+                    820 => DrillCycle::Dwell,
+                    830 => DrillCycle::Peck,
+                    _ => DrillCycle::ChipBreak,
+                };
+                let peck = block.word('Q').unwrap_or(0.0);
+                if (motion == 730 || motion == 830) && peck <= 0.0 {
+                    return Err(format!("G{} Q word must be positive", motion / 10));
+                }
+                self.push(Motion::Cycle(Cycle::Drill {
+                    kind,
+                    start_z: retract,
+                    z: z.ok_or("drilling cycle with no Z word")?,
+                    retract,
+                    peck,
+                    dwell: block.word('P').unwrap_or(0.0),
+                    feed: self.feed(block)?,
+                }));
+                self.modal.motion = None;
+            }
+            700 | 710 => {
+                // The contour subroutine is named by Q, and both cycles
+                // finish back at the start point
+                let number = block.word('Q').ok_or("G70/G71 with no Q word")? as u32;
+                let feed = block.word('F').or(self.modal.feed);
+                let cycle = if motion == 710 {
+                    let word = |letter: char| {
+                        block
+                            .word(letter)
+                            .ok_or_else(|| format!("G71 with no {} word", letter))
+                    };
+                    let step = word('I')?;
+                    if step <= 0.0 {
+                        return Err("G71 I word must be positive".to_string());
+                    }
+                    Cycle::Rough {
+                        profile: self.profile(number, feed.ok_or("G71 with no F word")?)?,
+                        step: step * x_scale,
+                        allowance: word('D')? * x_scale,
+                        retract: word('R')? * x_scale,
+                    }
+                } else {
+                    let profile = self.profile(number, 0.0)?;
+                    Cycle::Finish(Profile {
+                        feed: feed.unwrap_or(profile.finish_feed),
+                        ..profile
+                    })
+                };
+                self.push(Motion::Cycle(cycle));
+                self.modal.motion = None;
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
+
+// The moves of a program, errors name the line they are on
+pub fn read_program(text: &str) -> Result<Toolpath, String> {
+    let mut reader = Reader {
+        modal: Modal::new(),
+        units_inch: false,
+        radius_mode: false,
+        feed_per_rev: false,
+        tool: None,
+        speed: None,
+        max_rpm: 0,
+        css: false,
+        spindle: None,
+        subs: HashMap::new(),
+        sub: None,
+    };
+    for (number, line) in text.lines().enumerate() {
+        let result = match parse_o_word(line) {
+            Some(o_word) => o_word.and_then(|o_word| reader.o_word(o_word)),
+            None if line.trim() == "%" => Ok(()),
+            None => parse_block(line).and_then(|block| reader.block(block)),
+        };
+        result.map_err(|why| format!("line {}: {}", number + 1, why))?;
+    }
+    if let Some((number, _)) = reader.sub {
+        return Err(format!("o{} sub has no endsub", number));
+    }
+    Ok(Toolpath {
+        moves: reader.modal.moves,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emitter::emit_gcode;
+    use crate::job::Program;
+    use crate::post::{Controller, LinuxCnc};
+
+    fn round_trip(program: &Program) {
+        let g_code = program.generate_gcode().unwrap();
+        let path = read_program(&g_code).unwrap();
+        assert_eq!(emit_gcode(&path, &LinuxCnc), g_code);
+    }
+
+    #[test]
+    fn shaft_example_reads_back() {
+        let program = Program::from_toml(include_str!("../examples/shaft.toml")).unwrap();
+        round_trip(&program);
+    }
+
+    #[test]
+    fn expanded_cycles_read_back() {
+        let mut program = Program::from_toml(include_str!("../examples/shaft.toml")).unwrap();
+        program.machine.canned_cycles = false;
+        round_trip(&program);
+    }
+
+    #[test]
+    fn contour_subroutines_read_back() {
+        let mut program = Program::from_toml(include_str!("../examples/spindle.toml")).unwrap();
+        program.machine.controller = Controller::LinuxCnc;
+        round_trip(&program);
+    }
+
+    #[test]
+    fn rejected_words() {
+        // Each block follows a setup and a start position, on line 3
+        let cases = [
+            ("G5 X10", "unsupported G5"),
+            ("M7", "unsupported M7"),
+            ("G1 A5 F50", "unsupported word A5"),
+            ("G1 X10 (open", "unclosed comment"),
+            ("G1 Z-10", "feed move with no F word"),
+            ("M6", "tool change with no T word"),
+            ("M3", "spindle started with no S word"),
+            ("G4", "G4 with no P word"),
+            ("G2 X24 Z-5 F50", "arc with no centre, give I/K or R"),
+            ("G2 X24 Z-5 R3 I1 F50", "arc with both R and I/K words"),
+            ("G2 X24 Z-5 I0 K-3 F50", "at the start differs"),
+            ("G33 Z-10", "G33 with no K word"),
+            ("G76 P1.5 Z-20 I-1 J0.3 K0", "G76 K word must be positive"),
+            ("G76 P1.5 Z-20 I-1 J0 K0.9", "G76 J word must be positive"),
+            (
+                "G76 P1.5 Z-20 I-1 J0.3 K0.9 R0",
+                "G76 R word must be between",
+            ),
+            (
+                "G76 P1.5 Z-20 I-1 J0.3 K0.9 R20",
+                "G76 R word must be between",
+            ),
+            ("G76 Z-20 I-1 J0.3 K0.9", "G76 with no P word"),
+            ("G83 Z-10 R1 F50", "G83 Q word must be positive"),
+            ("G73 Z-10 R1 Q-1 F50", "G73 Q word must be positive"),
+            ("G81 Z-10 F50", "drilling cycle with no R word"),
+            ("G71 Q100 I0 D0.2 R0.5 F100", "G71 I word must be positive"),
+            ("G71 Q100 I1 D0.2 R0.5 F100", "no subroutine o100"),
+            ("o100 endsub", "o100 endsub without sub"),
+            ("o100 call", "unsupported o-word"),
+        ];
+        for (block, error) in cases.iter() {
+            let program = format!("G90 G21 G18 G7\nG0 X20 Z1\n{}\n", block);
+            match read_program(&program) {
+                Ok(_) => panic!("{} was read", block),
+                Err(why) => assert!(
+                    why.starts_with("line 3: ") && why.contains(error),
+                    "{}",
+                    why
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn axis_words_need_a_motion_mode() {
+        let result = read_program("G90 G21 G18 G7\nX20 Z1\n");
+        assert_eq!(
+            result.err().unwrap(),
+            "line 2: axis words with no motion mode"
+        );
+    }
+
+    #[test]
+    fn position_needed_for_arcs_and_cycles() {
+        let result = read_program("G90 G21 G18 G7\nG76 P1.5 Z-20 I-1 J0.3 K0.9\n");
+        assert_eq!(
+            result.err().unwrap(),
+            "line 2: position unknown, give X and Z first"
+        );
+    }
+
+    #[test]
+    fn unterminated_subroutine() {
+        let result = read_program("o100 sub\nG1 X10 Z0 F50\n");
+        assert_eq!(result.err().unwrap(), "o100 sub has no endsub");
+    }
+
+    #[test]
+    fn arc_given_by_radius() {
+        // Quarter circle from X20 Z1 to X24 Z-1, centre at radius 10 Z-1
+        let path = read_program("G90 G21 G18 G7\nG0 X20 Z1\nG3 X24 Z-1 R2 F50\n").unwrap();
+        match path.moves.last() {
+            Some(&Motion::Arc { cw, i, k, .. }) => {
+                assert!(!cw);
+                assert!(i.abs() < 1e-4 && (k + 2.0).abs() < 1e-4, "I{} K{}", i, k);
+            }
+            _ => panic!("no arc"),
+        }
+    }
+}
@@ -0,0 +1,543 @@
+// Controller dialects. The emitter handles motion and modal state, a
+// PostProcessor decides the words that differ between controllers.
+
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use crate::emitter::contour_lines;
+use crate::generator::DrillCycle;
+use crate::geometry::x_scale;
+use crate::toolpath::{CompSide, Cycle, SpindleSpeed};
+
+#[derive(Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Controller {
+    #[default]
+    LinuxCnc,
+    Fanuc, // 0i-T style, diameter programming set by parameter
+    Grbl,  // Lathe builds with G7/G8 and G33, no canned cycles
+}
+
+impl FromStr for Controller {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Controller, String> {
+        match name.to_lowercase().as_ref() {
+            "linuxcnc" => Ok(Controller::LinuxCnc),
+            "fanuc" => Ok(Controller::Fanuc),
+            "grbl" => Ok(Controller::Grbl),
+            _ => Err(format!("unknown controller: {}", name)),
+        }
+    }
+}
+
+impl Controller {
+    pub fn post_processor(&self) -> Box<dyn PostProcessor> {
+        match *self {
+            Controller::LinuxCnc => Box::new(LinuxCnc),
+            Controller::Fanuc => Box::new(Fanuc { program_number: 1 }),
+            Controller::Grbl => Box::new(Grbl),
+        }
+    }
+}
+
+// Modal state a post needs to format cycles
+pub struct ProgramState {
+    pub units_inch: bool,
+    pub radius_mode: bool,
+}
+
+pub trait PostProcessor {
+    fn program_start(&self) -> Vec<String> {
+        vec![]
+    }
+    fn setup(&self, units_inch: bool, radius_mode: bool, feed_per_rev: bool) -> String;
+    fn tool_change(&self, tool: i32) -> String;
+    fn spindle(&self, speed: SpindleSpeed, cw: bool) -> String {
+        let dir = if cw { "M3" } else { "M4" };
+        match speed {
+            SpindleSpeed::Rpm(rpm) => format!("G97 S{} {}", rpm, dir),
+            SpindleSpeed::SurfaceSpeed { speed, max_rpm } => {
+                format!("G96 D{} S{} {}", max_rpm, speed, dir)
+            }
+        }
+    }
+    fn dwell(&self, seconds: f32) -> String {
+        format!("G4 P{:.2}", seconds)
+    }
+    fn comment(&self, text: &str) -> String {
+        format!("({})", text)
+    }
+    fn reference(&self, second: bool) -> String {
+        (if second { "G30" } else { "G28" }).to_string()
+    }
+    fn thread_move(&self, z: f32, pitch: f32) -> String {
+        format!("G33 Z{:.3} K{:.4}", z, pitch)
+    }
+    fn rigid_tap(&self, z: f32, pitch: f32) -> String {
+        format!("G33.1 Z{:.3} K{:.4}", z, pitch)
+    }
+    // false -> only floating holder tapping can be used
+    fn rigid_tap_supported(&self) -> bool {
+        false
+    }
+    // false -> no G96, operations have to run at a fixed rpm
+    fn css_supported(&self) -> bool {
+        true
+    }
+    fn nose_compensation(&self, side: Option<CompSide>) -> String {
+        match side {
+            Some(CompSide::Left) => "G41".to_string(),
+            Some(CompSide::Right) => "G42".to_string(),
+            None => "G40".to_string(),
+        }
+    }
+    // false -> G41/G42 can't be used, the generator has to offset the path
+    fn nose_compensation_supported(&self) -> bool {
+        true
+    }
+    // None -> the emitter expands the cycle into explicit moves
+    fn cycle(&self, _cycle: &Cycle, _state: &ProgramState) -> Option<String> {
+        None
+    }
+    // Some(step) -> number every block N<step>, N<2*step>, ...
+    fn line_number_step(&self) -> Option<u32> {
+        None
+    }
+    fn program_end(&self) -> Vec<String>;
+}
+
+pub struct LinuxCnc;
+
+impl PostProcessor for LinuxCnc {
+    fn setup(&self, units_inch: bool, radius_mode: bool, feed_per_rev: bool) -> String {
+        // G64 - path blending, G18 - Plane select, G94 per minute is the default
+        let units = if units_inch { "G20" } else { "G21" };
+        let lathe_mode = if radius_mode { "G8" } else { "G7" };
+        let mut code = format!("G90 {} G64 G18 {}", units, lathe_mode);
+        if feed_per_rev {
+            code += " G95";
+        }
+        code
+    }
+
+    fn tool_change(&self, tool: i32) -> String {
+        format!("M6 T{} G43", tool)
+    }
+
+    fn rigid_tap_supported(&self) -> bool {
+        true
+    }
+
+    fn cycle(&self, cycle: &Cycle, state: &ProgramState) -> Option<String> {
+        if let Cycle::Thread {
+            ref finish_depths, ..
+        } = *cycle
+        {
+            // G76 has spring passes but no separate finishing passes
+            if !finish_depths.is_empty() {
+                return None;
+            }
+        }
+        Some(match *cycle {
+            Cycle::Thread {
+                drive_x,
+                z,
+                crest_x,
+                pitch,
+                first_depth,
+                depth,
+                infeed_angle,
+                spring_passes,
+                degression,
+                ..
+            } => {
+                // I/J/K are radial values
+                let x_scale = x_scale(state.radius_mode);
+                format!(
+                    "G76 P{:.4} Z{:.3} I{:.3} J{:.3} K{:.3} Q{:.1} H{} R{:.2}",
+                    pitch,
+                    z,
+                    (crest_x - drive_x) / x_scale,
+                    first_depth,
+                    depth,
+                    infeed_angle,
+                    spring_passes,
+                    degression
+                )
+            }
+            Cycle::Drill {
+                ref kind,
+                z,
+                retract,
+                peck,
+                dwell,
+                feed,
+                ..
+            } => {
+                // Canned cycles drill along the plane normal, so switch to G17 for Z
+                let cycle = match kind {
+                    DrillCycle::Simple => format!("G81 Z{:.3} R{:.3}", z, retract),
+                    DrillCycle::Dwell => format!("G82 Z{:.3} R{:.3} P{:.2}", z, retract, dwell),
+                    DrillCycle::Peck => format!("G83 Z{:.3} R{:.3} Q{:.3}", z, retract, peck),
+                    DrillCycle::ChipBreak => {
+                        format!("G73 Z{:.3} R{:.3} Q{:.3}", z, retract, peck)
+                    }
+                };
+                format!("G17\n{} F{}\nG80\nG18", cycle, feed)
+            }
+            Cycle::Rough {
+                ref profile,
+                step,
+                allowance,
+                retract,
+            } => {
+                // The contour is a subroutine, defined where it is first used
+                let x_scale = x_scale(state.radius_mode);
+                let number = 100 + profile.number;
+                let mut lines = vec![format!("o{} sub", number)];
+                lines.extend(contour_lines(&profile.path().moves, self));
+                lines.push(format!("o{} endsub", number));
+                lines.push(format!(
+                    "G71 Q{} D{:.3} I{:.3} R{:.3} F{}",
+                    number,
+                    allowance / x_scale,
+                    step / x_scale,
+                    retract / x_scale,
+                    profile.feed
+                ));
+                lines.join("\n")
+            }
+            Cycle::Finish(ref profile) => format!("G70 Q{}", 100 + profile.number),
+        })
+    }
+
+    fn program_end(&self) -> Vec<String> {
+        vec!["M2".to_string()]
+    }
+}
+
+fn profile_sequence(number: u32) -> (u32, u32) {
+    (80000 + number * 100, 80000 + number * 100 + 99)
+}
+
+pub struct Fanuc {
+    pub program_number: u32,
+}
+
+impl PostProcessor for Fanuc {
+    fn program_start(&self) -> Vec<String> {
+        vec!["%".to_string(), format!("O{:04}", self.program_number)]
+    }
+
+    fn setup(&self, units_inch: bool, radius_mode: bool, feed_per_rev: bool) -> String {
+        // No G90 on G-code system A, G98 - feed per minute, G99 - per revolution
+        let units = if units_inch { "G20" } else { "G21" };
+        let feed_mode = if feed_per_rev { "G99" } else { "G98" };
+        let mut code = format!("{} G18 G40 {}", units, feed_mode);
+        if radius_mode {
+            code += " (RADIUS PROGRAMMING - CHECK PARAMETER 1006)";
+        }
+        code
+    }
+
+    fn tool_change(&self, tool: i32) -> String {
+        // Tool and offset number
+        format!("T{:02}{:02}", tool, tool)
+    }
+
+    fn spindle(&self, speed: SpindleSpeed, cw: bool) -> String {
+        // Maximum spindle speed is clamped with G50
+        let dir = if cw { "M3" } else { "M4" };
+        match speed {
+            SpindleSpeed::Rpm(rpm) => format!("G97 S{} {}", rpm, dir),
+            SpindleSpeed::SurfaceSpeed { speed, max_rpm } => {
+                format!("G50 S{}\nG96 S{} {}", max_rpm, speed, dir)
+            }
+        }
+    }
+
+    fn dwell(&self, seconds: f32) -> String {
+        format!("G04 X{:.2}", seconds)
+    }
+
+    fn reference(&self, second: bool) -> String {
+        // Through the current position, U and W are incremental
+        format!("{} U0 W0", if second { "G30" } else { "G28" })
+    }
+
+    fn thread_move(&self, z: f32, pitch: f32) -> String {
+        format!("G32 Z{:.3} F{:.4}", z, pitch)
+    }
+
+    fn cycle(&self, cycle: &Cycle, state: &ProgramState) -> Option<String> {
+        match *cycle {
+            Cycle::Thread {
+                z,
+                root_x,
+                pitch,
+                first_depth,
+                depth,
+                infeed_angle,
+                ref finish_depths,
+                spring_passes,
+                ..
+            } => {
+                // Two block G76, depths in least input increment. The depth
+                // schedule is fixed at constant area on this control and the
+                // tool angle must be one of the listed values. One finishing
+                // allowance can be given, repeated as the spring passes.
+                let (finishes, allowance) = match finish_depths[..] {
+                    [] => (spring_passes.max(1), 0.0),
+                    [allowance] => (spring_passes + 1, allowance),
+                    _ => return None,
+                };
+                let increment = if state.units_inch { 10000.0 } else { 1000.0 };
+                let tool_angle = [0, 29, 30, 55, 60, 80]
+                    .iter()
+                    .min_by_key(|angle| ((infeed_angle * 2.0) as i32 - **angle).abs())
+                    .unwrap();
+                Some(format!(
+                    "G76 P{:02}00{:02} Q{} R{:.3}\nG76 X{:.3} Z{:.3} P{} Q{} F{:.4}",
+                    finishes,
+                    tool_angle,
+                    (first_depth * increment / 4.0).round() as i32,
+                    allowance,
+                    root_x,
+                    z,
+                    (depth * increment).round() as i32,
+                    (first_depth * increment).round() as i32,
+                    pitch
+                ))
+            }
+            Cycle::Drill { .. } => None,
+            Cycle::Rough {
+                ref profile,
+                step,
+                allowance,
+                retract,
+            } => {
+                // Contour blocks follow the cycle between sequence numbers ns
+                // and nf, set apart from the numbering of the other blocks
+                let x_scale = x_scale(state.radius_mode);
+                let (ns, nf) = profile_sequence(profile.number);
+                let mut lines = vec![
+                    format!("G71 U{:.3} R{:.3}", step / x_scale, retract / x_scale),
+                    format!(
+                        "G71 P{} Q{} U{:.3} W{:.3} F{}",
+                        ns,
+                        nf,
+                        allowance,
+                        allowance / x_scale,
+                        profile.feed
+                    ),
+                ];
+                let contour = contour_lines(&profile.path().moves, self);
+                let last = contour.len() - 1;
+                for (i, line) in contour.iter().enumerate() {
+                    lines.push(match i {
+                        0 => format!("N{} {}", ns, line.trim_start()),
+                        _ if i == last => format!("N{} {}", nf, line.trim_start()),
+                        _ => line.clone(),
+                    });
+                }
+                Some(lines.join("\n"))
+            }
+            Cycle::Finish(ref profile) => {
+                let (ns, nf) = profile_sequence(profile.number);
+                Some(format!("G70 P{} Q{}", ns, nf))
+            }
+        }
+    }
+
+    fn line_number_step(&self) -> Option<u32> {
+        Some(10)
+    }
+
+    fn program_end(&self) -> Vec<String> {
+        vec!["M30".to_string(), "%".to_string()]
+    }
+}
+
+pub struct Grbl;
+
+impl PostProcessor for Grbl {
+    fn setup(&self, units_inch: bool, radius_mode: bool, feed_per_rev: bool) -> String {
+        let units = if units_inch { "G20" } else { "G21" };
+        let lathe_mode = if radius_mode { "G8" } else { "G7" };
+        let mut code = format!("G90 {} G18 {}", units, lathe_mode);
+        if feed_per_rev {
+            code += " G95";
+        }
+        code
+    }
+
+    fn tool_change(&self, tool: i32) -> String {
+        // No automatic tool changer, pause for a manual change
+        format!("M0 (CHANGE TO TOOL {})", tool)
+    }
+
+    fn css_supported(&self) -> bool {
+        false
+    }
+
+    fn nose_compensation_supported(&self) -> bool {
+        false
+    }
+
+    fn program_end(&self) -> Vec<String> {
+        vec!["M2".to_string()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emitter::emit_gcode;
+    use crate::toolpath::{Motion, Toolpath};
+
+    fn program(speed: SpindleSpeed) -> Toolpath {
+        let mut path = Toolpath::new();
+        path.push(Motion::Setup {
+            units_inch: false,
+            radius_mode: false,
+            feed_per_rev: false,
+        });
+        path.push(Motion::ToolChange(2));
+        path.push(Motion::Spindle { speed, cw: true });
+        path.rapid_x(20.0);
+        path.push(Motion::Dwell(1.5));
+        path.push(Motion::Reference { second: true });
+        path.push(Motion::Thread {
+            z: -20.0,
+            pitch: 1.5,
+        });
+        path.push(Motion::End);
+        path
+    }
+
+    fn thread() -> Cycle {
+        Cycle::Thread {
+            drive_x: 14.0,
+            start_z: 2.0,
+            z: -20.0,
+            crest_x: 12.0,
+            root_x: 10.16,
+            pitch: 1.5,
+            first_depth: 0.2,
+            depth: 0.92,
+            infeed_angle: 29.5,
+            finish_depths: vec![],
+            spring_passes: 1,
+            degression: 1.5,
+        }
+    }
+
+    const DIAMETER: ProgramState = ProgramState {
+        units_inch: false,
+        radius_mode: false,
+    };
+
+    const CSS: SpindleSpeed = SpindleSpeed::SurfaceSpeed {
+        speed: 150.0,
+        max_rpm: 2500,
+    };
+
+    #[test]
+    fn linuxcnc_program() {
+        assert_eq!(
+            emit_gcode(&program(CSS), &LinuxCnc),
+            "G90 G21 G64 G18 G7
+M6 T2 G43
+G96 D2500 S150 M3
+G0 X20.000
+G4 P1.50
+G30
+G33 Z-20.000 K1.5000
+M2
+"
+        );
+        assert_eq!(
+            LinuxCnc.cycle(&thread(), &DIAMETER).unwrap(),
+            "G76 P1.5000 Z-20.000 I-1.000 J0.200 K0.920 Q29.5 H1 R1.50"
+        );
+    }
+
+    #[test]
+    fn fanuc_program() {
+        let fanuc = Fanuc { program_number: 12 };
+        assert_eq!(
+            emit_gcode(&program(CSS), &fanuc),
+            "%
+O0012
+N10 G21 G18 G40 G98
+N20 T0202
+N30 G50 S2500
+N40 G96 S150 M3
+N50 G0 X20.000
+N60 G04 X1.50
+N70 G30 U0 W0
+N80 G32 Z-20.000 F1.5000
+N90 M30
+%
+"
+        );
+        assert_eq!(
+            fanuc.cycle(&thread(), &DIAMETER).unwrap(),
+            "G76 P010060 Q50 R0.000\nG76 X10.160 Z-20.000 P920 Q200 F1.5000"
+        );
+        assert!(fanuc
+            .cycle(
+                &Cycle::Drill {
+                    kind: DrillCycle::Simple,
+                    start_z: 0.0,
+                    z: -5.0,
+                    retract: 1.0,
+                    peck: 0.0,
+                    dwell: 0.0,
+                    feed: 60.0,
+                },
+                &DIAMETER
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn grbl_program() {
+        assert_eq!(
+            emit_gcode(&program(SpindleSpeed::Rpm(800)), &Grbl),
+            "G90 G21 G18 G7
+M0 (CHANGE TO TOOL 2)
+G97 S800 M3
+G0 X20.000
+G4 P1.50
+G30
+G33 Z-20.000 K1.5000
+M2
+"
+        );
+        assert!(Grbl.cycle(&thread(), &DIAMETER).is_none());
+        assert!(!Grbl.css_supported());
+        assert!(!Grbl.nose_compensation_supported());
+    }
+
+    #[test]
+    fn feed_per_revolution() {
+        assert_eq!(LinuxCnc.setup(true, true, true), "G90 G20 G64 G18 G8 G95");
+        assert_eq!(
+            Fanuc { program_number: 1 }.setup(false, false, true),
+            "G21 G18 G40 G99"
+        );
+        assert_eq!(Grbl.setup(false, true, true), "G90 G21 G18 G8 G95");
+    }
+
+    #[test]
+    fn controller_names() {
+        assert!(matches!("Fanuc".parse(), Ok(Controller::Fanuc)));
+        assert!(matches!("linuxcnc".parse(), Ok(Controller::LinuxCnc)));
+        assert_eq!(
+            "haas".parse::<Controller>().err().unwrap(),
+            "unknown controller: haas"
+        );
+    }
+}
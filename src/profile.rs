@@ -0,0 +1,163 @@
+// Layered roughing of a contour, the expanded form of a type I G71. Layers
+// run in Z at falling X, each one stopping where it meets the contour moved
+// out by the finishing allowance, then a pass along that boundary removes
+// the steps left between layers.
+
+use crate::geometry::{motions, segments, x_scale, Element, Point, Segment, EPSILON};
+use crate::toolpath::{Profile, Toolpath};
+
+// Furthest Z along the boundary at this radius, the boundary only grows in X
+fn layer_end(boundary: &[Segment], level: f32) -> f32 {
+    for segment in boundary.iter().rev() {
+        let (low, high) = if segment.start.r < segment.end.r {
+            (segment.start.r, segment.end.r)
+        } else {
+            (segment.end.r, segment.start.r)
+        };
+        if level < low - EPSILON || level > high + EPSILON {
+            continue;
+        }
+        match segment.element {
+            Element::Line => {
+                let rise = segment.end.r - segment.start.r;
+                if rise.abs() < EPSILON {
+                    return segment.end.z;
+                }
+                let t = (level - segment.start.r) / rise;
+                return segment.start.z + (segment.end.z - segment.start.z) * t;
+            }
+            Element::Arc { centre, .. } => {
+                let radius = segment.start.minus(centre).length();
+                let half = (radius * radius - (level - centre.r).powi(2))
+                    .max(0.0)
+                    .sqrt();
+                let (near, far) = (
+                    segment.start.z.max(segment.end.z),
+                    segment.start.z.min(segment.end.z),
+                );
+                for z in [centre.z - half, centre.z + half].iter() {
+                    if *z >= far - EPSILON && *z <= near + EPSILON {
+                        return *z;
+                    }
+                }
+            }
+        }
+    }
+    boundary.last().map_or(0.0, |segment| segment.end.z)
+}
+
+pub fn rough_layers(profile: &Profile, step: f32, allowance: f32, retract: f32) -> Toolpath {
+    // Starts and ends at the cycle start point
+    let mut path = Toolpath::new();
+    let x_scale = x_scale(profile.radius_mode);
+    let first = Point {
+        z: profile.first_z,
+        r: profile.first_x / x_scale,
+    };
+    let offset = Point {
+        z: allowance / x_scale,
+        r: allowance / x_scale,
+    };
+    let boundary: Vec<Segment> = segments(first, &profile.contour, x_scale)
+        .iter()
+        .map(|segment| segment.translate(offset))
+        .collect();
+    let bottom = first.add(offset, 1.0);
+
+    let mut level = profile.start_x / x_scale - step / x_scale;
+    while level > bottom.r + EPSILON {
+        let z = layer_end(&boundary, level);
+        path.rapid_x(level * x_scale);
+        path.feed_z(z, profile.feed);
+        path.feed_xz(
+            (level + retract / x_scale) * x_scale,
+            z + retract / x_scale,
+            profile.feed,
+        );
+        path.rapid_z(profile.start_z);
+        level -= step / x_scale;
+    }
+
+    path.append(profile.blocks(bottom.r * x_scale, bottom.z, motions(&boundary, x_scale)));
+    path.rapid_z(profile.start_z);
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emitter::contour_lines;
+    use crate::post::LinuxCnc;
+    use crate::toolpath::Motion;
+
+    fn feed(x: Option<f32>, z: Option<f32>) -> Motion {
+        Motion::Feed { x, z, feed: 50.0 }
+    }
+
+    #[test]
+    fn layer_ends_on_lines_and_arcs() {
+        // Rounded edge, diameter, fillet into a shoulder, in radius mode
+        let contour = [
+            Motion::Arc {
+                cw: false,
+                x: 5.0,
+                z: -2.0,
+                i: 0.0,
+                k: -2.0,
+                feed: 50.0,
+            },
+            feed(None, Some(-10.0)),
+            Motion::Arc {
+                cw: true,
+                x: 8.0,
+                z: -13.0,
+                i: 3.0,
+                k: 0.0,
+                feed: 50.0,
+            },
+            feed(Some(12.0), None),
+        ];
+        let boundary = segments(Point { z: 0.0, r: 3.0 }, &contour, 1.0);
+        for &(level, z) in &[(4.0, -0.268), (5.0, -10.0), (6.0, -12.236), (10.0, -13.0)] {
+            let end = layer_end(&boundary, level);
+            assert!((end - z).abs() < 1e-3, "level {} ends at {}", level, end);
+        }
+    }
+
+    #[test]
+    fn layers_stop_at_the_allowance() {
+        let profile = Profile {
+            number: 1,
+            start_x: 14.0,
+            start_z: 1.0,
+            first_x: 10.0,
+            first_z: 0.0,
+            contour: vec![feed(None, Some(-20.0)), feed(Some(16.0), None)],
+            feed: 100.0,
+            finish_feed: 50.0,
+            radius_mode: true,
+        };
+        let path = rough_layers(&profile, 1.5, 0.2, 0.5);
+        let lines = contour_lines(&path.moves, &LinuxCnc);
+        let lines: Vec<&str> = lines.iter().map(|line| line.trim()).collect();
+        assert_eq!(
+            lines,
+            [
+                "G0 X12.500",
+                "G1 Z-19.800 F100",
+                "X13.000 Z-19.300",
+                "G0 Z1.000",
+                "X11.000",
+                "G1 Z-19.800",
+                "X11.500 Z-19.300",
+                "G0 Z1.000",
+                "X10.200",
+                "G1 Z0.200 F50",
+                "Z-19.800",
+                "X16.200",
+                "X14.000",
+                "G0 Z1.000",
+            ]
+        );
+    }
+}
@@ -0,0 +1,680 @@
+// Material removal simulation. The stock is a grid of rings in (z, radius)
+// that the tool cuts away as the toolpath is replayed. The tool is its nose
+// circle, or the blade for grooving, and the shank behind it, which sits
+// outside the nose for turning, inside it for boring and towards the
+// tailstock for facing and drilling.
+// What is left is compared with the surface each operation should leave, and
+// rapids are checked for running through stock that is still there.
+
+use std::fmt;
+
+use crate::compensation::{normal, tip_vector};
+use crate::generator::{JobParams, JobType, MachineParams};
+use crate::geometry::{segments, Element, Point, Position, Segment};
+use crate::job::Stock;
+use crate::toolpath::{expand_cycles, CompSide, Motion, Toolpath};
+
+const MAX_CELLS: f32 = 2e7; // About 20 MB of grid
+
+// Positions are program units, (x, z) for points
+pub enum Finding {
+    // Stock an operation should have cut
+    Leftover {
+        z: (f32, f32),
+        x: (f32, f32),
+    },
+    // Cut below a finished surface
+    Gouge {
+        z: (f32, f32),
+        x: (f32, f32),
+    },
+    NoCut {
+        operation: usize, // Counts from 1
+        name: &'static str,
+    },
+    Rapid {
+        from: (f32, f32),
+        to: (f32, f32),
+        at: (f32, f32), // Where it meets the stock
+    },
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Finding::Leftover { z, x } => write!(
+                f,
+                "stock left at Z{:.3} to Z{:.3}, X{:.3} to X{:.3}",
+                z.0, z.1, x.0, x.1
+            ),
+            Finding::Gouge { z, x } => write!(
+                f,
+                "gouge at Z{:.3} to Z{:.3}, X{:.3} to X{:.3}",
+                z.0, z.1, x.0, x.1
+            ),
+            Finding::NoCut { operation, name } => {
+                write!(f, "operation {} ({}) cuts no stock", operation, name)
+            }
+            Finding::Rapid { from, to, at } => write!(
+                f,
+                "rapid from X{:.3} Z{:.3} to X{:.3} Z{:.3} runs through stock at X{:.3} Z{:.3}",
+                from.0, from.1, to.0, to.1, at.0, at.1
+            ),
+        }
+    }
+}
+
+// Cells over the stock, true while there is material in them
+struct Grid {
+    left: f32, // Z of the chuck end
+    cell: f32,
+    columns: usize,
+    rows: usize,
+    material: Vec<bool>,
+}
+
+impl Grid {
+    fn centre(&self, column: usize, row: usize) -> Point {
+        Point {
+            z: self.left + (column as f32 + 0.5) * self.cell,
+            r: (row as f32 + 0.5) * self.cell,
+        }
+    }
+
+    fn at(&self, point: Point) -> bool {
+        let column = ((point.z - self.left) / self.cell).floor();
+        let row = (point.r / self.cell).floor();
+        if column < 0.0 || row < 0.0 {
+            return false;
+        }
+        let (column, row) = (column as usize, row as usize);
+        column < self.columns && row < self.rows && self.material[column * self.rows + row]
+    }
+
+    fn remaining(&self) -> usize {
+        self.material.iter().filter(|cell| **cell).count()
+    }
+
+    // Cells with centres inside the box
+    fn range(&self, from: f32, to: f32, count: usize) -> std::ops::Range<usize> {
+        let first = ((from / self.cell - 0.5).ceil().max(0.0) as usize).min(count);
+        let last = ((to / self.cell - 0.5).floor() + 1.0).max(0.0) as usize;
+        first..last.min(count).max(first)
+    }
+
+    fn clear_box(&mut self, z: (f32, f32), r: (f32, f32)) {
+        let columns = self.range(z.0 - self.left, z.1 - self.left, self.columns);
+        let rows = self.range(r.0, r.1, self.rows);
+        for column in columns {
+            for row in rows.clone() {
+                self.material[column * self.rows + row] = false;
+            }
+        }
+    }
+
+    fn clear_circle(&mut self, centre: Point, radius: f32) {
+        let columns = self.range(
+            centre.z - radius - self.left,
+            centre.z + radius - self.left,
+            self.columns,
+        );
+        let rows = self.range(centre.r - radius, centre.r + radius, self.rows);
+        for column in columns {
+            for row in rows.clone() {
+                if self.centre(column, row).minus(centre).length() <= radius {
+                    self.material[column * self.rows + row] = false;
+                }
+            }
+        }
+    }
+}
+
+// Which way the shank runs from the nose
+fn shank(job: Option<&JobParams>, tip: Point) -> Point {
+    let (z, r) = match job.map(|job| &job.job_type) {
+        Some(JobType::Boring(_)) => (0.0, -1.0),
+        Some(JobType::Facing)
+        | Some(JobType::FaceBoring)
+        | Some(JobType::FaceGrooving(_))
+        | Some(JobType::Drilling(_))
+        | Some(JobType::Tapping(_)) => (1.0, 0.0),
+        Some(_) => (0.0, 1.0),
+        // Programs read back only have the tool orientation to go on
+        None if tip.length() > 0.0 => (-tip.z, -tip.r),
+        None => (0.0, 1.0),
+    };
+    Point { z, r }
+}
+
+struct Simulation<'a> {
+    grid: Grid,
+    machine: &'a MachineParams,
+    position: Position,
+    tool: Option<i32>,
+    compensation: Option<CompSide>,
+    previous: Option<Segment>, // Last move cut, for the corner with the next
+    corner: Vec<Segment>,      // Moves either side of the one being cut
+    job: Option<&'a JobParams>, // Operation being run
+    findings: Vec<Finding>,
+}
+
+impl<'a> Simulation<'a> {
+    fn program(&self, point: Point) -> (f32, f32) {
+        (point.r * self.position.x_scale, point.z)
+    }
+
+    fn nose(&self) -> (f32, Point) {
+        match self.tool.and_then(|number| self.machine.tool(number)) {
+            Some(tool) => (tool.nose_radius, tip_vector(tool.orientation)),
+            None => (0.0, Point { z: 0.0, r: 0.0 }),
+        }
+    }
+
+    fn reach(&self, radius: f32) -> f32 {
+        // A sharp tool still clears the cells it runs through
+        radius.max(self.grid.cell / 2.0) + self.grid.cell * 0.01
+    }
+
+    fn cut(&mut self, tip: Point, direction: Point) {
+        let (radius, tip_vector) = self.nose();
+        let centre = match self.compensation {
+            // The controller keeps the nose on the programmed path
+            Some(side) if direction.length() > 0.0 => tip.add(normal(direction, side), radius),
+            _ => tip.add(tip_vector, -radius),
+        };
+        // In an inside corner the controller stops the nose where it touches
+        // both moves, the offset of one would cut into the other
+        let limit = radius - self.grid.cell * 0.01;
+        if self.compensation.is_some()
+            && self
+                .corner
+                .iter()
+                .any(|segment| segment.distance(centre) < limit)
+        {
+            return;
+        }
+        let reach = self.reach(radius);
+        let shank = shank(self.job, tip_vector);
+        self.grid.clear_circle(centre, reach);
+        // Grooving blades are set by one corner and reach across to the other
+        let blade = match self.job.map(|job| &job.job_type) {
+            Some(JobType::Grooving(groove)) => Point {
+                z: -groove.tool_width,
+                r: 0.0,
+            },
+            Some(JobType::FaceGrooving(groove)) => Point {
+                z: 0.0,
+                r: -groove.tool_width,
+            },
+            _ => Point { z: 0.0, r: 0.0 },
+        };
+        let other = centre.add(blade, 1.0);
+        let z = (centre.z.min(other.z) - reach, centre.z.max(other.z) + reach);
+        let r = (centre.r.min(other.r) - reach, centre.r.max(other.r) + reach);
+        if blade.length() > 0.0 {
+            self.grid.clear_box(z, r);
+        }
+        let along = |from: f32, side: f32| match side {
+            s if s > 0.0 => Some((from, f32::INFINITY)),
+            s if s < 0.0 => Some((-f32::INFINITY, from)),
+            _ => None,
+        };
+        if let Some(r) = along(centre.r, shank.r) {
+            self.grid.clear_box(z, r);
+        }
+        if let Some(z) = along(centre.z, shank.z) {
+            self.grid.clear_box(z, r);
+        }
+    }
+
+    fn feed(&mut self, segment: &Segment) {
+        let steps = (segment.length() / (self.grid.cell / 2.0)).ceil().max(1.0) as usize;
+        for step in 0..=steps {
+            let point = segment.point_at(step as f32 / steps as f32);
+            let direction = segment.tangent(point);
+            self.cut(point, direction);
+        }
+    }
+
+    fn line_to(&mut self, z: f32) {
+        if let Some(start) = self.position.point() {
+            self.feed(&Segment {
+                element: Element::Line,
+                start,
+                end: Point { z, r: start.r },
+                feed: 0.0,
+            });
+        }
+    }
+
+    fn rapid(&mut self, motion: &Motion) {
+        let to = self.position.after(motion);
+        let (from, to) = match (self.position.point(), to.point()) {
+            (Some(from), Some(to)) => (from, to),
+            _ => return,
+        };
+        let length = to.minus(from).length();
+        let steps = (length / (self.grid.cell / 2.0)).ceil().max(1.0) as usize;
+        // Follows the centre of the nose, the tip point of a round nose sits
+        // in the stock it leaves in a corner
+        let (radius, tip_vector) = self.nose();
+        for step in 0..=steps {
+            let point = from.add(to.minus(from), step as f32 / steps as f32);
+            if self.grid.at(point.add(tip_vector, -radius)) {
+                self.findings.push(Finding::Rapid {
+                    from: self.program(from),
+                    to: self.program(to),
+                    at: self.program(point),
+                });
+                return;
+            }
+        }
+    }
+
+    fn cut_along(&mut self, motion: &Motion, next: Option<&Motion>) {
+        for segment in self.position.segments(motion) {
+            // The moves either side guard the corners at each end
+            self.corner = self.previous.take().into_iter().collect();
+            if let Some(next) = next {
+                self.corner.extend(segments(
+                    segment.end,
+                    std::slice::from_ref(next),
+                    self.position.x_scale,
+                ));
+            }
+            self.feed(&segment);
+            self.previous = Some(segment);
+        }
+    }
+
+    fn run(&mut self, moves: &[Motion]) {
+        let moves = expand_cycles(moves);
+        for (i, motion) in moves.iter().enumerate() {
+            match *motion {
+                Motion::Feed { .. } | Motion::Arc { .. } => (),
+                _ => self.previous = None,
+            }
+            match *motion {
+                Motion::ToolChange(tool) => self.tool = Some(tool),
+                Motion::NoseCompensation(side) => self.compensation = side,
+                Motion::Rapid { .. } => self.rapid(motion),
+                Motion::Feed { .. } | Motion::Arc { .. } => {
+                    self.cut_along(motion, moves.get(i + 1))
+                }
+                Motion::Thread { z, .. } | Motion::RigidTap { z, .. } => self.line_to(z),
+                _ => (),
+            }
+            self.position.follow(motion);
+        }
+    }
+}
+
+fn inside(polygon: &[Point], point: Point) -> bool {
+    // Crossings of a ray towards +z
+    let mut inside = false;
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        if (a.r > point.r) != (b.r > point.r) {
+            let z = a.z + (point.r - a.r) / (b.r - a.r) * (b.z - a.z);
+            if point.z < z {
+                inside = !inside;
+            }
+        }
+    }
+    inside
+}
+
+fn distance(polygon: &[Point], point: Point) -> f32 {
+    // To the nearest edge
+    let mut nearest = f32::INFINITY;
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        let edge = b.minus(*a);
+        let length = edge.length();
+        let t = if length > 0.0 {
+            let offset = point.minus(*a);
+            ((offset.z * edge.z + offset.r * edge.r) / (length * length)).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        nearest = nearest.min(point.minus(a.add(edge, t)).length());
+    }
+    nearest
+}
+
+// The region each operation should clear, closed past the stock
+fn target(job: &JobParams, machine: &MachineParams, grid: &Grid, reach: f32) -> Vec<Point> {
+    let x_scale = machine.x_scale();
+    let top = grid.rows as f32 * grid.cell + 1.0;
+    let right = grid.left + grid.columns as f32 * grid.cell + 1.0;
+    let end_z = job.start_cut - job.length;
+    let rectangle = |z: (f32, f32), r: (f32, f32)| {
+        vec![
+            Point { z: z.0, r: r.0 },
+            Point { z: z.1, r: r.0 },
+            Point { z: z.1, r: r.1 },
+            Point { z: z.0, r: r.1 },
+        ]
+    };
+    match job.job_type {
+        // Cut by the width of the tool rather than along a surface
+        JobType::Drilling(_) | JobType::Tapping(_) => {
+            return rectangle((end_z.min(job.start_cut), right), (-1.0, 2.0 * reach))
+        }
+        JobType::Parting(_) => {
+            return rectangle(
+                (job.start_cut - 2.0 * reach, job.start_cut + 2.0 * reach),
+                (job.finish_depth / x_scale, top),
+            )
+        }
+        _ => (),
+    }
+
+    let surface = job.finished_surface(machine);
+    let start = match surface.moves.first() {
+        Some(Motion::Rapid {
+            x: Some(x),
+            z: Some(z),
+        }) => Point {
+            z: *z,
+            r: x / x_scale,
+        },
+        _ => return vec![],
+    };
+    let mut outline = vec![start];
+    for segment in segments(start, &surface.moves[1..], x_scale) {
+        let steps = (segment.length() / grid.cell).ceil().max(1.0) as usize;
+        for step in 1..=steps {
+            outline.push(segment.point_at(step as f32 / steps as f32));
+        }
+    }
+    let last = *outline.last().unwrap();
+    match job.job_type {
+        JobType::Boring(_) => {
+            outline.push(Point { z: last.z, r: -1.0 });
+            outline.push(Point {
+                z: start.z,
+                r: -1.0,
+            });
+        }
+        JobType::Facing | JobType::FaceBoring | JobType::FaceGrooving(_) => {
+            outline.push(Point {
+                z: right,
+                r: last.r,
+            });
+            outline.push(Point {
+                z: right,
+                r: start.r,
+            });
+        }
+        _ => {
+            outline.push(Point { z: last.z, r: top });
+            outline.push(Point { z: start.z, r: top });
+        }
+    }
+    outline
+}
+
+// Bounding boxes of the connected groups of flagged cells
+fn regions(grid: &Grid, flagged: &mut [bool], x_scale: f32) -> Vec<((f32, f32), (f32, f32))> {
+    let mut regions = vec![];
+    for first in 0..flagged.len() {
+        if !flagged[first] {
+            continue;
+        }
+        flagged[first] = false;
+        let mut stack = vec![first];
+        let (mut columns, mut rows) = ((usize::MAX, 0), (usize::MAX, 0));
+        while let Some(index) = stack.pop() {
+            let (column, row) = (index / grid.rows, index % grid.rows);
+            columns = (columns.0.min(column), columns.1.max(column));
+            rows = (rows.0.min(row), rows.1.max(row));
+            let mut neighbours = vec![];
+            if column > 0 {
+                neighbours.push(index - grid.rows);
+            }
+            if column + 1 < grid.columns {
+                neighbours.push(index + grid.rows);
+            }
+            if row > 0 {
+                neighbours.push(index - 1);
+            }
+            if row + 1 < grid.rows {
+                neighbours.push(index + 1);
+            }
+            for next in neighbours {
+                if flagged[next] {
+                    flagged[next] = false;
+                    stack.push(next);
+                }
+            }
+        }
+        regions.push((
+            (
+                grid.left + columns.0 as f32 * grid.cell,
+                grid.left + (columns.1 + 1) as f32 * grid.cell,
+            ),
+            (
+                rows.0 as f32 * grid.cell * x_scale,
+                (rows.1 + 1) as f32 * grid.cell * x_scale,
+            ),
+        ));
+    }
+    regions
+}
+
+// Replay the sections from generate_sections, or a single section read back
+// from G-code when jobs is empty, through stock cut into cells of resolution.
+// Stock and gouges are only reported more than two cells from the surface
+// the operations should leave, and only when the jobs are known.
+pub fn simulate(
+    sections: &[Toolpath],
+    jobs: &[JobParams],
+    machine: &MachineParams,
+    stock: &Stock,
+    resolution: f32,
+) -> Result<Vec<Finding>, String> {
+    if !resolution.is_finite() || resolution <= 0.0 {
+        return Err(format!(
+            "resolution must be greater than zero, got {}",
+            resolution
+        ));
+    }
+    let finite = stock.length.is_finite() && stock.diameter.is_finite();
+    if !finite || stock.length <= 0.0 || stock.diameter <= 0.0 {
+        return Err("stock length and diameter must be greater than zero".to_string());
+    }
+    let face = Stock::face(jobs);
+    let columns = (stock.length / resolution).ceil();
+    let rows = (stock.diameter / 2.0 / resolution).ceil();
+    if columns * rows > MAX_CELLS {
+        return Err(format!(
+            "resolution {} needs {} cells for the stock, use a coarser one",
+            resolution,
+            columns * rows
+        ));
+    }
+    let (columns, rows) = (columns as usize, rows as usize);
+    let mut simulation = Simulation {
+        grid: Grid {
+            left: face - stock.length,
+            cell: resolution,
+            columns,
+            rows,
+            material: vec![true; columns * rows],
+        },
+        machine,
+        position: Position::new(machine.radius_mode),
+        tool: None,
+        compensation: None,
+        previous: None,
+        corner: vec![],
+        job: None,
+        findings: vec![],
+    };
+
+    // Boring starts from its pilot hole
+    for job in jobs {
+        if let JobType::Boring(_) = job.job_type {
+            let end_z = job.start_cut - job.length;
+            simulation.grid.clear_box(
+                (end_z.min(job.start_cut), f32::INFINITY),
+                (-f32::INFINITY, job.start_depth / machine.x_scale()),
+            );
+        }
+    }
+
+    for (i, section) in sections.iter().enumerate() {
+        // Sections are the setup, one per operation, then the end
+        simulation.job = if i > 0 { jobs.get(i - 1) } else { None };
+        let before = simulation.grid.remaining();
+        simulation.run(&section.moves);
+        if let Some(job) = simulation.job {
+            if simulation.grid.remaining() == before {
+                simulation.findings.push(Finding::NoCut {
+                    operation: i,
+                    name: job.job_type.name(),
+                });
+            }
+        }
+    }
+    if jobs.is_empty() {
+        return Ok(simulation.findings);
+    }
+
+    let grid = &simulation.grid;
+    let targets: Vec<Vec<Point>> = jobs
+        .iter()
+        .map(|job| {
+            let radius = machine
+                .tool(job.tool.unwrap_or(machine.tool_num))
+                .map_or(0.0, |tool| tool.nose_radius);
+            target(job, machine, grid, simulation.reach(radius))
+        })
+        .filter(|outline| outline.len() > 2)
+        .collect();
+    let tolerance = 2.0 * resolution;
+    let mut leftover = vec![false; grid.material.len()];
+    let mut gouge = vec![false; grid.material.len()];
+    for column in 0..grid.columns {
+        for row in 0..grid.rows {
+            let index = column * grid.rows + row;
+            let centre = grid.centre(column, row);
+            let containing = targets.iter().find(|outline| inside(outline, centre));
+            match (grid.material[index], containing) {
+                (true, Some(outline)) => {
+                    leftover[index] = distance(outline, centre) > tolerance;
+                }
+                (false, None) => {
+                    gouge[index] = targets
+                        .iter()
+                        .all(|outline| distance(outline, centre) > tolerance);
+                }
+                _ => (),
+            }
+        }
+    }
+    let x_scale = simulation.position.x_scale;
+    let mut findings = simulation.findings;
+    for (z, x) in regions(grid, &mut leftover, x_scale) {
+        findings.push(Finding::Leftover { z, x });
+    }
+    for (z, x) in regions(grid, &mut gouge, x_scale) {
+        findings.push(Finding::Gouge { z, x });
+    }
+    Ok(findings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::generate_sections;
+    use crate::job::Program;
+
+    // Facing and turning from the shaft example, with extra moves after the
+    // turning pass
+    fn turned(extra: Toolpath) -> Vec<Finding> {
+        let mut program = Program::from_toml(include_str!("../examples/shaft.toml")).unwrap();
+        program.operations.truncate(2);
+        let (mut sections, _) = generate_sections(&program.operations, &program.machine).unwrap();
+        sections[2].append(extra);
+        simulate(
+            &sections,
+            &program.operations,
+            &program.machine,
+            &program.stock,
+            0.05,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn resolution_must_be_usable() {
+        let program = Program::from_toml(include_str!("../examples/shaft.toml")).unwrap();
+        for &resolution in &[0.0, -0.05, f32::NAN, f32::INFINITY, 1e-5] {
+            let machine = &program.machine;
+            let result = simulate(
+                &[],
+                &program.operations,
+                machine,
+                &program.stock,
+                resolution,
+            );
+            assert!(result.is_err(), "{}", resolution);
+        }
+    }
+
+    #[test]
+    fn clean_program() {
+        let findings = turned(Toolpath::new());
+        assert!(
+            findings.is_empty(),
+            "{:?}",
+            findings.iter().map(|f| f.to_string()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn plunge_below_the_finish() {
+        // Down to X10 at Z-10 on a diameter turned to 12. The nose lies on the
+        // +Z side of the tip and the last 0.2 is within tolerance.
+        let mut extra = Toolpath::new();
+        extra.rapid_x(14.0);
+        extra.rapid_z(-10.0);
+        extra.feed_x(10.0, 50.0);
+        extra.rapid_x(14.0);
+        let findings = turned(extra);
+        assert_eq!(findings.len(), 1);
+        match findings[0] {
+            Finding::Gouge { z, x } => {
+                assert!(near(z, (-10.0, -9.2)), "{}", findings[0]);
+                assert!(near(x, (10.0, 11.8)), "{}", findings[0]);
+            }
+            _ => panic!("{}", findings[0]),
+        }
+    }
+
+    #[test]
+    fn rapid_into_the_shoulder() {
+        // The stock is still 16 beyond the turned length of 25, the nose
+        // reaches it with the tip 0.4 short of the shoulder
+        let mut extra = Toolpath::new();
+        extra.rapid_x(14.0);
+        extra.rapid_z(-30.0);
+        extra.rapid_x(18.0);
+        let findings = turned(extra);
+        assert!(!findings.is_empty());
+        match findings[0] {
+            Finding::Rapid { from, to, at } => {
+                assert_eq!((from.0, to), (14.0, (14.0, -30.0)));
+                assert!(near(at, (14.0, -25.4)), "{}", findings[0]);
+            }
+            _ => panic!("{}", findings[0]),
+        }
+    }
+
+    // Within a cell
+    fn near(a: (f32, f32), b: (f32, f32)) -> bool {
+        (a.0 - b.0).abs() <= 0.05 + 1e-4 && (a.1 - b.1).abs() <= 0.05 + 1e-4
+    }
+}
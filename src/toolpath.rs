@@ -0,0 +1,503 @@
+// Intermediate representation of a lathe program.
+// Operations generate into a Toolpath, the emitter turns it into G-code text.
+// Coordinates are in program units, X follows the active radius/diameter mode.
+
+use std::ops::RangeInclusive;
+
+use crate::generator::DrillCycle;
+use crate::profile::rough_layers;
+
+const PECK_GAP: f32 = 0.25; // Re-entry gap above the last peck
+const MAX_THREAD_PASSES: i32 = 1000; // Degressive passes before the last rough pass
+pub const DEGRESSION: RangeInclusive<f32> = 1.0..=3.0; // Roughing and threading
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SpindleSpeed {
+    Rpm(i32),                                  // G97
+    SurfaceSpeed { speed: f32, max_rpm: i32 }, // G96, clamped to max_rpm
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CompSide {
+    Left,  // G41, tool left of the path
+    Right, // G42, tool right of the path
+}
+
+#[derive(Clone)]
+pub enum Motion {
+    // Modal preamble
+    Setup {
+        units_inch: bool,
+        radius_mode: bool,
+        feed_per_rev: bool, // Feeds are per revolution instead of per minute
+    },
+    ToolChange(i32),
+    Spindle {
+        speed: SpindleSpeed,
+        cw: bool,
+    },
+    SpindleStop,
+    Coolant(bool),
+    Reference {
+        second: bool,
+    }, // Rapid to the reference position, G28 or G30 when second
+    Rapid {
+        x: Option<f32>,
+        z: Option<f32>,
+    },
+    Feed {
+        x: Option<f32>,
+        z: Option<f32>,
+        feed: f32,
+    },
+    Arc {
+        cw: bool,
+        x: f32,
+        z: f32,
+        i: f32,
+        k: f32,
+        feed: f32,
+    }, // I/K relative to start
+    Dwell(f32),                         // Seconds
+    NoseCompensation(Option<CompSide>), // None cancels (G40)
+    Thread {
+        z: f32,
+        pitch: f32,
+    }, // Spindle synchronised (G33)
+    RigidTap {
+        z: f32,
+        pitch: f32,
+    }, // Tap to z and back out to the start (G33.1)
+    Cycle(Cycle),
+    Comment(String),
+    End,
+}
+
+#[derive(Clone)]
+pub enum Cycle {
+    Thread {
+        drive_x: f32, // Start position, the cycle returns here between passes
+        start_z: f32,
+        z: f32, // End of thread
+        crest_x: f32,
+        root_x: f32,
+        pitch: f32,
+        first_depth: f32, // Radial
+        depth: f32,       // Radial
+        infeed_angle: f32,
+        finish_depths: Vec<f32>, // Radial, taken after the degressive passes
+        spring_passes: i32,
+        degression: f32,
+    },
+    Drill {
+        kind: DrillCycle,
+        start_z: f32, // Top of hole
+        z: f32,       // Bottom of hole
+        retract: f32, // R plane
+        peck: f32,
+        dwell: f32,
+        feed: f32,
+    },
+    Rough {
+        profile: Profile,
+        step: f32,      // Depth of each layer in X
+        allowance: f32, // Stock left for finishing, in X and the same radially in Z
+        retract: f32,   // 45 degree pull off at the end of each layer
+    }, // G71
+    Finish(Profile), // G70
+}
+
+#[derive(Clone)]
+pub struct Profile {
+    pub number: u32,  // Subroutine or sequence number the cycles refer to
+    pub start_x: f32, // Cycle start, clear of the stock
+    pub start_z: f32,
+    pub first_x: f32, // First point of the contour
+    pub first_z: f32,
+    pub contour: Vec<Motion>, // Feed and arc moves from the first point
+    pub feed: f32,            // Roughing layers
+    pub finish_feed: f32,     // Contour blocks, used by G70
+    pub radius_mode: bool,
+}
+
+impl Profile {
+    // Contour blocks as the cycles expect them: X only to the first point's
+    // diameter, feed onto it in Z, follow the contour and feed out to the
+    // start diameter
+    pub fn blocks(&self, first_x: f32, first_z: f32, contour: Vec<Motion>) -> Toolpath {
+        let mut path = Toolpath::new();
+        path.rapid_x(first_x);
+        path.feed_z(first_z, self.finish_feed);
+        path.moves.extend(contour);
+        path.feed_x(self.start_x, self.finish_feed);
+        path
+    }
+
+    pub fn path(&self) -> Toolpath {
+        self.blocks(self.first_x, self.first_z, self.contour.clone())
+    }
+}
+
+pub struct Toolpath {
+    pub moves: Vec<Motion>,
+}
+
+impl Toolpath {
+    pub fn new() -> Toolpath {
+        Toolpath { moves: vec![] }
+    }
+
+    pub fn push(&mut self, motion: Motion) {
+        self.moves.push(motion);
+    }
+
+    pub fn append(&mut self, mut other: Toolpath) {
+        self.moves.append(&mut other.moves);
+    }
+
+    pub fn rapid_x(&mut self, x: f32) {
+        self.push(Motion::Rapid {
+            x: Some(x),
+            z: None,
+        });
+    }
+
+    pub fn rapid_z(&mut self, z: f32) {
+        self.push(Motion::Rapid {
+            x: None,
+            z: Some(z),
+        });
+    }
+
+    pub fn feed_x(&mut self, x: f32, feed: f32) {
+        self.push(Motion::Feed {
+            x: Some(x),
+            z: None,
+            feed,
+        });
+    }
+
+    pub fn feed_z(&mut self, z: f32, feed: f32) {
+        self.push(Motion::Feed {
+            x: None,
+            z: Some(z),
+            feed,
+        });
+    }
+
+    pub fn feed_xz(&mut self, x: f32, z: f32, feed: f32) {
+        self.push(Motion::Feed {
+            x: Some(x),
+            z: Some(z),
+            feed,
+        });
+    }
+}
+
+impl Default for Toolpath {
+    fn default() -> Toolpath {
+        Toolpath::new()
+    }
+}
+
+// The moves as they run, with any cycles expanded
+pub fn expand_cycles(moves: &[Motion]) -> Vec<Motion> {
+    let mut expanded = vec![];
+    for motion in moves {
+        match *motion {
+            Motion::Cycle(ref cycle) => expanded.extend(expand_cycles(&cycle.expand().moves)),
+            _ => expanded.push(motion.clone()),
+        }
+    }
+    expanded
+}
+
+pub fn thread_pass_depths(
+    first_depth: f32,
+    depth: f32,
+    finish_depths: &[f32],
+    spring_passes: i32,
+    degression: f32,
+) -> Vec<f32> {
+    // Same schedule as G76: pass n cuts to first_depth * n^(1/degression),
+    // capped at full depth less the finishing passes, then the finishing
+    // passes and the spring passes at full depth. The pass count grows as
+    // (depth / first_depth)^degression, so it is capped.
+    let rough_depth = depth - finish_depths.iter().sum::<f32>();
+    let mut passes = vec![];
+    for n in 1..=MAX_THREAD_PASSES {
+        let pass = first_depth * (n as f32).powf(1.0 / degression);
+        if pass >= rough_depth {
+            break;
+        }
+        passes.push(pass);
+    }
+    let mut last = rough_depth;
+    passes.push(last);
+    for finish in finish_depths {
+        last += finish;
+        passes.push(last);
+    }
+    for _ in 0..spring_passes {
+        passes.push(depth);
+    }
+    passes
+}
+
+impl Cycle {
+    // Explicit moves for controllers without the cycle, starting from the
+    // position the cycle would be called from
+    pub fn expand(&self) -> Toolpath {
+        let mut path = Toolpath::new();
+        match *self {
+            Cycle::Thread {
+                drive_x,
+                start_z,
+                z,
+                crest_x,
+                root_x,
+                pitch,
+                first_depth,
+                depth,
+                infeed_angle,
+                ref finish_depths,
+                spring_passes,
+                degression,
+            } => {
+                // One G33 per pass, shifting the start in Z to follow the
+                // compound infeed angle
+                let flank = infeed_angle.to_radians().tan();
+                for pass in
+                    thread_pass_depths(first_depth, depth, finish_depths, spring_passes, degression)
+                {
+                    path.rapid_z(start_z - pass * flank);
+                    path.rapid_x(crest_x - (crest_x - root_x) * pass / depth);
+                    path.push(Motion::Thread { z, pitch });
+                    path.rapid_x(drive_x); // Retract to drive line
+                }
+                path.rapid_z(start_z);
+            }
+            Cycle::Drill {
+                ref kind,
+                start_z,
+                z,
+                retract,
+                peck,
+                dwell,
+                feed,
+            } => {
+                match kind {
+                    DrillCycle::Simple | DrillCycle::Dwell => {
+                        path.feed_z(z, feed);
+                        if let DrillCycle::Dwell = kind {
+                            path.push(Motion::Dwell(dwell));
+                        }
+                    }
+                    DrillCycle::Peck | DrillCycle::ChipBreak => {
+                        let mut depth = start_z;
+                        while depth > z {
+                            let next = (depth - peck).max(z);
+                            if let DrillCycle::Peck = kind {
+                                if depth < start_z {
+                                    path.rapid_z(depth + PECK_GAP); // Back down to last peck
+                                }
+                            }
+                            path.feed_z(next, feed);
+                            if next > z {
+                                match kind {
+                                    DrillCycle::Peck => path.rapid_z(retract), // Clear chips
+                                    _ => path.rapid_z(next + PECK_GAP),        // Break chip
+                                }
+                            }
+                            depth = next;
+                        }
+                    }
+                }
+                path.rapid_z(retract);
+            }
+            Cycle::Rough {
+                ref profile,
+                step,
+                allowance,
+                retract,
+            } => path.append(rough_layers(profile, step, allowance, retract)),
+            Cycle::Finish(ref profile) => {
+                path.append(profile.path());
+                path.rapid_z(profile.start_z);
+            }
+        }
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emitter::contour_lines;
+    use crate::post::LinuxCnc;
+
+    fn near(actual: &[f32], expected: &[f32]) -> bool {
+        actual.len() == expected.len()
+            && actual
+                .iter()
+                .zip(expected)
+                .all(|(a, b)| (a - b).abs() < 1e-4)
+    }
+
+    fn thread(infeed_angle: f32) -> Cycle {
+        Cycle::Thread {
+            drive_x: 14.0,
+            start_z: 2.0,
+            z: -20.0,
+            crest_x: 12.0,
+            root_x: 10.8,
+            pitch: 1.5,
+            first_depth: 0.2,
+            depth: 0.6,
+            infeed_angle,
+            finish_depths: vec![],
+            spring_passes: 1,
+            degression: 1.0,
+        }
+    }
+
+    #[test]
+    fn constant_depth_thread_passes() {
+        let passes = thread_pass_depths(0.2, 0.92, &[], 0, 1.0);
+        assert!(near(&passes, &[0.2, 0.4, 0.6, 0.8, 0.92]), "{:?}", passes);
+    }
+
+    #[test]
+    fn degressive_thread_passes_get_lighter() {
+        let passes = thread_pass_depths(0.2, 0.92, &[], 0, 2.0);
+        assert_eq!(passes.len(), 22);
+        assert!((passes[0] - 0.2).abs() < 1e-4);
+        assert_eq!(passes[passes.len() - 1], 0.92);
+        for pair in passes.windows(3) {
+            assert!(
+                pair[2] - pair[1] <= pair[1] - pair[0] + 1e-4,
+                "{:?}",
+                passes
+            );
+        }
+    }
+
+    #[test]
+    fn finishing_and_spring_passes_follow_the_roughing() {
+        let passes = thread_pass_depths(0.3, 0.92, &[0.05, 0.02], 2, 1.0);
+        assert!(
+            near(&passes, &[0.3, 0.6, 0.85, 0.9, 0.92, 0.92, 0.92]),
+            "{:?}",
+            passes
+        );
+    }
+
+    #[test]
+    fn thread_passes_are_capped() {
+        let passes = thread_pass_depths(1e-6, 1.0, &[], 0, 3.0);
+        assert_eq!(passes.len(), MAX_THREAD_PASSES as usize + 1);
+        assert_eq!(passes[passes.len() - 1], 1.0);
+    }
+
+    #[test]
+    fn thread_infeed_follows_the_flank() {
+        // Each pass starts further towards the chuck by depth * tan(angle)
+        let lines = contour_lines(&thread(30.0).expand().moves, &LinuxCnc);
+        let starts: Vec<&str> = lines.iter().step_by(4).map(|line| line.trim()).collect();
+        assert_eq!(
+            starts,
+            ["G0 Z1.885", "Z1.769", "Z1.654", "Z1.654", "Z2.000"]
+        );
+        assert_eq!(
+            &lines[..4],
+            [
+                "G0 Z1.885",
+                "   X11.600",
+                "G33 Z-20.000 K1.5000",
+                "G0 X14.000"
+            ]
+        );
+        // The last passes reach the root
+        assert_eq!(lines[9], "   X10.800");
+        assert_eq!(lines[13], "   X10.800");
+
+        // Straight in without a compound angle
+        let lines = contour_lines(&thread(0.0).expand().moves, &LinuxCnc);
+        assert!(lines
+            .iter()
+            .filter(|line| line.contains('Z') && !line.contains("G33"))
+            .all(|line| line.ends_with("Z2.000")));
+    }
+
+    fn drill(kind: DrillCycle) -> Cycle {
+        Cycle::Drill {
+            kind,
+            start_z: 0.0,
+            z: -10.0,
+            retract: 2.0,
+            peck: 4.0,
+            dwell: 0.5,
+            feed: 50.0,
+        }
+    }
+
+    fn expanded(cycle: Cycle) -> Vec<String> {
+        contour_lines(&cycle.expand().moves, &LinuxCnc)
+            .iter()
+            .map(|line| line.trim().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn drilling_in_one_go() {
+        assert_eq!(
+            expanded(drill(DrillCycle::Simple)),
+            ["G1 Z-10.000 F50", "G0 Z2.000"]
+        );
+        assert_eq!(
+            expanded(drill(DrillCycle::Dwell)),
+            ["G1 Z-10.000 F50", "G4 P0.50", "G0 Z2.000"]
+        );
+    }
+
+    #[test]
+    fn peck_drilling_clears_the_hole() {
+        assert_eq!(
+            expanded(drill(DrillCycle::Peck)),
+            [
+                "G1 Z-4.000 F50",
+                "G0 Z2.000",
+                "Z-3.750",
+                "G1 Z-8.000",
+                "G0 Z2.000",
+                "Z-7.750",
+                "G1 Z-10.000",
+                "G0 Z2.000",
+            ]
+        );
+    }
+
+    #[test]
+    fn chip_breaking_backs_off_a_little() {
+        assert_eq!(
+            expanded(drill(DrillCycle::ChipBreak)),
+            [
+                "G1 Z-4.000 F50",
+                "G0 Z-3.750",
+                "G1 Z-8.000",
+                "G0 Z-7.750",
+                "G1 Z-10.000",
+                "G0 Z2.000",
+            ]
+        );
+    }
+
+    #[test]
+    fn drill_cycles_on_linuxcnc() {
+        let lines = contour_lines(&[Motion::Cycle(drill(DrillCycle::Peck))], &LinuxCnc);
+        assert_eq!(
+            lines,
+            ["G17", "G83 Z-10.000 R2.000 Q4.000 F50", "G80", "G18"]
+        );
+    }
+}